@@ -0,0 +1,243 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Declarative configuration for the Postgres clusters `cargo pgrx start`/`run`/`connect` manage
+//!
+//! Read from the extension's `Cargo.toml`:
+//!
+//! ```toml
+//! [package.metadata.pgrx]
+//! shared_preload_libraries = ["my_extension"]
+//! pg_hba = ["host replication all 127.0.0.1/32 trust"]
+//!
+//! [package.metadata.pgrx.postgresql_conf]
+//! wal_level = "logical"
+//! max_replication_slots = 10
+//! ```
+//!
+//! The `postgresql.conf` settings are written to `pgrx.conf` in the data directory, which we
+//! `include_if_exists` from `postgresql.conf`.  The `pg_hba` lines are kept in a marked block at the
+//! top of `pg_hba.conf`, ahead of `initdb`'s defaults, since the first matching line wins.
+use cargo_toml::Manifest;
+use eyre::{eyre, WrapErr};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+const PGRX_CONF: &str = "pgrx.conf";
+const INCLUDE_PGRX_CONF: &str = "include_if_exists = 'pgrx.conf'";
+const HBA_BEGIN: &str = "# BEGIN settings managed by cargo-pgrx from Cargo.toml";
+const HBA_END: &str = "# END settings managed by cargo-pgrx";
+
+/// The `[package.metadata.pgrx]` section of an extension's `Cargo.toml`
+#[derive(Debug, Default, Clone, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct ClusterConfig {
+    /// Libraries to load at server start, generally including the extension itself
+    shared_preload_libraries: Vec<String>,
+    /// Arbitrary `postgresql.conf` settings
+    postgresql_conf: BTreeMap<String, toml::Value>,
+    /// Lines to add to `pg_hba.conf`
+    pg_hba: Vec<String>,
+}
+
+impl ClusterConfig {
+    pub(crate) fn from_manifest(manifest: &Manifest) -> eyre::Result<Self> {
+        let section = manifest
+            .package
+            .as_ref()
+            .and_then(|package| package.metadata.as_ref())
+            .and_then(|metadata| metadata.get("pgrx"));
+        match section {
+            None => Ok(Self::default()),
+            Some(section) => section
+                .clone()
+                .try_into()
+                .wrap_err("invalid `[package.metadata.pgrx]` section in Cargo.toml"),
+        }
+    }
+
+    /// Render our settings as the contents of `pgrx.conf`
+    fn postgresql_conf(&self) -> eyre::Result<String> {
+        let mut conf = String::new();
+        writeln!(conf, "# Managed by cargo-pgrx from [package.metadata.pgrx] in Cargo.toml.")?;
+        writeln!(conf, "# Changes made here will be overwritten.")?;
+        if !self.shared_preload_libraries.is_empty() {
+            let libraries = self.shared_preload_libraries.join(",");
+            writeln!(conf, "shared_preload_libraries = {}", quote(&libraries))?;
+        }
+        for (name, value) in &self.postgresql_conf {
+            let value = match value {
+                toml::Value::String(s) => quote(s),
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Float(f) => f.to_string(),
+                toml::Value::Boolean(b) => if *b { "on" } else { "off" }.to_string(),
+                other => {
+                    return Err(eyre!(
+                        "unsupported value for `{name}` in `[package.metadata.pgrx.postgresql_conf]`: {other}"
+                    ))
+                }
+            };
+            writeln!(conf, "{name} = {value}")?;
+        }
+        Ok(conf)
+    }
+
+    /// Replace our managed block in `pg_hba`, which may be absent, with the current `pg_hba` lines
+    fn pg_hba_conf(&self, pg_hba: &str) -> String {
+        let mut unmanaged = String::new();
+        let mut in_block = false;
+        for line in pg_hba.lines() {
+            match line {
+                HBA_BEGIN => in_block = true,
+                HBA_END => in_block = false,
+                line if !in_block => {
+                    unmanaged.push_str(line);
+                    unmanaged.push('\n');
+                }
+                _ => {}
+            }
+        }
+
+        if self.pg_hba.is_empty() {
+            return unmanaged;
+        }
+        let mut conf = String::new();
+        conf.push_str(HBA_BEGIN);
+        conf.push('\n');
+        for line in &self.pg_hba {
+            conf.push_str(line);
+            conf.push('\n');
+        }
+        conf.push_str(HBA_END);
+        conf.push('\n');
+        conf.push_str(&unmanaged);
+        conf
+    }
+
+    /// Bring the configuration files in `datadir` up to date.
+    ///
+    /// Returns `true` if anything changed, in which case a running Postgres needs to be restarted
+    pub(crate) fn apply(&self, datadir: &Path) -> eyre::Result<bool> {
+        let mut changed = false;
+
+        let pgrx_conf = datadir.join(PGRX_CONF);
+        // don't litter data directories that have never been configured
+        if *self != Self::default() || pgrx_conf.exists() {
+            let postgresql_conf = datadir.join("postgresql.conf");
+            let conf = read_to_string(&postgresql_conf)?;
+            if !conf.lines().any(|line| line.trim() == INCLUDE_PGRX_CONF) {
+                let separator = if conf.ends_with('\n') || conf.is_empty() { "" } else { "\n" };
+                write(&postgresql_conf, format!("{conf}{separator}{INCLUDE_PGRX_CONF}\n"))?;
+            }
+            changed |= write_if_changed(&pgrx_conf, self.postgresql_conf()?)?;
+        }
+
+        let pg_hba = datadir.join("pg_hba.conf");
+        if pg_hba.exists() {
+            let existing = read_to_string(&pg_hba)?;
+            changed |= write_if_changed(&pg_hba, self.pg_hba_conf(&existing))?;
+        }
+
+        Ok(changed)
+    }
+}
+
+/// Quote a string the way `postgresql.conf` expects
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn read_to_string(path: &Path) -> eyre::Result<String> {
+    std::fs::read_to_string(path).wrap_err_with(|| format!("unable to read {}", path.display()))
+}
+
+fn write(path: &Path, contents: String) -> eyre::Result<()> {
+    std::fs::write(path, contents).wrap_err_with(|| format!("unable to write {}", path.display()))
+}
+
+/// Write `contents` to `path` unless it already holds them, give or take trailing whitespace, which
+/// hand-edited files often lack or have extra of
+fn write_if_changed(path: &Path, contents: String) -> eyre::Result<bool> {
+    match std::fs::read_to_string(path) {
+        Ok(existing) if existing.trim_end() == contents.trim_end() => Ok(false),
+        _ => write(path, contents).map(|_| true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> ClusterConfig {
+        let manifest = Manifest::from_str(&format!(
+            "[package]\nname = \"ext\"\nversion = \"0.0.0\"\n\n[lib]\npath = \"lib.rs\"\n\n{toml}"
+        ))
+        .unwrap();
+        ClusterConfig::from_manifest(&manifest).unwrap()
+    }
+
+    #[test]
+    fn renders_postgresql_conf() {
+        let config = config(
+            r#"
+[package.metadata.pgrx]
+shared_preload_libraries = ["ext", "pg_stat_statements"]
+
+[package.metadata.pgrx.postgresql_conf]
+wal_level = "logical"
+max_replication_slots = 10
+fsync = false
+application_name = "it's"
+"#,
+        );
+        let conf = config.postgresql_conf().unwrap();
+        let settings = conf.lines().filter(|line| !line.starts_with('#')).collect::<Vec<_>>();
+        assert_eq!(
+            settings,
+            [
+                "shared_preload_libraries = 'ext,pg_stat_statements'",
+                "application_name = 'it''s'",
+                "fsync = off",
+                "max_replication_slots = 10",
+                "wal_level = 'logical'",
+            ]
+        );
+    }
+
+    #[test]
+    fn replaces_pg_hba_block() {
+        let initdb = "local all all trust\n";
+        let config = config(
+            r#"
+[package.metadata.pgrx]
+pg_hba = ["host replication all 127.0.0.1/32 trust"]
+"#,
+        );
+        let once = config.pg_hba_conf(initdb);
+        assert!(once.starts_with(HBA_BEGIN));
+        assert!(once.ends_with(initdb));
+        // applying it again is a no-op
+        assert_eq!(config.pg_hba_conf(&once), once);
+        // and removing every line removes the block
+        assert_eq!(ClusterConfig::default().pg_hba_conf(&once), initdb);
+    }
+
+    #[test]
+    fn ignores_trailing_whitespace_in_pg_hba() {
+        let datadir = tempfile::tempdir().unwrap();
+        let pg_hba = datadir.path().join("pg_hba.conf");
+        std::fs::write(&pg_hba, "local all all trust").unwrap();
+        assert!(!ClusterConfig::default().apply(datadir.path()).unwrap());
+        assert_eq!(std::fs::read_to_string(&pg_hba).unwrap(), "local all all trust");
+
+        std::fs::write(&pg_hba, "local all all trust\n\n").unwrap();
+        assert!(!ClusterConfig::default().apply(datadir.path()).unwrap());
+    }
+}
@@ -0,0 +1,156 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use eyre::WrapErr;
+use pgrx_pg_config::{get_target_dir, Pgrx};
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::command::test::test_extension;
+use crate::manifest::{get_package_manifest, pg_config_and_version};
+use crate::profile::CargoProfile;
+use crate::CommandExecute;
+
+/// Run the `#[pg_bench]` benchmarks for this crate
+#[derive(clap::Args, Debug, Clone)]
+#[clap(author)]
+pub(crate) struct Bench {
+    /// Do you want to run against pg12, pg13, pg14, pg15, pg16, pg17, or all?
+    #[clap(env = "PG_VERSION")]
+    pg_version: Option<String>,
+    /// If specified, only run benchmarks whose names start with this string
+    benchname: Option<String>,
+    /// Package to build (see `cargo help pkgid`)
+    #[clap(long, short)]
+    package: Option<String>,
+    /// Path to Cargo.toml
+    #[clap(long, value_parser)]
+    manifest_path: Option<PathBuf>,
+    /// Specific profile to use (default is release)
+    #[clap(long)]
+    profile: Option<String>,
+    /// Don't regenerate the schema
+    #[clap(long, short)]
+    no_schema: bool,
+    /// Use `sudo` to initialize and run the Postgres test instance as this system user
+    #[clap(long, value_name = "USER")]
+    runas: Option<String>,
+    /// Initialize the test database cluster here, instead of the default location.  If used with `--runas`, then it must be writable by the user
+    #[clap(long, value_name = "DIR")]
+    pgdata: Option<PathBuf>,
+    /// Run every benchmark this many times, overriding their own `iterations`
+    #[clap(long, env = "PGRX_BENCH_ITERATIONS")]
+    iterations: Option<u32>,
+    /// Save the results under this name, in `target/pgrx-bench/<NAME>/`
+    #[clap(long, value_name = "NAME", default_value = "new")]
+    save_baseline: String,
+    /// Compare the results against those previously saved under this name
+    #[clap(long, value_name = "NAME")]
+    baseline: Option<String>,
+    #[clap(flatten)]
+    features: clap_cargo::Features,
+    #[clap(from_global, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+impl CommandExecute for Bench {
+    #[tracing::instrument(level = "error", skip(self))]
+    fn execute(self) -> eyre::Result<()> {
+        #[tracing::instrument(level = "error", skip(me))]
+        fn perform(me: Bench, pgrx: &Pgrx) -> eyre::Result<()> {
+            let mut features = me.features.clone();
+            let (package_manifest, _package_manifest_path) =
+                get_package_manifest(&me.features, me.package.as_ref(), me.manifest_path.as_ref())?;
+            let (pg_config, _pg_version) = pg_config_and_version(
+                pgrx,
+                &package_manifest,
+                me.pg_version.clone(),
+                Some(&mut features),
+                true,
+            )?;
+
+            // timings from unoptimized code aren't worth comparing
+            let profile = CargoProfile::from_flags(me.profile.as_deref(), CargoProfile::Release)?;
+
+            // results for each Postgres version are kept apart, as they're not comparable
+            let results_dir = get_target_dir()?.join("pgrx-bench");
+            let pg_label = pg_config.label()?;
+            let harness = BenchHarness {
+                output_dir: results_dir.join(&me.save_baseline).join(&pg_label),
+                baseline_dir: me.baseline.map(|name| results_dir.join(name).join(&pg_label)),
+                iterations: me.iterations,
+            };
+            std::fs::create_dir_all(&harness.output_dir)
+                .wrap_err_with(|| format!("unable to create `{}`", harness.output_dir.display()))?;
+
+            test_extension(
+                &pg_config,
+                me.manifest_path.as_ref(),
+                me.package.as_ref(),
+                &package_manifest,
+                &profile,
+                me.no_schema,
+                &features,
+                Some(format!("pg_bench_{}", me.benchname.unwrap_or_default())),
+                me.runas,
+                me.pgdata,
+                false,
+                Some(&harness),
+            )?;
+
+            Ok(())
+        }
+
+        let (package_manifest, _) = get_package_manifest(
+            &self.features,
+            self.package.as_ref(),
+            self.manifest_path.as_ref(),
+        )?;
+        let pgrx = Pgrx::from_config()?;
+        if self.pg_version == Some("all".to_string()) {
+            for v in crate::manifest::all_pg_in_both_tomls(&package_manifest, &pgrx) {
+                let mut versioned_bench = self.clone();
+                versioned_bench.pg_version = Some(v?.label()?);
+                perform(versioned_bench, &pgrx)?;
+            }
+
+            Ok(())
+        } else {
+            perform(self, &pgrx)
+        }
+    }
+}
+
+/// How `cargo test` needs to be run so that it runs the `#[pg_bench]` functions, which
+/// `pgrx-tests` then reads back from the environment
+#[derive(Debug, Clone)]
+pub(crate) struct BenchHarness {
+    output_dir: PathBuf,
+    baseline_dir: Option<PathBuf>,
+    iterations: Option<u32>,
+}
+
+impl BenchHarness {
+    pub(crate) fn configure(&self, command: &mut Command) {
+        command.env("PGRX_BENCH_OUTPUT", &self.output_dir);
+        if let Some(baseline_dir) = &self.baseline_dir {
+            command.env("PGRX_BENCH_BASELINE", baseline_dir);
+        }
+        if let Some(iterations) = self.iterations {
+            command.env("PGRX_BENCH_ITERATIONS", iterations.to_string());
+        }
+    }
+
+    /// Arguments for the test harness itself, which go after `--`
+    pub(crate) fn harness_args(&self) -> [&'static str; 2] {
+        // `#[pg_bench]` tests are `#[ignore]`d so that `cargo pgrx test` skips them, and we run
+        // them one at a time so they don't skew each other's timings
+        ["--ignored", "--test-threads=1"]
+    }
+}
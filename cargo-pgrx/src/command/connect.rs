@@ -7,6 +7,7 @@
 //LICENSE All rights reserved.
 //LICENSE
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use crate::cluster_config::ClusterConfig;
 use crate::command::get::get_property;
 use crate::command::run::exec_psql;
 use crate::command::start::start_postgres;
@@ -79,7 +80,9 @@ impl CommandExecute for Connect {
             }
         };
 
-        connect_psql(&pg_config, &dbname, self.pgcli)
+        let cluster_config = ClusterConfig::from_manifest(&package_manifest)?;
+
+        connect_psql(&pg_config, &cluster_config, &dbname, self.pgcli)
     }
 }
 
@@ -87,9 +90,14 @@ impl CommandExecute for Connect {
     pg_version = %pg_config.version()?,
     dbname,
 ))]
-pub(crate) fn connect_psql(pg_config: &PgConfig, dbname: &str, pgcli: bool) -> eyre::Result<()> {
+pub(crate) fn connect_psql(
+    pg_config: &PgConfig,
+    cluster_config: &ClusterConfig,
+    dbname: &str,
+    pgcli: bool,
+) -> eyre::Result<()> {
     // restart postgres
-    start_postgres(pg_config)?;
+    start_postgres(pg_config, cluster_config)?;
 
     // create the named database
     if !createdb(pg_config, dbname, false, true, None)? {
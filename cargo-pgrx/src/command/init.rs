@@ -12,9 +12,11 @@ use crate::command::version::pgrx_default;
 use crate::CommandExecute;
 use bzip2::bufread::BzDecoder;
 use eyre::{eyre, WrapErr};
+use flate2::read::GzDecoder;
 use owo_colors::OwoColorize;
 use pgrx_pg_config::{
-    get_c_locale_flags, prefix_path, ConfigToml, PgConfig, PgConfigSelector, Pgrx, PgrxHomeError,
+    get_c_locale_flags, prefix_path, ConfigToml, PgConfig, PgConfigSelector, PgMinorVersion,
+    PgSource, PgSourceOrigin, PgVersion, Pgrx, PgrxHomeError,
 };
 use tar::Archive;
 
@@ -45,22 +47,22 @@ static PROCESS_ENV_DENYLIST: &[&str] = &[
 #[derive(clap::Args, Debug)]
 #[clap(author)]
 pub(crate) struct Init {
-    /// If installed locally, the path to PG12's `pgconfig` tool, or `download` to have pgrx download/compile/install it
+    /// If installed locally, the path to PG12's `pgconfig` tool, or `download`, `tarball:<PATH>`, or `git:<REPO>[@<REF>]` to have pgrx compile/install it from that source
     #[clap(env = "PG12_PG_CONFIG", long)]
     pg12: Option<String>,
-    /// If installed locally, the path to PG13's `pgconfig` tool, or `download` to have pgrx download/compile/install it
+    /// If installed locally, the path to PG13's `pgconfig` tool, or `download`, `tarball:<PATH>`, or `git:<REPO>[@<REF>]` to have pgrx compile/install it from that source
     #[clap(env = "PG13_PG_CONFIG", long)]
     pg13: Option<String>,
-    /// If installed locally, the path to PG14's `pgconfig` tool, or `download` to have pgrx download/compile/install it
+    /// If installed locally, the path to PG14's `pgconfig` tool, or `download`, `tarball:<PATH>`, or `git:<REPO>[@<REF>]` to have pgrx compile/install it from that source
     #[clap(env = "PG14_PG_CONFIG", long)]
     pg14: Option<String>,
-    /// If installed locally, the path to PG15's `pgconfig` tool, or `download` to have pgrx download/compile/install it
+    /// If installed locally, the path to PG15's `pgconfig` tool, or `download`, `tarball:<PATH>`, or `git:<REPO>[@<REF>]` to have pgrx compile/install it from that source
     #[clap(env = "PG15_PG_CONFIG", long)]
     pg15: Option<String>,
-    /// If installed locally, the path to PG16's `pgconfig` tool, or `download` to have pgrx download/compile/install it
+    /// If installed locally, the path to PG16's `pgconfig` tool, or `download`, `tarball:<PATH>`, or `git:<REPO>[@<REF>]` to have pgrx compile/install it from that source
     #[clap(env = "PG16_PG_CONFIG", long)]
     pg16: Option<String>,
-    /// If installed locally, the path to PG17's `pgconfig` tool, or `download` to have pgrx download/compile/install it
+    /// If installed locally, the path to PG17's `pgconfig` tool, or `download`, `tarball:<PATH>`, or `git:<REPO>[@<REF>]` to have pgrx compile/install it from that source
     #[clap(env = "PG17_PG_CONFIG", long)]
     pg17: Option<String>,
     #[clap(from_global, action = ArgAction::Count)]
@@ -80,6 +82,14 @@ pub(crate) struct Init {
     valgrind: bool,
     #[clap(long, short, help = "Allow N make jobs at once")]
     jobs: Option<usize>,
+    /// Register the Postgres given by the single `--pgXX` option under this label, rather than
+    /// `pgXX`, so it can sit alongside other builds of the same major version
+    #[clap(long)]
+    label: Option<String>,
+    /// A patch file to apply, with `patch -p1`, to the Postgres sources before building them.  May
+    /// be given more than once
+    #[clap(long, value_name = "FILE")]
+    patch: Vec<PathBuf>,
     #[clap(skip)]
     jobserver: OnceLock<jobslot::Client>,
 }
@@ -122,15 +132,28 @@ impl CommandExecute for Init {
         }
 
         if versions.is_empty() {
+            if self.label.is_some() || !self.patch.is_empty() {
+                return Err(eyre!("`--label` and `--patch` require a `--pgXX` option"));
+            }
             // no arguments specified, so we'll just install our defaults
-            init_pgrx(&pgrx_default()?, &self)
+            init_pgrx(&pgrx_default()?, &HashMap::new(), &self)
         } else {
+            if self.label.is_some() && versions.len() > 1 {
+                return Err(eyre!("`--label` can only be used with a single `--pgXX` option"));
+            }
+
             // user specified arguments, so we'll only install those versions of Postgres
             let mut default_pgrx = None;
             let mut pgrx = Pgrx::default();
+            let mut origins = HashMap::new();
 
             for (pgver, pg_config_path) in versions {
-                let config = if pg_config_path == "download" {
+                let origin = parse_source(&pg_config_path)?;
+                let mut config = if origin.is_some() {
+                    // we won't know exactly which version it is until it's built
+                    let major = pgver.trim_start_matches("pg").parse()?;
+                    PgConfig::from(PgVersion::new(major, PgMinorVersion::Latest, None))
+                } else if pg_config_path == "download" {
                     if default_pgrx.is_none() {
                         default_pgrx = Some(pgrx_default()?);
                     }
@@ -152,16 +175,68 @@ impl CommandExecute for Init {
                     }
                     config
                 };
+
+                if config.is_real() && !self.patch.is_empty() {
+                    return Err(eyre!("`--patch` can't be applied to the `pg_config` given to `--{pgver}`, as it's already built"));
+                }
+                if let Some(label) = &self.label {
+                    validate_label(label, pgver)?;
+                    config = config.with_label(label);
+                }
+                if let Some(origin) = origin {
+                    origins.insert(config.label()?, origin);
+                }
                 pgrx.push(config);
             }
 
-            init_pgrx(&pgrx, &self)
+            init_pgrx(&pgrx, &origins, &self)
         }
     }
 }
 
+/// Parse the local source forms of a `--pgXX` argument, `tarball:<PATH>` and `git:<REPO>[@<REF>]`
+fn parse_source(arg: &str) -> eyre::Result<Option<PgSourceOrigin>> {
+    let canonicalize = |path: &str| {
+        std::fs::canonicalize(path).wrap_err_with(|| format!("unable to find `{path}`"))
+    };
+    if let Some(path) = arg.strip_prefix("tarball:") {
+        Ok(Some(PgSourceOrigin::Tarball { path: canonicalize(path)? }))
+    } else if let Some(repo) = arg.strip_prefix("git:") {
+        // refs like `HEAD@{1}` have `@`s of their own, and paths may too, so split after the
+        // longest prefix that's a path, or, if none is, at the first `@`
+        let (repo, git_ref) = std::iter::once((repo, "HEAD"))
+            .chain(repo.match_indices('@').rev().map(|(i, _)| (&repo[..i], &repo[i + 1..])))
+            .find(|(path, _)| Path::new(path).exists())
+            .or_else(|| repo.split_once('@'))
+            .unwrap_or((repo, "HEAD"));
+        Ok(Some(PgSourceOrigin::Git { repo: canonicalize(repo)?, git_ref: git_ref.to_string() }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn validate_label(label: &str, pgver: &str) -> eyre::Result<()> {
+    if label == "all" || label.is_empty() {
+        return Err(eyre!("`{label}` can't be used as a label"));
+    }
+    if label.contains(|c: char| c.is_whitespace() || std::path::is_separator(c)) {
+        return Err(eyre!("labels can't contain whitespace or path separators: `{label}`"));
+    }
+    // labels like `pg15` are how the other major versions are found
+    let is_major_version_label =
+        label.strip_prefix("pg").is_some_and(|major| major.parse::<u16>().is_ok());
+    if is_major_version_label && label != pgver {
+        return Err(eyre!("the label `{label}` is reserved for that major version of Postgres"));
+    }
+    Ok(())
+}
+
 #[tracing::instrument(skip_all)]
-pub(crate) fn init_pgrx(pgrx: &Pgrx, init: &Init) -> eyre::Result<()> {
+pub(crate) fn init_pgrx(
+    pgrx: &Pgrx,
+    origins: &HashMap<String, PgSourceOrigin>,
+    init: &Init,
+) -> eyre::Result<()> {
     let pgrx_home = match Pgrx::home() {
         Ok(path) => path,
         Err(e) => match e {
@@ -191,32 +266,46 @@ pub(crate) fn init_pgrx(pgrx: &Pgrx, init: &Init) -> eyre::Result<()> {
                 let _span = span.entered();
                 let mut pg_config = pg_config.clone();
                 stop_postgres(&pg_config).ok(); // no need to fail on errors trying to stop postgres while initializing
+                let mut source = None;
                 if !pg_config.is_real() {
-                    pg_config = match download_postgres(&pg_config, &pgrx_home, init) {
-                        Ok(pg_config) => pg_config,
-                        Err(e) => return Err(eyre!(e)),
-                    }
+                    let origin = match origins.get(&pg_config.label()?) {
+                        Some(origin) => origin.clone(),
+                        None => PgSourceOrigin::Download {
+                            url: pg_config.url().expect("no url").to_string(),
+                        },
+                    };
+                    let patches = init
+                        .patch
+                        .iter()
+                        .map(|patch| {
+                            std::fs::canonicalize(patch).wrap_err_with(|| {
+                                format!("unable to find patch `{}`", patch.display())
+                            })
+                        })
+                        .collect::<eyre::Result<Vec<_>>>()?;
+                    let pg_source = PgSource { origin, patches };
+                    pg_config = build_postgres(&pg_config, &pg_source, &pgrx_home, init)?;
+                    source = Some(pg_source);
                 }
 
-                Ok(pg_config)
+                Ok::<_, eyre::Report>((pg_config, source))
             }));
         }
 
         let mut output_configs = Vec::with_capacity(threads.len());
         for thread in threads {
-            let pg_config = thread.join().map_err(|_| eyre!("thread panicked"))??;
-            output_configs.push(pg_config);
+            output_configs.push(thread.join().map_err(|_| eyre!("thread panicked"))??);
         }
 
         Ok(output_configs)
     })?;
 
-    output_configs.sort_by(|a, b| {
+    output_configs.sort_by(|(a, _), (b, _)| {
         a.major_version()
             .unwrap_or_else(|e| panic!("{e}:  could not determine major version for: `{a:?}`"))
             .cmp(&b.major_version().expect("could not determine major version"))
     });
-    for pg_config in output_configs.iter() {
+    for (pg_config, _) in output_configs.iter() {
         validate_pg_config(pg_config)?;
 
         if is_root_user() {
@@ -235,20 +324,38 @@ pub(crate) fn init_pgrx(pgrx: &Pgrx, init: &Init) -> eyre::Result<()> {
 }
 
 #[tracing::instrument(level = "error", skip_all, fields(pg_version = %pg_config.version()?, pgrx_home))]
-fn download_postgres(
+fn build_postgres(
     pg_config: &PgConfig,
+    source: &PgSource,
     pgrx_home: &Path,
     init: &Init,
 ) -> eyre::Result<PgConfig> {
+    // downloaded releases are built in a directory named for their version, and everything else
+    // in one named for its label, as we don't know its version until it's built
+    let dirname = match &source.origin {
+        PgSourceOrigin::Download { .. } if pg_config.has_default_label()? => pg_config.version()?,
+        _ => pg_config.label()?,
+    };
+    let tarball = match &source.origin {
+        PgSourceOrigin::Download { url } => download_postgres(pg_config, url)?,
+        PgSourceOrigin::Tarball { path } => {
+            println!("{} Postgres from {}", "      Reading".bold().green(), path.display());
+            std::fs::read(path).wrap_err_with(|| format!("unable to read `{}`", path.display()))?
+        }
+        PgSourceOrigin::Git { repo, git_ref } => git_archive(repo, git_ref)?,
+    };
+    let pgdir = untar(&tarball, pgrx_home, &dirname, init)?;
+    patch_postgres(&source.patches, &pgdir)?;
+    configure_postgres(pg_config, &pgdir, init)?;
+    make_postgres(pg_config, &pgdir, init)?;
+    // returns a new PgConfig object
+    Ok(make_install_postgres(pg_config, &pgdir, init)?.with_label(pg_config.label()?))
+}
+
+fn download_postgres(pg_config: &PgConfig, url: &str) -> eyre::Result<Vec<u8>> {
     use crate::command::build_agent_for_url;
 
-    println!(
-        "{} Postgres v{} from {}",
-        "  Downloading".bold().green(),
-        pg_config.version()?,
-        pg_config.url().expect("no url"),
-    );
-    let url = pg_config.url().expect("no url for pg_config").as_str();
+    println!("{} Postgres v{} from {}", "  Downloading".bold().green(), pg_config.version()?, url);
     tracing::debug!(url = %url, "Fetching");
     let http_client = build_agent_for_url(url)?;
     let http_response = http_client.get(url).call()?;
@@ -257,23 +364,76 @@ fn download_postgres(
     if status != 200 {
         return Err(eyre!(
             "Problem downloading {}:\ncode={status}\n{}",
-            pg_config.url().unwrap().to_string().yellow().bold(),
+            url.yellow().bold(),
             http_response.into_string()?
         ));
     }
     let mut buf = Vec::new();
     let _count = http_response.into_reader().read_to_end(&mut buf)?;
-    let pgdir = untar(&buf, pgrx_home, pg_config, init)?;
-    configure_postgres(pg_config, &pgdir, init)?;
-    make_postgres(pg_config, &pgdir, init)?;
-    make_install_postgres(pg_config, &pgdir, init) // returns a new PgConfig object
+    Ok(buf)
+}
+
+/// Export `git_ref` from the git repository at `repo` as a tarball, much like a release tarball
+fn git_archive(repo: &Path, git_ref: &str) -> eyre::Result<Vec<u8>> {
+    println!("{} Postgres from {}@{git_ref}", "    Exporting".bold().green(), repo.display());
+    let mut command = Command::new("git");
+    command
+        .arg("-C")
+        .arg(repo)
+        .arg("archive")
+        .arg("--format=tar")
+        .arg("--prefix=postgres/")
+        .arg(git_ref)
+        .stdin(Stdio::null());
+
+    let command_str = format!("{command:?}");
+    tracing::debug!(command = %command_str, "Running");
+    let output = command.output().wrap_err_with(|| eyre!("unable to execute: {command_str}"))?;
+    tracing::trace!(status_code = %output.status, command = %command_str, "Finished");
+
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(eyre!("{}\n{}", command_str, String::from_utf8_lossy(&output.stderr)))
+    }
 }
 
-fn untar(bytes: &[u8], pgrxdir: &Path, pg_config: &PgConfig, init: &Init) -> eyre::Result<PathBuf> {
+fn patch_postgres(patches: &[PathBuf], pgdir: &Path) -> eyre::Result<()> {
+    for patch in patches {
+        println!("{} {}", "     Patching".bold().green(), patch.display());
+        let mut command = Command::new("patch");
+        command
+            .arg("-p1")
+            .arg("--forward")
+            .arg("--batch")
+            .arg("-i")
+            .arg(patch)
+            .stdin(Stdio::null())
+            .current_dir(pgdir);
+
+        let command_str = format!("{command:?}");
+        tracing::debug!(command = %command_str, "Running");
+        let output =
+            command.output().wrap_err_with(|| eyre!("unable to execute: {command_str}"))?;
+        tracing::trace!(status_code = %output.status, command = %command_str, "Finished");
+
+        if !output.status.success() {
+            return Err(eyre!(
+                "{}\n{}{}",
+                command_str,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn untar(bytes: &[u8], pgrxdir: &Path, dirname: &str, init: &Init) -> eyre::Result<PathBuf> {
     let _token = init.jobserver.get().unwrap().acquire().unwrap();
 
     let mut unpackdir = pgrxdir.to_path_buf();
-    unpackdir.push(format!("{}_unpack", pg_config.version()?));
+    unpackdir.push(format!("{dirname}_unpack"));
     if unpackdir.exists() {
         // delete everything at this path if it already exists
         println!("{} {}", "     Removing".bold().green(), unpackdir.display());
@@ -281,17 +441,18 @@ fn untar(bytes: &[u8], pgrxdir: &Path, pg_config: &PgConfig, init: &Init) -> eyr
     }
     std::fs::create_dir_all(&unpackdir)?;
 
-    println!(
-        "{} Postgres v{} to {}",
-        "    Untarring".bold().green(),
-        pg_config.version()?,
-        unpackdir.display()
-    );
-    let mut tar_decoder = Archive::new(BzDecoder::new(bytes));
+    println!("{} Postgres sources to {}", "    Untarring".bold().green(), unpackdir.display());
+    // release tarballs come compressed with bzip2 or gzip, and `git archive`'s not at all
+    let reader: Box<dyn Read> = match bytes {
+        [b'B', b'Z', b'h', ..] => Box::new(BzDecoder::new(bytes)),
+        [0x1f, 0x8b, ..] => Box::new(GzDecoder::new(bytes)),
+        _ => Box::new(bytes),
+    };
+    let mut tar_decoder = Archive::new(reader);
     tar_decoder.unpack(&unpackdir)?;
 
     let mut pgdir = pgrxdir.to_path_buf();
-    pgdir.push(&pg_config.version()?);
+    pgdir.push(dirname);
     if pgdir.exists() {
         // delete everything at this path if it already exists
         println!("{} {}", "     Removing".bold().green(), pgdir.display());
@@ -327,6 +488,15 @@ fn untar(bytes: &[u8], pgrxdir: &Path, pg_config: &PgConfig, init: &Init) -> eyr
     Ok(pgdir)
 }
 
+/// How to refer to a Postgres we're building: its version if it's a release we downloaded, or
+/// its label otherwise, since we won't know its version until it's built
+fn build_name(pg_config: &PgConfig) -> eyre::Result<String> {
+    match pg_config.url() {
+        Some(_) => Ok(format!("v{}", pg_config.version()?)),
+        None => pg_config.label(),
+    }
+}
+
 fn fixup_homebrew_for_icu(configure_cmd: &mut Command) {
     // See if it's disabled via an argument
     if configure_cmd.get_args().any(|a| a == "--without-icu") {
@@ -402,7 +572,7 @@ fn fixup_homebrew_for_icu(configure_cmd: &mut Command) {
 fn configure_postgres(pg_config: &PgConfig, pgdir: &Path, init: &Init) -> eyre::Result<()> {
     let _token = init.jobserver.get().unwrap().acquire().unwrap();
 
-    println!("{} Postgres v{}", "  Configuring".bold().green(), pg_config.version()?);
+    println!("{} Postgres {}", "  Configuring".bold().green(), build_name(pg_config)?);
     let mut configure_path = pgdir.to_path_buf();
     configure_path.push("configure");
     let mut command = std::process::Command::new(configure_path);
@@ -464,7 +634,7 @@ fn configure_postgres(pg_config: &PgConfig, pgdir: &Path, init: &Init) -> eyre::
 }
 
 fn make_postgres(pg_config: &PgConfig, pgdir: &Path, init: &Init) -> eyre::Result<()> {
-    println!("{} Postgres v{}", "    Compiling".bold().green(), pg_config.version()?);
+    println!("{} Postgres {}", "    Compiling".bold().green(), build_name(pg_config)?);
     let mut command = std::process::Command::new("make");
 
     command
@@ -498,9 +668,9 @@ fn make_postgres(pg_config: &PgConfig, pgdir: &Path, init: &Init) -> eyre::Resul
 
 fn make_install_postgres(version: &PgConfig, pgdir: &Path, init: &Init) -> eyre::Result<PgConfig> {
     println!(
-        "{} Postgres v{} to {}",
+        "{} Postgres {} to {}",
         "   Installing".bold().green(),
-        version.version()?,
+        build_name(version)?,
         get_pg_installdir(pgdir).display()
     );
     let mut command = std::process::Command::new("make");
@@ -548,7 +718,7 @@ fn validate_pg_config(pg_config: &PgConfig) -> eyre::Result<()> {
     Ok(())
 }
 
-fn write_config(pg_configs: &Vec<PgConfig>, init: &Init) -> eyre::Result<()> {
+fn write_config(pg_configs: &Vec<(PgConfig, Option<PgSource>)>, init: &Init) -> eyre::Result<()> {
     let config_path = Pgrx::config_toml()?;
     let mut config = match std::fs::read_to_string(&config_path) {
         Ok(file) => toml::from_str::<ConfigToml>(&file)?,
@@ -563,10 +733,19 @@ fn write_config(pg_configs: &Vec<PgConfig>, init: &Init) -> eyre::Result<()> {
 
     config.base_port = init.base_port;
     config.base_testing_port = init.base_testing_port;
-    for pg_config in pg_configs {
+    for (pg_config, source) in pg_configs {
+        let label = pg_config.label()?;
         config
             .configs
-            .insert(pg_config.label()?, pg_config.path().ok_or(eyre!("no path for pg_config"))?);
+            .insert(label.clone(), pg_config.path().ok_or(eyre!("no path for pg_config"))?);
+        // forget how any previous build under this label was made
+        match source {
+            Some(source) => config.sources.insert(label.clone(), source.clone()),
+            None => config.sources.remove(&label),
+        };
+        if !pg_config.has_default_label()? {
+            config.assign_port_offset(&label);
+        }
     }
 
     let mut file = File::create(&config_path)?;
@@ -621,3 +800,28 @@ pub(crate) fn initdb(bindir: &Path, datadir: &Path) -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_source;
+    use pgrx_pg_config::PgSourceOrigin;
+
+    #[test]
+    fn git_source_splits_the_ref_after_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("me@work").join("postgres");
+        std::fs::create_dir_all(&repo).unwrap();
+        let repo = repo.canonicalize().unwrap();
+        let git =
+            |git_ref: &str| PgSourceOrigin::Git { repo: repo.clone(), git_ref: git_ref.into() };
+
+        let source = |arg: &str| parse_source(&format!("git:{}{arg}", repo.display())).unwrap();
+        assert_eq!(source(""), Some(git("HEAD")));
+        assert_eq!(source("@REL_16_STABLE"), Some(git("REL_16_STABLE")));
+        assert_eq!(source("@HEAD@{1}"), Some(git("HEAD@{1}")));
+        assert!(parse_source("git:/no/such/repo@HEAD@{1}")
+            .unwrap_err()
+            .to_string()
+            .contains("`/no/such/repo`"));
+    }
+}
@@ -139,7 +139,7 @@ pub(crate) fn install_extension(
     let versioned_so = get_property(&package_manifest_path, "module_pathname")?.is_none();
 
     let build_command_output =
-        build_extension(user_manifest_path.as_ref(), user_package, profile, features)?;
+        build_extension(pg_config, user_manifest_path.as_ref(), user_package, profile, features)?;
     let build_command_bytes = build_command_output.stdout;
     let build_command_reader = BufReader::new(build_command_bytes.as_slice());
     let build_command_stream = CargoMessage::parse_stream(build_command_reader);
@@ -283,6 +283,7 @@ fn copy_file(
 }
 
 pub(crate) fn build_extension(
+    pg_config: &PgConfig,
     user_manifest_path: Option<impl AsRef<Path>>,
     user_package: Option<&String>,
     profile: &CargoProfile,
@@ -291,6 +292,7 @@ pub(crate) fn build_extension(
     let flags = std::env::var("PGRX_BUILD_FLAGS").unwrap_or_default();
 
     let mut command = crate::env::cargo();
+    crate::env::use_pg_config(&mut command, pg_config)?;
     command.arg("build");
     command.arg("--lib");
 
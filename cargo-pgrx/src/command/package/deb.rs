@@ -0,0 +1,238 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Debian packages
+//!
+//! A `.deb` is an `ar(1)` archive of three members, in this order: `debian-binary`, holding the
+//! format version, `control.tar.gz`, holding the package's `control` file, and `data.tar.gz`,
+//! holding the files to install.
+use super::metadata::PackageMetadata;
+use eyre::{eyre, WrapErr};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Create a `.deb` named following Debian's `{package}_{version}_{arch}.deb` convention in `dest_dir`
+pub(crate) fn build_deb(metadata: &PackageMetadata, dest_dir: &Path) -> eyre::Result<PathBuf> {
+    let package = package_name(metadata);
+    let version = format!("{}-1", metadata.package_version());
+    let arch = debian_arch(metadata.arch());
+    let artifact = dest_dir.join(format!("{package}_{version}_{arch}.deb"));
+
+    let control = control_file(metadata, &package, &version, arch)?;
+    let control_tar = {
+        let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(control.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        tar.append_data(&mut header, "control", control.as_bytes())?;
+        tar.into_inner()?.finish()?
+    };
+    let data_tar = {
+        let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        // owned by root, with deterministic timestamps
+        tar.mode(tar::HeaderMode::Deterministic);
+        metadata.append_files(&mut tar, Path::new("."))?;
+        tar.into_inner()?.finish()?
+    };
+
+    let mut deb = Vec::new();
+    deb.write_all(b"!<arch>\n")?;
+    append_ar_member(&mut deb, "debian-binary", b"2.0\n")?;
+    append_ar_member(&mut deb, "control.tar.gz", &control_tar)?;
+    append_ar_member(&mut deb, "data.tar.gz", &data_tar)?;
+    std::fs::write(&artifact, deb)
+        .wrap_err_with(|| format!("unable to write `{}`", artifact.display()))?;
+
+    Ok(artifact)
+}
+
+/// Debian's naming convention for extension packages, e.g. `postgresql-16-pg-cron`
+fn package_name(metadata: &PackageMetadata) -> String {
+    let extname = metadata.extname.to_lowercase().replace('_', "-");
+    format!("postgresql-{}-{extname}", metadata.pg_major)
+}
+
+/// Debian's name for the server package of this major version
+fn server_package(metadata: &PackageMetadata) -> String {
+    format!("postgresql-{}", metadata.pg_major)
+}
+
+fn debian_arch(arch: &str) -> &str {
+    match arch {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "i386",
+        "powerpc64" => "ppc64el",
+        other => other,
+    }
+}
+
+fn control_file(
+    metadata: &PackageMetadata,
+    package: &str,
+    version: &str,
+    arch: &str,
+) -> eyre::Result<String> {
+    let mut control = String::new();
+    writeln!(control, "Package: {package}")?;
+    writeln!(control, "Version: {version}")?;
+    writeln!(control, "Architecture: {arch}")?;
+    let maintainer = metadata.maintainer.as_deref().ok_or_else(|| {
+        eyre!("a .deb must name its maintainer, but `Cargo.toml` has no `authors` in [package]")
+    })?;
+    writeln!(control, "Maintainer: {maintainer}")?;
+    writeln!(control, "Installed-Size: {}", metadata.installed_size()?.div_ceil(1024))?;
+    writeln!(control, "Depends: {}", server_package(metadata))?;
+    writeln!(control, "Section: database")?;
+    writeln!(control, "Priority: optional")?;
+    if let Some(homepage) = &metadata.homepage {
+        writeln!(control, "Homepage: {homepage}")?;
+    }
+    writeln!(control, "Description: {}", metadata.summary)?;
+    if let Some(description) = &metadata.description {
+        // continuation lines are indented, and blank lines are spelled " ."
+        for line in description.lines() {
+            if line.trim().is_empty() {
+                writeln!(control, " .")?;
+            } else {
+                writeln!(control, " {line}")?;
+            }
+        }
+    }
+    Ok(control)
+}
+
+/// Append one member to an `ar` archive, using the same fixed-width header `dpkg-deb` writes
+fn append_ar_member(ar: &mut Vec<u8>, name: &str, contents: &[u8]) -> std::io::Result<()> {
+    // name, mtime, uid, gid, mode (octal), size, and the header's terminating magic
+    writeln!(ar, "{name:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`", 0, 0, 0, "100644", contents.len())?;
+    ar.write_all(contents)?;
+    // members are aligned to an even offset
+    if contents.len() % 2 == 1 {
+        ar.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append_ar_member, build_deb, control_file};
+    use crate::command::package::metadata::tests::fixture;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn ar_member_headers_are_fixed_width() {
+        let mut ar = Vec::new();
+        append_ar_member(&mut ar, "debian-binary", b"2.0\n").unwrap();
+        assert_eq!(&ar[..60], b"debian-binary   0           0     0     100644  4         `\n");
+        assert_eq!(&ar[60..], b"2.0\n");
+
+        // odd-sized members get padded
+        let mut ar = Vec::new();
+        append_ar_member(&mut ar, "odd", b"abc").unwrap();
+        assert_eq!(ar.len(), 60 + 4);
+    }
+
+    #[test]
+    fn control_file_describes_the_package() {
+        let root = tempfile::tempdir().unwrap();
+        let metadata = fixture(root.path());
+        let control =
+            control_file(&metadata, "postgresql-16-demo-ext", "1.0.0~beta1-1", "amd64").unwrap();
+        assert_eq!(
+            control,
+            "Package: postgresql-16-demo-ext\n\
+             Version: 1.0.0~beta1-1\n\
+             Architecture: amd64\n\
+             Maintainer: Jane Doe <jane@example.com>\n\
+             Installed-Size: 1\n\
+             Depends: postgresql-16\n\
+             Section: database\n\
+             Priority: optional\n\
+             Homepage: https://example.com/demo\n\
+             Description: A demo extension\n \
+             Does demo things.\n \
+             .\n \
+             Very well.\n"
+        );
+    }
+
+    #[test]
+    fn control_file_requires_a_maintainer() {
+        let root = tempfile::tempdir().unwrap();
+        let mut metadata = fixture(root.path());
+        metadata.maintainer = None;
+        let err =
+            control_file(&metadata, "postgresql-16-demo-ext", "1.0.0-1", "amd64").unwrap_err();
+        assert!(err.to_string().contains("authors"), "{err}");
+    }
+
+    #[test]
+    fn deb_holds_its_members_in_order() {
+        let root = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let metadata = fixture(root.path());
+        let artifact = build_deb(&metadata, dest.path()).unwrap();
+        assert_eq!(
+            artifact.file_name().unwrap().to_str().unwrap(),
+            format!(
+                "postgresql-16-demo-ext_1.0.0~beta1-1_{}.deb",
+                super::debian_arch(metadata.arch())
+            )
+        );
+
+        let deb = std::fs::read(&artifact).unwrap();
+        assert_eq!(&deb[..8], b"!<arch>\n");
+        let mut members = Vec::new();
+        let mut offset = 8;
+        while offset < deb.len() {
+            let header = std::str::from_utf8(&deb[offset..offset + 60]).unwrap();
+            let size: usize = header[48..58].trim().parse().unwrap();
+            members.push((
+                header[..16].trim().to_string(),
+                deb[offset + 60..offset + 60 + size].to_vec(),
+            ));
+            offset += 60 + size + size % 2;
+        }
+        let names = members.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["debian-binary", "control.tar.gz", "data.tar.gz"]);
+        assert_eq!(members[0].1, b"2.0\n");
+
+        let mut control = tar::Archive::new(GzDecoder::new(&members[1].1[..]));
+        let mut entries = control.entries().unwrap();
+        let mut entry = entries.next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap().to_str().unwrap(), "control");
+        let mut text = String::new();
+        entry.read_to_string(&mut text).unwrap();
+        assert!(text.starts_with("Package: postgresql-16-demo-ext\n"), "{text}");
+
+        let mut data = tar::Archive::new(GzDecoder::new(&members[2].1[..]));
+        let mut paths = data
+            .entries()
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|entry| entry.header().entry_type().is_file())
+            .map(|entry| entry.path().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "usr/lib/postgresql/16/lib/demo_ext.so",
+                "usr/share/postgresql/16/extension/demo_ext--1.0.0-beta1.sql",
+                "usr/share/postgresql/16/extension/demo_ext.control",
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use crate::command::get::get_property;
+use crate::command::install::get_version;
+use cargo_toml::{Inheritable, Manifest};
+use eyre::{eyre, WrapErr};
+use pgrx_pg_config::PgConfig;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Everything the various package formats need to know about the extension being packaged,
+/// gathered from `Cargo.toml`, the extension's `.control` file, and `pg_config`
+#[derive(Debug, Clone)]
+pub(crate) struct PackageMetadata {
+    /// The extension's name, from its `.control` file
+    pub(crate) extname: String,
+    /// The extension's `default_version`, with `@CARGO_VERSION@` resolved
+    pub(crate) version: String,
+    /// The Postgres major version the extension was built against
+    pub(crate) pg_major: u16,
+    /// A one-line summary of the extension
+    pub(crate) summary: String,
+    /// A longer description, if `Cargo.toml` has both a `description` and the control file a `comment`
+    pub(crate) description: Option<String>,
+    pub(crate) license: Option<String>,
+    pub(crate) homepage: Option<String>,
+    /// The first of `Cargo.toml`'s `authors`
+    pub(crate) maintainer: Option<String>,
+    /// The root of the directory tree `cargo pgrx package` laid out
+    pub(crate) root: PathBuf,
+    /// Every file in the package, relative to `root`
+    pub(crate) files: Vec<PathBuf>,
+}
+
+impl PackageMetadata {
+    pub(crate) fn new(
+        package_manifest_path: impl AsRef<Path>,
+        pg_config: &PgConfig,
+        root: PathBuf,
+        output_files: &[PathBuf],
+    ) -> eyre::Result<Self> {
+        let package_manifest_path = package_manifest_path.as_ref();
+        let manifest =
+            Manifest::from_path(package_manifest_path).wrap_err("Couldn't parse manifest")?;
+        let package = manifest
+            .package
+            .as_ref()
+            .ok_or_else(|| eyre!("Could not get [package] from manifest."))?;
+
+        let extname = get_property(package_manifest_path, "extname")?
+            .ok_or_else(|| eyre!("could not determine extension name"))?;
+        let version = get_version(package_manifest_path)?;
+        let comment = get_property(package_manifest_path, "comment")?;
+        let cargo_description = package.description.as_ref().and_then(inherited);
+
+        // prefer the control file's `comment` for the summary, as that's what Postgres shows users
+        // in `pg_available_extensions`.  `Cargo.toml`'s `description` is then the longer text
+        let (summary, description) = match (comment, cargo_description) {
+            (Some(comment), Some(description)) if comment != description => {
+                (comment, Some(description))
+            }
+            (Some(summary), _) | (None, Some(summary)) => (summary, None),
+            (None, None) => (format!("The {extname} extension for Postgres"), None),
+        };
+
+        let files = output_files
+            .iter()
+            .map(|file| {
+                file.strip_prefix(&root).map(Path::to_path_buf).wrap_err_with(|| {
+                    format!("`{}` is not within `{}`", file.display(), root.display())
+                })
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            extname,
+            version,
+            pg_major: pg_config.major_version()?,
+            summary,
+            description,
+            license: package.license.as_ref().and_then(inherited),
+            homepage: package.homepage.as_ref().and_then(inherited),
+            maintainer: package.authors.get().ok().and_then(|authors| authors.first().cloned()),
+            root,
+            files,
+        })
+    }
+
+    /// The extension's version in a form both `dpkg` and `rpm` accept: they use `-` to separate
+    /// the upstream version from the package revision, and sort a `~` before anything else, which
+    /// is what a semver pre-release like `1.0.0-beta1` means
+    pub(crate) fn package_version(&self) -> String {
+        self.version.replace('-', "~")
+    }
+
+    /// The architecture name as the packaging tools know it, for the machine we're running on
+    pub(crate) fn arch(&self) -> &'static str {
+        std::env::consts::ARCH
+    }
+
+    /// The name of the artifact file, without its extension
+    pub(crate) fn artifact_stem(&self) -> String {
+        format!(
+            "{}-{}-pg{}-{}-{}",
+            self.extname,
+            self.version,
+            self.pg_major,
+            std::env::consts::OS,
+            self.arch()
+        )
+    }
+
+    /// Append the package's files to `tar` under `prefix`, after the directories holding them
+    ///
+    /// Only the files in [`PackageMetadata::files`] are appended, not whatever else is in `root`,
+    /// such as what's left over from earlier builds.
+    pub(crate) fn append_files<W: Write>(
+        &self,
+        tar: &mut tar::Builder<W>,
+        prefix: &Path,
+    ) -> eyre::Result<()> {
+        let dirs = self
+            .files
+            .iter()
+            .flat_map(|file| file.ancestors().skip(1))
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect::<BTreeSet<_>>();
+        for dir in dirs {
+            tar.append_dir(prefix.join(dir), self.root.join(dir))?;
+        }
+        for file in &self.files {
+            tar.append_path_with_name(self.root.join(file), prefix.join(file))?;
+        }
+        Ok(())
+    }
+
+    /// The total size of the package's files, in bytes
+    pub(crate) fn installed_size(&self) -> eyre::Result<u64> {
+        let mut size = 0;
+        for file in &self.files {
+            size += std::fs::metadata(self.root.join(file))?.len();
+        }
+        Ok(size)
+    }
+}
+
+fn inherited(value: &Inheritable<String>) -> Option<String> {
+    value.get().ok().cloned()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::PackageMetadata;
+    use std::path::{Path, PathBuf};
+
+    /// Metadata for a `demo_ext` extension built for Postgres 16, whose files are laid out in `root`
+    pub(crate) fn fixture(root: &Path) -> PackageMetadata {
+        let files = [
+            ("usr/lib/postgresql/16/lib/demo_ext.so", "\x7fELF"),
+            (
+                "usr/share/postgresql/16/extension/demo_ext.control",
+                "default_version = '1.0.0-beta1'\n",
+            ),
+            ("usr/share/postgresql/16/extension/demo_ext--1.0.0-beta1.sql", "SELECT 1;\n"),
+        ];
+        for (path, contents) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        // left over from an earlier build, so not part of the package
+        std::fs::write(root.join("usr/lib/postgresql/16/lib/stale_ext.so"), "\x7fELF").unwrap();
+
+        PackageMetadata {
+            extname: "demo_ext".into(),
+            version: "1.0.0-beta1".into(),
+            pg_major: 16,
+            summary: "A demo extension".into(),
+            description: Some("Does demo things.\n\nVery well.".into()),
+            license: Some("MIT".into()),
+            homepage: Some("https://example.com/demo".into()),
+            maintainer: Some("Jane Doe <jane@example.com>".into()),
+            root: root.to_path_buf(),
+            files: files.iter().map(|(path, _)| PathBuf::from(path)).collect(),
+        }
+    }
+
+    #[test]
+    fn package_version_sorts_prereleases_first() {
+        let root = tempfile::tempdir().unwrap();
+        let metadata = fixture(root.path());
+        assert_eq!(metadata.package_version(), "1.0.0~beta1");
+        assert_eq!(
+            metadata.artifact_stem(),
+            format!("demo_ext-1.0.0-beta1-pg16-{}-{}", std::env::consts::OS, metadata.arch())
+        );
+        assert_eq!(metadata.installed_size().unwrap(), 4 + 32 + 10);
+    }
+}
@@ -7,15 +7,37 @@
 //LICENSE All rights reserved.
 //LICENSE
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
-use crate::command::install::install_extension;
+use crate::command::install::{format_display_path, install_extension};
 use crate::manifest::{display_version_info, PgVersionSource};
 use crate::CommandExecute;
 use crate::{command::get::get_property, profile::CargoProfile};
 use cargo_toml::Manifest;
 use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
 use pgrx_pg_config::{get_target_dir, PgConfig, Pgrx};
 use std::path::{Path, PathBuf};
 
+mod deb;
+mod metadata;
+mod rpm;
+mod tarball;
+
+use metadata::PackageMetadata;
+
+/// The kinds of artifact `cargo pgrx package` can produce
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum PackageFormat {
+    /// Only lay out the directory tree
+    #[default]
+    Dir,
+    /// A `.tar.gz` of the directory tree, with a `manifest.toml` describing its contents
+    Tarball,
+    /// A Debian package that depends on the matching `postgresql-XX` package
+    Deb,
+    /// An RPM package that depends on the matching `postgresqlXX-server` package
+    Rpm,
+}
+
 /// Create an installation package directory.
 #[derive(clap::Args, Debug)]
 #[clap(author)]
@@ -41,6 +63,9 @@ pub(crate) struct Package {
     /// The directory to output the package (default is `./target/[debug|release]/extname-pgXX/`)
     #[clap(long, value_parser)]
     pub(crate) out_dir: Option<PathBuf>,
+    /// The kind of package to build from the directory.  Artifacts are written next to the directory
+    #[clap(long, value_enum, default_value_t)]
+    pub(crate) format: PackageFormat,
     #[clap(flatten)]
     pub(crate) features: clap_cargo::Features,
     #[clap(from_global, action = ArgAction::Count)]
@@ -93,6 +118,16 @@ impl Package {
             &self.features,
         )?;
 
+        if self.format != PackageFormat::Dir {
+            let metadata = PackageMetadata::new(
+                &package_manifest_path,
+                &pg_config,
+                out_dir.clone(),
+                &output_files,
+            )?;
+            build_artifact(&metadata, self.format)?;
+        }
+
         Ok((out_dir, output_files))
     }
 }
@@ -140,6 +175,28 @@ pub(crate) fn package_extension(
     )
 }
 
+fn build_artifact(metadata: &PackageMetadata, format: PackageFormat) -> eyre::Result<PathBuf> {
+    let dest_dir = metadata
+        .root
+        .parent()
+        .ok_or_else(|| eyre!("`{}` has no parent directory", metadata.root.display()))?;
+
+    let artifact = match format {
+        PackageFormat::Dir => return Ok(metadata.root.clone()),
+        PackageFormat::Tarball => tarball::build_tarball(metadata, dest_dir)?,
+        PackageFormat::Deb => deb::build_deb(metadata, dest_dir)?,
+        PackageFormat::Rpm => rpm::build_rpm(metadata, dest_dir)?,
+    };
+
+    println!(
+        "{} {:?} package {}",
+        "     Created".bold().green(),
+        format,
+        format_display_path(&artifact)?.cyan()
+    );
+    Ok(artifact)
+}
+
 pub(crate) fn build_base_path(
     pg_config: &PgConfig,
     manifest_path: impl AsRef<Path>,
@@ -0,0 +1,114 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use super::metadata::PackageMetadata;
+use ::rpm::{CompressionType, Dependency, FileOptions, PackageBuilder};
+use eyre::WrapErr;
+use std::path::{Path, PathBuf};
+
+/// Create an `.rpm` named following the `{name}-{version}-{release}.{arch}.rpm` convention in `dest_dir`
+pub(crate) fn build_rpm(metadata: &PackageMetadata, dest_dir: &Path) -> eyre::Result<PathBuf> {
+    let package = package_name(metadata);
+    let version = metadata.package_version();
+    let release = "1";
+    let arch = metadata.arch();
+    let artifact = dest_dir.join(format!("{package}-{version}-{release}.{arch}.rpm"));
+
+    let mut builder = PackageBuilder::new(
+        &package,
+        &version,
+        metadata.license.as_deref().unwrap_or("Unknown"),
+        arch,
+        &metadata.summary,
+    )
+    .release(release)
+    .description(metadata.description.as_deref().unwrap_or(&metadata.summary))
+    .group("Applications/Databases")
+    .compression(CompressionType::Gzip)
+    .requires(Dependency::any(server_package(metadata)));
+    if let Some(homepage) = &metadata.homepage {
+        builder = builder.url(homepage);
+    }
+    if let Some(maintainer) = &metadata.maintainer {
+        builder = builder.packager(maintainer);
+    }
+
+    for file in &metadata.files {
+        let dest = Path::new("/").join(file);
+        builder = builder
+            .with_file(
+                metadata.root.join(file),
+                FileOptions::new(dest.display().to_string()).user("root").group("root"),
+            )
+            .wrap_err_with(|| format!("unable to add `{}` to the rpm", dest.display()))?;
+    }
+
+    let package = builder.build().wrap_err("unable to build the rpm")?;
+    package
+        .write_file(&artifact)
+        .wrap_err_with(|| format!("unable to write `{}`", artifact.display()))?;
+
+    Ok(artifact)
+}
+
+/// The PGDG repository's naming convention for extension packages, e.g. `pg_cron_16`
+fn package_name(metadata: &PackageMetadata) -> String {
+    format!("{}_{}", metadata.extname, metadata.pg_major)
+}
+
+/// The PGDG repository's name for the server package of this major version
+fn server_package(metadata: &PackageMetadata) -> String {
+    format!("postgresql{}-server", metadata.pg_major)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_rpm;
+    use crate::command::package::metadata::tests::fixture;
+    use std::path::PathBuf;
+
+    #[test]
+    fn rpm_header_describes_the_package() {
+        let root = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let metadata = fixture(root.path());
+        let artifact = build_rpm(&metadata, dest.path()).unwrap();
+        assert_eq!(
+            artifact.file_name().unwrap().to_str().unwrap(),
+            format!("demo_ext_16-1.0.0~beta1-1.{}.rpm", metadata.arch())
+        );
+
+        let rpm = ::rpm::Package::open(&artifact).unwrap();
+        let header = &rpm.metadata;
+        assert_eq!(header.get_name().unwrap(), "demo_ext_16");
+        assert_eq!(header.get_version().unwrap(), "1.0.0~beta1");
+        assert_eq!(header.get_release().unwrap(), "1");
+        assert_eq!(header.get_arch().unwrap(), metadata.arch());
+        assert_eq!(header.get_license().unwrap(), "MIT");
+        assert_eq!(header.get_summary().unwrap(), "A demo extension");
+        assert_eq!(header.get_description().unwrap(), "Does demo things.\n\nVery well.");
+        assert_eq!(header.get_url().unwrap(), "https://example.com/demo");
+        assert!(header
+            .get_requires()
+            .unwrap()
+            .iter()
+            .any(|dependency| dependency.name == "postgresql16-server"));
+
+        let mut paths = header.get_file_paths().unwrap();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("/usr/lib/postgresql/16/lib/demo_ext.so"),
+                PathBuf::from("/usr/share/postgresql/16/extension/demo_ext--1.0.0-beta1.sql"),
+                PathBuf::from("/usr/share/postgresql/16/extension/demo_ext.control"),
+            ]
+        );
+    }
+}
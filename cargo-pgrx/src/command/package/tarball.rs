@@ -0,0 +1,135 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use super::metadata::PackageMetadata;
+use eyre::WrapErr;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// The `manifest.toml` written alongside the files in a tarball
+#[derive(Debug, serde::Serialize)]
+struct TarballManifest<'a> {
+    extname: &'a str,
+    version: &'a str,
+    pg_major: u16,
+    os: &'a str,
+    arch: &'a str,
+    files: Vec<TarballFile>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct TarballFile {
+    /// Where the file is to be installed
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+}
+
+/// Create `{artifact_stem}.tar.gz` in `dest_dir`.
+///
+/// Everything is placed under a top-level `{artifact_stem}/` directory, which holds `manifest.toml`
+/// and the package's files, laid out as they're to be installed, rooted at `/`
+pub(crate) fn build_tarball(metadata: &PackageMetadata, dest_dir: &Path) -> eyre::Result<PathBuf> {
+    let stem = metadata.artifact_stem();
+    let artifact = dest_dir.join(format!("{stem}.tar.gz"));
+    let prefix = PathBuf::from(&stem);
+
+    let mut files = Vec::with_capacity(metadata.files.len());
+    for file in &metadata.files {
+        let contents = std::fs::read(metadata.root.join(file))?;
+        files.push(TarballFile {
+            path: Path::new("/").join(file),
+            size: contents.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&contents)),
+        });
+    }
+    let manifest = toml::to_string_pretty(&TarballManifest {
+        extname: &metadata.extname,
+        version: &metadata.version,
+        pg_major: metadata.pg_major,
+        os: std::env::consts::OS,
+        arch: metadata.arch(),
+        files,
+    })?;
+
+    let output = File::create(&artifact)
+        .wrap_err_with(|| format!("unable to create `{}`", artifact.display()))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(output, Compression::default()));
+    tar.mode(tar::HeaderMode::Deterministic);
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    tar.append_data(&mut header, prefix.join("manifest.toml"), manifest.as_bytes())?;
+    metadata.append_files(&mut tar, &prefix)?;
+    tar.into_inner()?.finish()?;
+
+    Ok(artifact)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_tarball;
+    use crate::command::package::metadata::tests::fixture;
+    use flate2::read::GzDecoder;
+    use sha2::{Digest, Sha256};
+    use std::io::Read;
+
+    #[test]
+    fn tarball_holds_a_manifest_and_the_files_under_one_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let metadata = fixture(root.path());
+        let stem = metadata.artifact_stem();
+        let artifact = build_tarball(&metadata, dest.path()).unwrap();
+        assert_eq!(artifact, dest.path().join(format!("{stem}.tar.gz")));
+
+        let mut tar = tar::Archive::new(GzDecoder::new(std::fs::File::open(&artifact).unwrap()));
+        let mut paths = Vec::new();
+        let mut manifest = String::new();
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            assert!(path.starts_with(&format!("{stem}/")), "{path}");
+            if path.ends_with("/manifest.toml") {
+                entry.read_to_string(&mut manifest).unwrap();
+            }
+            if entry.header().entry_type().is_file() {
+                paths.push(path[stem.len() + 1..].to_string());
+            }
+        }
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "manifest.toml",
+                "usr/lib/postgresql/16/lib/demo_ext.so",
+                "usr/share/postgresql/16/extension/demo_ext--1.0.0-beta1.sql",
+                "usr/share/postgresql/16/extension/demo_ext.control",
+            ]
+        );
+
+        let manifest: toml::Value = toml::from_str(&manifest).unwrap();
+        assert_eq!(manifest["extname"].as_str(), Some("demo_ext"));
+        assert_eq!(manifest["version"].as_str(), Some("1.0.0-beta1"));
+        assert_eq!(manifest["pg_major"].as_integer(), Some(16));
+        let files = manifest["files"].as_array().unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0]["path"].as_str(), Some("/usr/lib/postgresql/16/lib/demo_ext.so"));
+        assert_eq!(files[0]["size"].as_integer(), Some(4));
+        assert_eq!(
+            files[0]["sha256"].as_str(),
+            Some(format!("{:x}", Sha256::digest(b"\x7fELF")).as_str())
+        );
+    }
+}
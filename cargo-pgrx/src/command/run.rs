@@ -7,6 +7,7 @@
 //LICENSE All rights reserved.
 //LICENSE
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use crate::cluster_config::ClusterConfig;
 use crate::command::get::get_property;
 use crate::command::install::install_extension;
 use crate::command::start::start_postgres;
@@ -77,6 +78,8 @@ impl CommandExecute for Run {
             if self.release { CargoProfile::Release } else { CargoProfile::Dev },
         )?;
 
+        let cluster_config = ClusterConfig::from_manifest(&package_manifest)?;
+
         run(
             &pg_config,
             self.manifest_path.as_ref(),
@@ -86,6 +89,7 @@ impl CommandExecute for Run {
             &profile,
             self.pgcli,
             &self.features,
+            &cluster_config,
         )
     }
 }
@@ -104,6 +108,7 @@ pub(crate) fn run(
     profile: &CargoProfile,
     pgcli: bool,
     features: &clap_cargo::Features,
+    cluster_config: &ClusterConfig,
 ) -> eyre::Result<()> {
     // stop postgres
     stop_postgres(pg_config)?;
@@ -121,7 +126,7 @@ pub(crate) fn run(
     )?;
 
     // restart postgres
-    start_postgres(pg_config)?;
+    start_postgres(pg_config, cluster_config)?;
 
     // create the named database
     if !createdb(pg_config, dbname, false, true, None)? {
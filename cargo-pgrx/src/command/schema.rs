@@ -18,7 +18,7 @@ use pgrx_pg_config::cargo::PgrxManifestExt;
 use pgrx_pg_config::{get_target_dir, PgConfig, Pgrx};
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Stdio;
+use std::process::{Command, Stdio};
 
 /// Generate extension schema files
 #[derive(clap::Args, Debug)]
@@ -146,13 +146,23 @@ pub(crate) fn generate_schema(
     let lib_name = manifest.lib_name()?;
     let lib_filename = manifest.lib_filename()?;
 
+    // both builds are of the extension for `pg_config`, logging at `log_level`
+    let cargo = || -> eyre::Result<Command> {
+        let mut command = crate::env::cargo();
+        crate::env::use_pg_config(&mut command, pg_config)?;
+        if let Some(log_level) = &log_level {
+            command.env("RUST_LOG", log_level);
+        }
+        Ok(command)
+    };
+
     if !skip_build {
         // NB:  The only path where this happens is via the command line using `cargo pgrx schema`
         first_build(
+            cargo()?,
             user_manifest_path.as_ref(),
             profile,
             features,
-            log_level.clone(),
             is_test,
             &features_arg,
             &flags,
@@ -202,9 +212,9 @@ pub(crate) fn generate_schema(
     }
 
     second_build(
+        cargo()?,
         user_manifest_path.as_ref(),
         features,
-        log_level.clone(),
         &features_arg,
         &flags,
         embed.path(),
@@ -305,16 +315,15 @@ fn compute_symbols(profile: &CargoProfile, lib_filename: &str) -> eyre::Result<V
 }
 
 fn first_build(
+    mut command: Command,
     user_manifest_path: Option<&impl AsRef<Path>>,
     profile: &CargoProfile,
     features: &clap_cargo::Features,
-    log_level: Option<String>,
     is_test: bool,
     features_arg: &str,
     flags: &str,
     package_name: &str,
 ) -> eyre::Result<()> {
-    let mut command = crate::env::cargo();
     command.stdin(Stdio::null());
     command.stdout(Stdio::null());
     command.stderr(Stdio::inherit());
@@ -337,10 +346,6 @@ fn first_build(
 
     command.args(profile.cargo_args());
 
-    if let Some(log_level) = &log_level {
-        command.env("RUST_LOG", log_level);
-    }
-
     if !features_arg.trim().is_empty() {
         command.arg("--features");
         command.arg(features_arg);
@@ -468,15 +473,14 @@ fn compute_codegen(
 }
 
 fn second_build(
+    mut command: Command,
     user_manifest_path: Option<&impl AsRef<Path>>,
     features: &clap_cargo::Features,
-    log_level: Option<String>,
     features_arg: &str,
     flags: &str,
     embed_path: impl AsRef<Path>,
     package_name: &str,
 ) -> eyre::Result<()> {
-    let mut command = crate::env::cargo();
     command.stdin(Stdio::null());
     command.stdout(Stdio::null());
     command.stderr(Stdio::inherit());
@@ -495,10 +499,6 @@ fn second_build(
         command.arg(user_manifest_path.as_ref());
     }
 
-    if let Some(log_level) = &log_level {
-        command.env("RUST_LOG", log_level);
-    }
-
     if !features_arg.trim().is_empty() {
         command.arg("--features");
         command.arg(features_arg);
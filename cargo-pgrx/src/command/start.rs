@@ -7,8 +7,10 @@
 //LICENSE All rights reserved.
 //LICENSE
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use crate::cluster_config::ClusterConfig;
 use crate::command::init::initdb;
 use crate::command::status::status_postgres;
+use crate::command::stop::stop_postgres;
 use crate::manifest::{get_package_manifest, pg_config_and_version};
 use crate::CommandExecute;
 use eyre::eyre;
@@ -46,8 +48,9 @@ impl CommandExecute for Start {
 
             let (pg_config, _) =
                 pg_config_and_version(pgrx, &package_manifest, me.pg_version, None, false)?;
+            let cluster_config = ClusterConfig::from_manifest(&package_manifest)?;
 
-            start_postgres(&pg_config)
+            start_postgres(&pg_config, &cluster_config)
         }
         let (package_manifest, _) = get_package_manifest(
             &clap_cargo::Features::default(),
@@ -70,7 +73,10 @@ impl CommandExecute for Start {
 }
 
 #[tracing::instrument(level = "error", skip_all, fields(pg_version = %pg_config.version()?))]
-pub(crate) fn start_postgres(pg_config: &PgConfig) -> eyre::Result<()> {
+pub(crate) fn start_postgres(
+    pg_config: &PgConfig,
+    cluster_config: &ClusterConfig,
+) -> eyre::Result<()> {
     let datadir = pg_config.data_dir()?;
     let logfile = pg_config.log_file()?;
     let bindir = pg_config.bin_dir()?;
@@ -80,9 +86,22 @@ pub(crate) fn start_postgres(pg_config: &PgConfig) -> eyre::Result<()> {
         initdb(&bindir, &datadir)?;
     }
 
+    let config_changed = cluster_config.apply(&datadir)?;
+
     if status_postgres(pg_config)? {
-        tracing::debug!("Already started");
-        return Ok(());
+        if !config_changed {
+            tracing::debug!("Already started");
+            return Ok(());
+        }
+
+        // the new configuration could include settings, like `shared_preload_libraries`, that
+        // only take effect at server start
+        println!(
+            "{} Postgres v{} to apply configuration changes from Cargo.toml",
+            "  Restarting".bold().green(),
+            pg_config.major_version()?
+        );
+        stop_postgres(pg_config)?;
     }
 
     println!(
@@ -4,7 +4,7 @@ use std::process::Command;
 use owo_colors::OwoColorize;
 
 use crate::command::install::Install;
-use crate::command::package::Package;
+use crate::command::package::{Package, PackageFormat};
 use crate::CommandExecute;
 
 /// Like `cargo pgrx install`, but uses `sudo` to copy the extension files
@@ -47,6 +47,7 @@ impl From<SudoInstall> for Package {
             test: value.test,
             pg_config: value.pg_config,
             out_dir: value.out_dir,
+            format: PackageFormat::Dir,
             features: value.features,
             verbose: value.verbose,
         }
@@ -7,11 +7,14 @@
 //LICENSE All rights reserved.
 //LICENSE
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use cargo_toml::Manifest;
 use eyre::Context;
-use pgrx_pg_config::{get_target_dir, PgConfig, Pgrx};
+use pgrx_pg_config::{cargo::PgrxManifestExt, get_target_dir, PgConfig, Pgrx};
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 
+use crate::command::bench::BenchHarness;
+use crate::coverage::Coverage;
 use crate::manifest::{get_package_manifest, pg_config_and_version};
 use crate::profile::CargoProfile;
 use crate::CommandExecute;
@@ -46,6 +49,10 @@ pub(crate) struct Test {
     /// Initialize the test database cluster here, instead of the default location.  If used with `--runas`, then it must be writable by the user
     #[clap(long, value_name = "DIR")]
     pgdata: Option<PathBuf>,
+    /// Build with `-C instrument-coverage` and produce lcov and HTML coverage reports, including
+    /// code run inside Postgres backends.  Requires `rustup component add llvm-tools-preview`
+    #[clap(long)]
+    coverage: bool,
     #[clap(flatten)]
     features: clap_cargo::Features,
     #[clap(from_global, action = clap::ArgAction::Count)]
@@ -77,12 +84,15 @@ impl CommandExecute for Test {
                 &pg_config,
                 me.manifest_path.as_ref(),
                 me.package.as_ref(),
+                &package_manifest,
                 &profile,
                 me.no_schema,
                 &features,
                 me.testname,
                 me.runas,
                 me.pgdata,
+                me.coverage,
+                None,
             )?;
 
             Ok(())
@@ -119,12 +129,15 @@ pub fn test_extension(
     pg_config: &PgConfig,
     user_manifest_path: Option<impl AsRef<Path>>,
     user_package: Option<&String>,
+    package_manifest: &Manifest,
     profile: &CargoProfile,
     no_schema: bool,
     features: &clap_cargo::Features,
     testname: Option<impl AsRef<str>>,
     runas: Option<String>,
     pgdata: Option<PathBuf>,
+    coverage: bool,
+    bench: Option<&BenchHarness>,
 ) -> eyre::Result<()> {
     if let Some(ref testname) = testname {
         tracing::Span::current().record("testname", tracing::field::display(&testname.as_ref()));
@@ -132,6 +145,7 @@ pub fn test_extension(
     let target_dir = get_target_dir()?;
 
     let mut command = crate::env::cargo();
+    crate::env::use_pg_config(&mut command, pg_config)?;
 
     let no_default_features_arg = features.no_default_features;
     let mut features_arg = features.features.join(" ");
@@ -154,10 +168,28 @@ pub fn test_extension(
         command.env("CARGO_PGRX_TEST_RUNAS", runas);
     }
 
+    // builds registered under their own labels can't share a test cluster with the other builds
+    // of their major version
+    let pgdata = match pgdata {
+        None if !pg_config.has_default_label()? => {
+            Some(target_dir.join("test-pgdata").join(pg_config.label()?))
+        }
+        pgdata => pgdata,
+    };
     if let Some(pgdata) = pgdata {
         command.env("CARGO_PGRX_TEST_PGDATA", pgdata);
     }
 
+    let coverage = coverage.then(|| Coverage::new(&target_dir));
+    if let Some(coverage) = &coverage {
+        coverage.prepare()?;
+        coverage.configure(&mut command);
+    }
+
+    if let Some(bench) = bench {
+        bench.configure(&mut command);
+    }
+
     if let Ok(rust_log) = std::env::var("RUST_LOG") {
         command.env("RUST_LOG", rust_log);
     }
@@ -191,6 +223,10 @@ pub fn test_extension(
         command.arg(testname.as_ref());
     }
 
+    if let Some(bench) = bench {
+        command.arg("--").args(bench.harness_args());
+    }
+
     eprintln!("{command:?}");
 
     tracing::debug!(command = ?command, "Running");
@@ -201,5 +237,17 @@ pub fn test_extension(
         std::process::exit(1)
     }
 
+    if let Some(coverage) = coverage {
+        // the extension's shared library is what the Postgres backends ran, and it's where
+        // all of the `#[pg_test]` code lives
+        let so_ext = if cfg!(target_os = "macos") { "dylib" } else { "so" };
+        let target_name = package_manifest.target_name()?.replace('-', "_");
+        let shlib = coverage
+            .target_dir()
+            .join(profile.target_subdir())
+            .join(format!("lib{target_name}.{so_ext}"));
+        coverage.report(&[shlib])?;
+    }
+
     Ok(())
 }
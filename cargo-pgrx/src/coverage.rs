@@ -0,0 +1,201 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Support for `cargo pgrx test --coverage`
+//!
+//! An instrumented build lives in its own target directory so that it doesn't clobber (or get
+//! clobbered by) the regular build.  Every process that runs instrumented code -- the test
+//! binary and each Postgres backend that loads the extension -- writes a `.profraw` file into
+//! `profraw/`, which we then merge and hand to `llvm-cov` for the lcov and HTML reports.
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The flags `cargo-pgrx` adds to `RUSTFLAGS` for an instrumented build.  `--cfg pgrx_coverage`
+/// tells `pgrx` to flush the profile from an `on_proc_exit` callback in each backend.
+const COVERAGE_RUSTFLAGS: &str = "-C instrument-coverage --cfg pgrx_coverage";
+
+/// The locations of everything a coverage run produces
+#[derive(Debug, Clone)]
+pub(crate) struct Coverage {
+    /// The `CARGO_TARGET_DIR` for the instrumented build
+    target_dir: PathBuf,
+}
+
+impl Coverage {
+    pub(crate) fn new(target_dir: &Path) -> Self {
+        Self { target_dir: target_dir.join("pgrx-coverage") }
+    }
+
+    pub(crate) fn target_dir(&self) -> &Path {
+        &self.target_dir
+    }
+
+    fn profraw_dir(&self) -> PathBuf {
+        self.target_dir.join("profraw")
+    }
+
+    fn profdata_file(&self) -> PathBuf {
+        self.target_dir.join("pgrx.profdata")
+    }
+
+    pub(crate) fn lcov_file(&self) -> PathBuf {
+        self.target_dir.join("lcov.info")
+    }
+
+    pub(crate) fn html_dir(&self) -> PathBuf {
+        self.target_dir.join("html")
+    }
+
+    /// Remove profiles left over from a previous run so they don't pollute this one
+    pub(crate) fn prepare(&self) -> eyre::Result<()> {
+        let profraw_dir = self.profraw_dir();
+        if profraw_dir.exists() {
+            std::fs::remove_dir_all(&profraw_dir).wrap_err_with(|| {
+                format!("unable to remove old coverage profiles in {}", profraw_dir.display())
+            })?;
+        }
+        std::fs::create_dir_all(&profraw_dir)
+            .wrap_err_with(|| format!("unable to create {}", profraw_dir.display()))?;
+        Ok(())
+    }
+
+    /// Configure `command` (generally `cargo test`) to build and run instrumented code.
+    ///
+    /// These environment variables are inherited by the `cargo pgrx install` that the test
+    /// framework runs and by the Postgres instance it starts, so they apply all the way down.
+    pub(crate) fn configure(&self, command: &mut Command) {
+        let mut rustflags = std::env::var_os("RUSTFLAGS").unwrap_or_default();
+        if !rustflags.is_empty() {
+            rustflags.push(" ");
+        }
+        rustflags.push(COVERAGE_RUSTFLAGS);
+
+        // `%p` is the pid of the process writing the profile and `%m` lets processes running
+        // the same binary safely merge into a shared file
+        let mut profile_file = OsString::from(self.profraw_dir());
+        profile_file.push("/pgrx-%p-%m.profraw");
+
+        command
+            .env("CARGO_TARGET_DIR", &self.target_dir)
+            .env("RUSTFLAGS", rustflags)
+            .env("LLVM_PROFILE_FILE", profile_file);
+    }
+
+    /// Merge every `.profraw` file and generate an lcov file and HTML report for `objects`
+    pub(crate) fn report(&self, objects: &[PathBuf]) -> eyre::Result<()> {
+        let profraws = std::fs::read_dir(self.profraw_dir())?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension() == Some("profraw".as_ref()))
+            .collect::<Vec<_>>();
+        if profraws.is_empty() {
+            return Err(eyre!(
+                "no coverage profiles were written to {}",
+                self.profraw_dir().display()
+            ));
+        }
+
+        println!(
+            "{} {} coverage profiles",
+            "     Merging".bold().green(),
+            profraws.len().to_string().cyan()
+        );
+        let mut merge = Command::new(llvm_tool("llvm-profdata")?);
+        merge.arg("merge").arg("-sparse").args(&profraws).arg("-o").arg(self.profdata_file());
+        run(merge)?;
+
+        let mut export = self.llvm_cov("export", objects)?;
+        export.arg("-format=lcov");
+        let output = output(&mut export)?;
+        std::fs::write(self.lcov_file(), output)
+            .wrap_err_with(|| format!("unable to write {}", self.lcov_file().display()))?;
+        println!(
+            "{} lcov report to {}",
+            "     Writing".bold().green(),
+            self.lcov_file().display().cyan()
+        );
+
+        let mut show = self.llvm_cov("show", objects)?;
+        show.arg("-format=html").arg(format!("-output-dir={}", self.html_dir().display()));
+        run(show)?;
+        println!(
+            "{} HTML report to {}",
+            "     Writing".bold().green(),
+            self.html_dir().join("index.html").display().cyan()
+        );
+
+        Ok(())
+    }
+
+    fn llvm_cov(&self, subcommand: &str, objects: &[PathBuf]) -> eyre::Result<Command> {
+        let mut objects = objects.iter();
+        let first = objects
+            .next()
+            .ok_or_else(|| eyre!("no instrumented objects to generate a coverage report for"))?;
+
+        let mut command = Command::new(llvm_tool("llvm-cov")?);
+        command
+            .arg(subcommand)
+            .arg(format!("-instr-profile={}", self.profdata_file().display()))
+            // we only care about the extension's code, not its dependencies or std
+            .arg("-ignore-filename-regex=/.cargo/registry/|/rustc/")
+            .arg(first);
+        for object in objects {
+            command.arg("-object").arg(object);
+        }
+        Ok(command)
+    }
+}
+
+/// Find one of the LLVM tools shipped by rustup's `llvm-tools` component, falling back to `$PATH`
+fn llvm_tool(name: &str) -> eyre::Result<PathBuf> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc).arg("--print").arg("target-libdir").output()?;
+    if output.status.success() {
+        // the tools live next to the host's `lib/` directory, in `bin/`
+        let libdir = PathBuf::from(String::from_utf8(output.stdout)?.trim());
+        if let Some(tool) = libdir.parent().map(|dir| dir.join("bin").join(name)) {
+            if tool.exists() {
+                return Ok(tool);
+            }
+        }
+    }
+
+    match which(name) {
+        Some(tool) => Ok(tool),
+        None => Err(eyre!(
+            "unable to find `{name}`.  Install it with `rustup component add llvm-tools-preview`"
+        )),
+    }
+}
+
+fn which(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).map(|dir| dir.join(name)).find(|tool| tool.exists())
+}
+
+fn run(mut command: Command) -> eyre::Result<()> {
+    output(command.stdout(std::process::Stdio::inherit())).map(|_| ())
+}
+
+fn output(command: &mut Command) -> eyre::Result<Vec<u8>> {
+    let command_str = format!("{command:?}");
+    tracing::debug!(command = %command_str, "Running");
+    let output = command
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .wrap_err_with(|| format!("failed to spawn `{command_str}`"))?;
+    if !output.status.success() {
+        return Err(eyre!("`{command_str}` failed with {}", output.status));
+    }
+    Ok(output.stdout)
+}
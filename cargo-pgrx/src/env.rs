@@ -12,6 +12,22 @@ pub(crate) fn cargo() -> std::process::Command {
     std::process::Command::new(cargo)
 }
 
+/// Point a `cargo` command at the Postgres described by `pg_config`.
+///
+/// `pgrx-pg-sys`' build script and the test framework look Postgres up by its feature flag, which
+/// finds the default build of a major version, so a build registered under its own label must be
+/// named to them directly.
+pub(crate) fn use_pg_config(
+    command: &mut std::process::Command,
+    pg_config: &pgrx_pg_config::PgConfig,
+) -> eyre::Result<()> {
+    if !pg_config.has_default_label()? {
+        let path = pg_config.path().ok_or_else(|| eyre::eyre!("no path for pg_config"))?;
+        command.env("PGRX_PG_CONFIG_PATH", path);
+    }
+    Ok(())
+}
+
 /// Set some environment variables for use downstream (in `pgrx-test` for
 /// example). Does nothing if already set.
 pub(crate) fn initialize() {
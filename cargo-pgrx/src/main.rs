@@ -7,7 +7,9 @@
 //LICENSE All rights reserved.
 //LICENSE
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+mod cluster_config;
 mod command;
+mod coverage;
 mod manifest;
 mod metadata;
 
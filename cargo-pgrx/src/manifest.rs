@@ -171,8 +171,10 @@ pub(crate) fn pg_config_and_version(
         Some(pg_version) => {
             // we have determined a Postgres version
 
-            modify_features_for_version(pgrx, user_features, manifest, &pg_version, false);
             let pg_config = pgrx.get(pg_version.label())?;
+            // a labeled build's feature flag is still the one for its major version
+            let feature_flag = PgVersionSource::PgConfig(pg_config.default_label()?);
+            modify_features_for_version(pgrx, user_features, manifest, &feature_flag, false);
 
             if verbose {
                 display_version_info(&pg_config, &pg_version);
@@ -219,6 +221,8 @@ pub(crate) fn all_pg_in_both_tomls<'a>(
     // as it makes sense to further constrain support from the version set pgrx supports,
     // but it doesn't make sense to e.g. not run tests when admin thought it was requested?
     pgrx.iter(PgConfigSelector::All).filter(|result| match result {
+        // builds registered under their own labels are only used when asked for by name
+        Ok(pg_config) if !pg_config.has_default_label().unwrap_or(true) => false,
         Ok(pg_config) => {
             if let Ok(ver) = pg_config.major_version() {
                 // Clumsy: we rely on these features enabling `pgrx/pg{ver}` instead of verifying.
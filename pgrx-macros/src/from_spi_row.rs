@@ -0,0 +1,107 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{Data, DeriveInput, Fields, LitInt, LitStr};
+
+pub(crate) fn deriving_from_spi_row(ast: DeriveInput) -> syn::Result<TokenStream> {
+    let Data::Struct(data) = &ast.data else {
+        return Err(syn::Error::new(
+            ast.span(),
+            "#[derive(FromSpiRow)] can only be applied to structs",
+        ));
+    };
+
+    let mut columns = Vec::new();
+    let mut reads = Vec::new();
+    for (idx, field) in data.fields.iter().enumerate() {
+        let column = match field_column(field)? {
+            Some(column) => column,
+            None => match &field.ident {
+                Some(ident) => {
+                    let name = ident.to_string();
+                    let name = name.strip_prefix("r#").unwrap_or(&name);
+                    quote! { ::pgrx::spi::SpiColumn::Name(#name) }
+                }
+                None => {
+                    let position = idx + 1;
+                    quote! { ::pgrx::spi::SpiColumn::Position(#position) }
+                }
+            },
+        };
+        let ty = &field.ty;
+        columns.push(quote_spanned! { ty.span() =>
+            #column.find::<#ty>(table)?
+        });
+        let read = quote_spanned! { ty.span() =>
+            <#ty as ::pgrx::spi::SpiField>::read(row, ordinals[#idx])?
+        };
+        reads.push(match &field.ident {
+            Some(ident) => quote! { #ident: #read },
+            None => read,
+        });
+    }
+
+    let construct = match &data.fields {
+        Fields::Named(_) => quote! { Self { #(#reads,)* } },
+        Fields::Unnamed(_) => quote! { Self(#(#reads,)*) },
+        Fields::Unit => quote! { Self },
+    };
+
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::pgrx::spi::FromSpiRow for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn columns(
+                table: &::pgrx::spi::SpiTupleTable<'_>,
+            ) -> ::pgrx::spi::SpiResult<::std::vec::Vec<usize>> {
+                ::std::result::Result::Ok(::std::vec![#(#columns),*])
+            }
+
+            #[allow(unused_variables)]
+            fn from_spi_row(
+                row: &::pgrx::spi::SpiHeapTupleData<'_>,
+                ordinals: &[usize],
+            ) -> ::pgrx::spi::SpiResult<Self> {
+                ::std::result::Result::Ok(#construct)
+            }
+        }
+    })
+}
+
+/// The column given by a field's `#[spi(name = "...")]` or `#[spi(position = N)]`, if it has one
+fn field_column(field: &syn::Field) -> syn::Result<Option<TokenStream>> {
+    let mut column = None;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("spi")) {
+        attr.parse_nested_meta(|meta| {
+            if column.is_some() {
+                return Err(meta.error("a field can only be read from one column"));
+            }
+            if meta.path.is_ident("name") {
+                let name: LitStr = meta.value()?.parse()?;
+                column = Some(quote! { ::pgrx::spi::SpiColumn::Name(#name) });
+                Ok(())
+            } else if meta.path.is_ident("position") {
+                let lit: LitInt = meta.value()?.parse()?;
+                let position = lit.base10_parse::<usize>()?;
+                if position == 0 {
+                    return Err(syn::Error::new(lit.span(), "column positions start at 1"));
+                }
+                column = Some(quote! { ::pgrx::spi::SpiColumn::Position(#position) });
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"` or `position = N`"))
+            }
+        })?;
+    }
+    Ok(column)
+}
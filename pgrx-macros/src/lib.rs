@@ -17,6 +17,7 @@ use quote::{format_ident, quote, ToTokens};
 use syn::spanned::Spanned;
 use syn::{parse_macro_input, Attribute, Data, DeriveInput, Item, ItemImpl};
 
+use from_spi_row::deriving_from_spi_row;
 use operators::{deriving_postgres_eq, deriving_postgres_hash, deriving_postgres_ord};
 use pgrx_sql_entity_graph as sql_gen;
 use sql_gen::{
@@ -24,6 +25,7 @@ use sql_gen::{
     PgAggregate, PgCast, PgExtern, PostgresEnum, Schema,
 };
 
+mod from_spi_row;
 mod operators;
 mod rewriter;
 
@@ -145,6 +147,286 @@ pub fn pg_test(attr: TokenStream, item: TokenStream) -> TokenStream {
     stream.into()
 }
 
+/// `#[pg_bench]` functions are benchmarks, run repeatedly in-process inside Postgres during
+/// `cargo pgrx bench`.
+///
+/// A function that returns nothing is itself what's benchmarked.  A function that returns a string
+/// instead returns a SQL statement, and that statement is what's benchmarked.
+///
+/// Each benchmark runs 100 times, unless given `#[pg_bench(iterations = N)]`.  Like `#[pg_test]`
+/// functions, they belong in the extension's `tests` schema.
+#[proc_macro_attribute]
+pub fn pg_bench(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut iterations = 100u32;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("iterations") {
+            iterations = meta.value()?.parse::<syn::LitInt>()?.base10_parse()?;
+            Ok(())
+        } else {
+            Err(meta.error("unsupported #[pg_bench] argument"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+
+    let func = match parse_macro_input!(item as syn::Item) {
+        Item::Fn(func) => func,
+        thing => {
+            return syn::Error::new(
+                thing.span(),
+                "#[pg_bench] can only be applied to top-level functions",
+            )
+            .into_compile_error()
+            .into()
+        }
+    };
+
+    let bench_func_name = &func.sig.ident;
+    let sql_funcname = bench_func_name.to_string();
+    let wrapper_func_name = format_ident!("__pgrx_bench_{}", bench_func_name);
+    let test_func_name = format_ident!("pg_bench_{}", bench_func_name);
+
+    let run = match func.sig.output {
+        syn::ReturnType::Default => quote! {
+            ::pgrx::bench::run(iterations as u32, || #bench_func_name())
+        },
+        syn::ReturnType::Type(..) => quote! {
+            ::pgrx::bench::run_sql(iterations as u32, ::core::convert::AsRef::<str>::as_ref(&#bench_func_name()))
+        },
+    };
+    let wrapper = quote! {
+        fn #wrapper_func_name(iterations: i32) -> ::pgrx::datum::JsonB {
+            if iterations < 0 {
+                ::pgrx::error!("benchmark iterations must not be negative");
+            }
+            ::pgrx::datum::JsonB::from(#run)
+        }
+    };
+
+    let mut stream = proc_macro2::TokenStream::from(pg_extern(
+        quote!(name = #sql_funcname).into(),
+        wrapper.into(),
+    ));
+    stream.extend(quote! {
+        #func
+
+        #[test]
+        #[ignore = "benchmarks only run under `cargo pgrx bench`"]
+        fn #test_func_name() {
+            crate::pg_test::setup(Vec::new());
+            let res = pgrx_tests::run_bench(#sql_funcname, #iterations, crate::pg_test::postgresql_conf_options());
+            match res {
+                Ok(()) => (),
+                Err(e) => panic!("{e:?}")
+            }
+        }
+    });
+    stream.into()
+}
+
+/// Declare a function as `#[pg_bgworker]` to run it as a background worker.
+///
+/// The function takes no arguments, or one argument of a type that's `serde::Serialize` and
+/// `serde::Deserialize`, which is passed to the worker serialized in its `extra` data, so must
+/// encode to at most 128 bytes.  Alongside it, a module of the same name is generated, with:
+///
+/// * `register(args)`, which registers the worker to start with Postgres, from `_PG_init()`
+/// * `launch(args)`, which starts it now, returning a `DynamicBackgroundWorker`
+/// * `builder(&args)`, the `BackgroundWorkerBuilder` both of those use, for anything else
+///
+/// The worker's `SIGHUP` and `SIGTERM` handlers are attached before the function is called, and
+/// if it's given a `database`, it's connected to it for SPI.  Options are:
+///
+/// * `name = "..."`: the worker's name, which defaults to the function's
+/// * `library = "..."`: the shared library it's in, which defaults to the current crate's
+/// * `start = PostmasterStart | ConsistentState | RecoveryFinished`: when Postgres starts it,
+///   which defaults to `RecoveryFinished` if it connects to a database, and `PostmasterStart` if
+///   not
+/// * `restart = N`: restart it `N` seconds after it crashes, rather than never
+/// * `database = ...` and `user = ...`: `&str` expressions naming the database and user to
+///   connect as, evaluated in the worker
+///
+/// ```rust,ignore
+/// use pgrx::prelude::*;
+/// use pgrx::bgworkers::BackgroundWorker;
+/// use std::time::Duration;
+///
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Config {
+///     interval_secs: u64,
+/// }
+///
+/// #[pg_bgworker(database = "postgres", restart = 10)]
+/// fn vacuum_nagger(config: Config) {
+///     while BackgroundWorker::wait_latch(Some(Duration::from_secs(config.interval_secs))) {
+///         BackgroundWorker::transaction(|| Spi::run("SELECT nag()")).unwrap();
+///     }
+/// }
+///
+/// #[pg_guard]
+/// pub extern "C" fn _PG_init() {
+///     vacuum_nagger::register(Config { interval_secs: 60 });
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn pg_bgworker(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name = None::<syn::LitStr>;
+    let mut library = None::<syn::LitStr>;
+    let mut start = None::<Ident>;
+    let mut restart = None::<syn::LitInt>;
+    let mut database = None::<syn::Expr>;
+    let mut user = None::<syn::Expr>;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("library") {
+            library = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("start") {
+            let ident = meta.value()?.parse::<Ident>()?;
+            if !["PostmasterStart", "ConsistentState", "RecoveryFinished"]
+                .contains(&ident.to_string().as_str())
+            {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "expected `PostmasterStart`, `ConsistentState`, or `RecoveryFinished`",
+                ));
+            }
+            start = Some(ident);
+        } else if meta.path.is_ident("restart") {
+            let secs = meta.value()?.parse::<syn::LitInt>()?;
+            secs.base10_parse::<u64>()?;
+            restart = Some(secs);
+        } else if meta.path.is_ident("database") {
+            database = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("user") {
+            user = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error("unsupported #[pg_bgworker] argument"));
+        }
+        Ok(())
+    });
+    parse_macro_input!(attr with parser);
+
+    let func = match parse_macro_input!(item as syn::Item) {
+        Item::Fn(func) => func,
+        thing => {
+            return syn::Error::new(
+                thing.span(),
+                "#[pg_bgworker] can only be applied to top-level functions",
+            )
+            .into_compile_error()
+            .into()
+        }
+    };
+    if !func.sig.generics.params.is_empty() || func.sig.asyncness.is_some() {
+        return syn::Error::new(
+            func.sig.span(),
+            "#[pg_bgworker] functions can't be generic or async",
+        )
+        .into_compile_error()
+        .into();
+    }
+    if user.is_some() && database.is_none() {
+        return syn::Error::new(
+            func.sig.span(),
+            "#[pg_bgworker(user = ...)] also needs a `database`",
+        )
+        .into_compile_error()
+        .into();
+    }
+    let arg_type = match func.sig.inputs.iter().collect::<Vec<_>>()[..] {
+        [] => None,
+        [syn::FnArg::Typed(arg)] => Some(arg.ty.clone()),
+        _ => {
+            return syn::Error::new(
+                func.sig.inputs.span(),
+                "#[pg_bgworker] functions take no arguments, or a single serializable one",
+            )
+            .into_compile_error()
+            .into()
+        }
+    };
+
+    let vis = &func.vis;
+    let ident = &func.sig.ident;
+    let function = ident.to_string();
+    let name = name.map_or_else(|| quote!(#function), |name| quote!(#name));
+    let library = library.map_or_else(|| quote!(env!("CARGO_CRATE_NAME")), |lib| quote!(#lib));
+    let module_doc = format!("Registers and launches the `{function}` background worker");
+
+    let connect = database.as_ref().map(|database| {
+        let user = user.as_ref().map_or_else(|| quote!(None), |user| quote!(Some(#user)));
+        quote! {
+            ::pgrx::bgworkers::BackgroundWorker::connect_worker_to_spi(Some(#database), #user);
+        }
+    });
+    let access = match database {
+        Some(_) => quote!(.enable_spi_access()),
+        None => quote!(.enable_shmem_access(None)),
+    };
+    let start =
+        start.map(|start| quote!(.set_start_time(::pgrx::bgworkers::BgWorkerStartTime::#start)));
+    let restart = restart
+        .map(|secs| quote!(.set_restart_time(Some(::core::time::Duration::from_secs(#secs)))));
+    let (call, builder_params, params, args, extra) = match arg_type {
+        Some(ty) => (
+            quote!(super::#ident(::pgrx::bgworkers::BackgroundWorker::get_extra_args::<#ty>())),
+            quote!(args: &#ty),
+            quote!(args: #ty),
+            quote!(&args),
+            quote!(.set_extra_args(args)),
+        ),
+        None => (quote!(super::#ident()), quote!(), quote!(), quote!(), quote!()),
+    };
+
+    quote! {
+        #func
+
+        #[doc = #module_doc]
+        #vis mod #ident {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[::pgrx::pg_guard]
+            #[no_mangle]
+            pub extern "C" fn #ident(_arg: ::pgrx::pg_sys::Datum) {
+                ::pgrx::bgworkers::BackgroundWorker::attach_signal_handlers(
+                    ::pgrx::bgworkers::SignalWakeFlags::SIGHUP
+                        | ::pgrx::bgworkers::SignalWakeFlags::SIGTERM,
+                );
+                #connect
+                #call;
+            }
+
+            /// The worker's builder, configured from its `#[pg_bgworker]` options
+            pub fn builder(#builder_params) -> ::pgrx::bgworkers::BackgroundWorkerBuilder {
+                ::pgrx::bgworkers::BackgroundWorkerBuilder::new(#name)
+                    .set_library(#library)
+                    .set_function(#function)
+                    #access
+                    #start
+                    #restart
+                    #extra
+            }
+
+            /// Register the worker to be started by Postgres.  This must be called from
+            /// `_PG_init()`, in a library loaded by `shared_preload_libraries`.
+            pub fn register(#params) {
+                builder(#args).load()
+            }
+
+            /// Start the worker now, returning `Err(())` if Postgres has no room for another
+            pub fn launch(
+                #params
+            ) -> ::core::result::Result<::pgrx::bgworkers::DynamicBackgroundWorker, ()> {
+                builder(#args)
+                    .set_notify_pid(unsafe { ::pgrx::pg_sys::MyProcPid })
+                    .load_dynamic()
+            }
+        }
+    }
+    .into()
+}
+
 /// Associated macro for `#[pg_test]` to provide context back to your test framework to indicate
 /// that the test system is being initialized
 #[proc_macro_attribute]
@@ -1093,6 +1375,33 @@ fn impl_guc_enum(ast: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     Ok(stream)
 }
 
+/**
+Derives `pgrx::spi::FromSpiRow`, so SPI results can be read as a struct with
+`SpiClient::select_as` or `SpiTupleTable::rows_as`.
+
+```rust,ignore
+use pgrx::prelude::*;
+
+#[derive(FromSpiRow)]
+struct Dog {
+    name: String,
+    #[spi(name = "age_in_years")]
+    age: Option<i32>,
+}
+```
+
+Fields are read from the column of the same name, or for tuple structs, from the column in the
+same position.  Optionally accepts the following attributes on fields:
+
+* `name = "..."`: Read the field from the column with this name.
+* `position = N`: Read the field from the column at this 1-based position.
+*/
+#[proc_macro_derive(FromSpiRow, attributes(spi))]
+pub fn derive_from_spi_row(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as syn::DeriveInput);
+    deriving_from_spi_row(ast).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
 #[derive(Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
 enum PostgresTypeAttribute {
     InOutFuncs,
@@ -15,7 +15,7 @@ use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
 use thiserror::Error;
@@ -25,6 +25,9 @@ pub mod cargo;
 
 pub static BASE_POSTGRES_PORT_NO: u16 = 28800;
 pub static BASE_POSTGRES_TESTING_PORT_NO: u16 = 32200;
+/// The lowest port offset given to a Postgres registered under its own label.  The ones with
+/// default labels are offset by their major version, so these stay well clear of them
+pub static FIRST_LABELED_PORT_OFFSET: u16 = 100;
 
 /// The flags to specify to get a "C.UTF-8" locale on this system, or "C" locale on systems without
 /// a "C.UTF-8" locale equivalent.
@@ -116,6 +119,8 @@ pub struct PgConfig {
     known_props: Option<BTreeMap<String, String>>,
     base_port: u16,
     base_testing_port: u16,
+    label: Option<String>,
+    port_offset: Option<u16>,
 }
 
 impl Display for PgConfig {
@@ -132,6 +137,8 @@ impl Default for PgConfig {
             known_props: None,
             base_port: BASE_POSTGRES_PORT_NO,
             base_testing_port: BASE_POSTGRES_TESTING_PORT_NO,
+            label: None,
+            port_offset: None,
         }
     }
 }
@@ -150,6 +157,8 @@ impl PgConfig {
             known_props: None,
             base_port,
             base_testing_port,
+            label: None,
+            port_offset: None,
         }
     }
 
@@ -160,6 +169,8 @@ impl PgConfig {
             known_props: None,
             base_port: BASE_POSTGRES_PORT_NO,
             base_testing_port: BASE_POSTGRES_TESTING_PORT_NO,
+            label: None,
+            port_offset: None,
         }
     }
 
@@ -193,6 +204,8 @@ impl PgConfig {
                 known_props: Some(known_props),
                 base_port: 0,
                 base_testing_port: 0,
+                label: None,
+                port_offset: None,
             })
         }
     }
@@ -208,10 +221,45 @@ impl PgConfig {
         self.pg_config.is_some()
     }
 
+    /// Register this Postgres under `label` rather than the default `pgXX`, so that it can sit
+    /// alongside other builds of the same major version
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Offset this Postgres' ports from the base ports by `offset` rather than by its major version
+    pub fn with_port_offset(mut self, offset: u16) -> Self {
+        self.port_offset = Some(offset);
+        self
+    }
+
     pub fn label(&self) -> eyre::Result<String> {
+        match &self.label {
+            Some(label) => Ok(label.clone()),
+            None => self.default_label(),
+        }
+    }
+
+    /// The label for this major version, `pgXX`, which is also the name of its feature flag
+    pub fn default_label(&self) -> eyre::Result<String> {
         Ok(format!("pg{}", self.major_version()?))
     }
 
+    pub fn has_default_label(&self) -> eyre::Result<bool> {
+        Ok(self.label()? == self.default_label()?)
+    }
+
+    /// The name used for things that must be kept apart per registered Postgres, like data
+    /// directories: the major version for default-labeled ones, or the label otherwise
+    fn instance_name(&self) -> eyre::Result<String> {
+        if self.has_default_label()? {
+            Ok(self.major_version()?.to_string())
+        } else {
+            self.label()
+        }
+    }
+
     pub fn path(&self) -> Option<PathBuf> {
         self.pg_config.clone()
     }
@@ -309,11 +357,18 @@ impl PgConfig {
     }
 
     pub fn port(&self) -> eyre::Result<u16> {
-        Ok(self.base_port + self.major_version()?)
+        Ok(self.base_port + self.port_offset()?)
     }
 
     pub fn test_port(&self) -> eyre::Result<u16> {
-        Ok(self.base_testing_port + self.major_version()?)
+        Ok(self.base_testing_port + self.port_offset()?)
+    }
+
+    fn port_offset(&self) -> eyre::Result<u16> {
+        match self.port_offset {
+            Some(offset) => Ok(offset),
+            None => self.major_version(),
+        }
     }
 
     pub fn host(&self) -> &'static str {
@@ -361,13 +416,13 @@ impl PgConfig {
 
     pub fn data_dir(&self) -> eyre::Result<PathBuf> {
         let mut path = Pgrx::home()?;
-        path.push(format!("data-{}", self.major_version()?));
+        path.push(format!("data-{}", self.instance_name()?));
         Ok(path)
     }
 
     pub fn log_file(&self) -> eyre::Result<PathBuf> {
         let mut path = Pgrx::home()?;
-        path.push(format!("{}.log", self.major_version()?));
+        path.push(format!("{}.log", self.instance_name()?));
         Ok(path)
     }
 
@@ -473,11 +528,69 @@ impl Default for Pgrx {
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ConfigToml {
+    /// The `pg_config` of each registered Postgres, by label
     pub configs: HashMap<String, PathBuf>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub base_port: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub base_testing_port: Option<u16>,
+    /// How `cargo pgrx init` built each of the registered Postgres that it built itself, by label
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<String, PgSource>,
+    /// The port offsets of the registered Postgres that aren't under their default labels, by
+    /// label, so they don't share ports with the other builds of their major version
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub port_offsets: BTreeMap<String, u16>,
+}
+
+impl ConfigToml {
+    /// The port offset of the Postgres registered under `label`, giving it the lowest unused one
+    /// if it doesn't have one yet
+    pub fn assign_port_offset(&mut self, label: &str) -> u16 {
+        if let Some(&offset) = self.port_offsets.get(label) {
+            return offset;
+        }
+        let offset = (FIRST_LABELED_PORT_OFFSET..)
+            .find(|offset| !self.port_offsets.values().any(|used| used == offset))
+            .expect("ran out of port offsets");
+        self.port_offsets.insert(label.to_string(), offset);
+        offset
+    }
+}
+
+/// Where `cargo pgrx init` got the Postgres source code it built, and what it did to it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PgSource {
+    #[serde(flatten)]
+    pub origin: PgSourceOrigin,
+    /// Patch files applied, in order, before running `configure`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "from", rename_all = "lowercase")]
+pub enum PgSourceOrigin {
+    /// A release tarball downloaded from this URL
+    Download { url: String },
+    /// A local tarball, compressed with gzip or bzip2
+    Tarball { path: PathBuf },
+    /// A ref in a local git repository
+    Git {
+        repo: PathBuf,
+        #[serde(rename = "ref")]
+        git_ref: String,
+    },
+}
+
+impl Display for PgSourceOrigin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PgSourceOrigin::Download { url } => write!(f, "{url}"),
+            PgSourceOrigin::Tarball { path } => write!(f, "{}", path.display()),
+            PgSourceOrigin::Git { repo, git_ref } => write!(f, "{}@{git_ref}", repo.display()),
+        }
+    }
 }
 
 pub enum PgConfigSelector<'a> {
@@ -530,8 +643,15 @@ impl Pgrx {
         match std::env::var("PGRX_PG_CONFIG_PATH") {
             Ok(pg_config) => {
                 // we have an environment variable that tells us the pg_config to use
+                let pg_config = PathBuf::from(pg_config);
                 let mut pgrx = Pgrx::default();
-                pgrx.push(PgConfig::new(pg_config.into(), pgrx.base_port, pgrx.base_testing_port));
+                let mut config =
+                    PgConfig::new(pg_config.clone(), pgrx.base_port, pgrx.base_testing_port);
+                // a build registered under its own label keeps its own ports, even when found this way
+                if let Some(offset) = Pgrx::registered_port_offset(&pg_config)? {
+                    config = config.with_port_offset(offset);
+                }
+                pgrx.push(config);
                 Ok(pgrx)
             }
             Err(_) => {
@@ -552,8 +672,13 @@ impl Pgrx {
                             configs.base_testing_port.unwrap_or(BASE_POSTGRES_TESTING_PORT_NO),
                         );
 
-                        for (_, v) in configs.configs {
-                            pgrx.push(PgConfig::new(v, pgrx.base_port, pgrx.base_testing_port));
+                        for (label, v) in configs.configs {
+                            let mut pg_config =
+                                PgConfig::new(v, pgrx.base_port, pgrx.base_testing_port);
+                            if let Some(&offset) = configs.port_offsets.get(&label) {
+                                pg_config = pg_config.with_port_offset(offset);
+                            }
+                            pgrx.push(pg_config.with_label(label));
                         }
                         Ok(pgrx)
                     }
@@ -565,6 +690,23 @@ impl Pgrx {
         }
     }
 
+    /// The port offset `config.toml` gives the Postgres with this `pg_config`, if any
+    fn registered_port_offset(pg_config: &Path) -> eyre::Result<Option<u16>> {
+        // `PGRX_PG_CONFIG_PATH` doesn't need a `$PGRX_HOME`
+        let Ok(path) = Pgrx::config_toml() else { return Ok(None) };
+        let configs = match std::fs::read_to_string(&path) {
+            Ok(file) => toml::from_str::<ConfigToml>(&file)
+                .wrap_err_with(|| format!("Could not read `{}`", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(configs
+            .configs
+            .iter()
+            .find(|(_, path)| *path == pg_config)
+            .and_then(|(label, _)| configs.port_offsets.get(label).copied()))
+    }
+
     pub fn push(&mut self, pg_config: PgConfig) {
         self.pg_configs.push(pg_config);
     }
@@ -589,10 +731,8 @@ impl Pgrx {
 
             (PgConfigSelector::All, _) => {
                 let mut configs = self.pg_configs.iter().collect::<Vec<_>>();
-                configs.sort_by(|a, b| {
-                    a.major_version()
-                        .expect("no major version")
-                        .cmp(&b.major_version().expect("no major version"))
+                configs.sort_by_cached_key(|c| {
+                    (c.major_version().expect("no major version"), c.label().expect("no label"))
                 });
 
                 configs.into_iter().map(|c| Ok(c.clone())).collect::<Vec<_>>().into_iter()
@@ -805,6 +945,61 @@ fn parse_version() {
         PgConfig::parse_version_str("PostgresSQL .53").expect_err("Parsed invalid version string");
 }
 
+#[test]
+fn config_toml_sources() -> eyre::Result<()> {
+    let toml = r#"
+[configs]
+pg16 = "/home/me/.pgrx/16.4/pgrx-install/bin/pg_config"
+pg16-patched = "/home/me/.pgrx/pg16-patched/pgrx-install/bin/pg_config"
+
+[sources.pg16-patched]
+from = "git"
+repo = "/home/me/src/postgres"
+ref = "REL_16_STABLE"
+patches = ["/home/me/fix.patch"]
+"#;
+    let config = toml::from_str::<ConfigToml>(toml)?;
+    assert_eq!(
+        config.sources["pg16-patched"],
+        PgSource {
+            origin: PgSourceOrigin::Git {
+                repo: "/home/me/src/postgres".into(),
+                git_ref: "REL_16_STABLE".into()
+            },
+            patches: vec!["/home/me/fix.patch".into()],
+        }
+    );
+    // and it survives a round trip
+    let config = toml::from_str::<ConfigToml>(&toml::to_string(&config)?)?;
+    assert_eq!(config.sources.len(), 1);
+    assert_eq!(config.configs.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn config_toml_port_offsets() -> eyre::Result<()> {
+    let mut config = toml::from_str::<ConfigToml>(
+        r#"
+[configs]
+pg16 = "/home/me/.pgrx/16.4/pgrx-install/bin/pg_config"
+pg16-patched = "/home/me/.pgrx/pg16-patched/pgrx-install/bin/pg_config"
+
+[port_offsets]
+pg16-patched = 100
+"#,
+    )?;
+    assert_eq!(config.assign_port_offset("pg16-patched"), 100);
+    assert_eq!(config.assign_port_offset("pg16-debug"), 101);
+    assert_eq!(config.assign_port_offset("pg16-debug"), 101);
+
+    let pg_config = PgConfig::from(PgVersion::new(16, PgMinorVersion::Release(4), None));
+    assert_eq!(pg_config.port()?, BASE_POSTGRES_PORT_NO + 16);
+    let pg_config = pg_config.with_label("pg16-patched").with_port_offset(100);
+    assert_eq!(pg_config.port()?, BASE_POSTGRES_PORT_NO + 100);
+    assert_eq!(pg_config.test_port()?, BASE_POSTGRES_TESTING_PORT_NO + 100);
+    Ok(())
+}
+
 #[test]
 fn from_empty_env() -> eyre::Result<()> {
     // without "PGRX_PG_CONFIG_AS_ENV" we can't get one of these
@@ -819,6 +1014,7 @@ fn from_empty_env() -> eyre::Result<()> {
 
     let pg_config = PgConfig::from_env().unwrap();
     assert_eq!(pg_config.major_version()?, 15, "Major version should match");
+    assert_eq!(pg_config.label()?, "pg15", "Label should default to the major version");
     assert_eq!(
         pg_config.minor_version()?,
         PgMinorVersion::Release(1),
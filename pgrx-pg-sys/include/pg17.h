@@ -94,6 +94,7 @@
 #include "foreign/fdwapi.h"
 #include "foreign/foreign.h"
 #include "jit/jit.h"
+#include "lib/dshash.h"
 #include "lib/stringinfo.h"
 #include "libpq/pqformat.h"
 #include "mb/pg_wchar.h"
@@ -145,6 +146,7 @@
 #include "storage/bufmgr.h"
 #include "storage/buffile.h"
 #include "storage/bufpage.h"
+#include "storage/dsm_registry.h"
 #include "storage/indexfsm.h"
 #include "storage/freespace.h"
 #include "storage/ipc.h"
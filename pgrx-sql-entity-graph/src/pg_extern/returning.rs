@@ -63,8 +63,10 @@ impl Returning {
             syn::Type::Path(typepath) => {
                 let is_option = typepath.last_ident_is("Option");
                 let is_result = typepath.last_ident_is("Result");
-                let mut is_setof_iter = typepath.last_ident_is("SetOfIterator");
-                let mut is_table_iter = typepath.last_ident_is("TableIterator");
+                let mut is_setof_iter = typepath.last_ident_is("SetOfIterator")
+                    || typepath.last_ident_is("MaterializedSetOf");
+                let mut is_table_iter = typepath.last_ident_is("TableIterator")
+                    || typepath.last_ident_is("MaterializedTable");
                 let path = &mut typepath.path;
 
                 if is_option || is_result || is_setof_iter || is_table_iter {
@@ -118,9 +120,13 @@ impl Returning {
                             };
                             segments = this_path.path.segments.clone(); // recurse deeper
                         } else {
-                            if segments.last_ident_is("SetOfIterator") {
+                            if segments.last_ident_is("SetOfIterator")
+                                || segments.last_ident_is("MaterializedSetOf")
+                            {
                                 is_setof_iter = true;
-                            } else if segments.last_ident_is("TableIterator") {
+                            } else if segments.last_ident_is("TableIterator")
+                                || segments.last_ident_is("MaterializedTable")
+                            {
                                 is_table_iter = true;
                             }
                             break;
@@ -179,6 +179,102 @@ pub fn run_test(
     }
 }
 
+/// Run the `#[pg_bench]` function `sql_funcname`, which measures itself inside Postgres, then
+/// report its results and save them as JSON for later comparison
+pub fn run_bench(
+    sql_funcname: &str,
+    iterations: u32,
+    postgresql_conf: Vec<&'static str>,
+) -> eyre::Result<()> {
+    let iterations = match std::env::var("PGRX_BENCH_ITERATIONS") {
+        Ok(n) => n.parse().wrap_err_with(|| format!("invalid PGRX_BENCH_ITERATIONS: {n:?}"))?,
+        Err(_) => iterations,
+    };
+    let iterations = i32::try_from(iterations).wrap_err("too many benchmark iterations")?;
+    initialize_test_framework(postgresql_conf)?;
+
+    let (mut client, _session_id) = client()?;
+    let mut tx = client.transaction()?;
+    let schema = "tests"; // get_extension_schema();
+    let row = tx
+        .query_one(&format!("SELECT \"{schema}\".\"{sql_funcname}\"($1)::text;"), &[&iterations])
+        .wrap_err_with(|| format!("benchmark `{sql_funcname}` failed"))?;
+    // whatever the benchmark did, don't keep it around to affect the next one
+    tx.rollback()?;
+
+    let json: String = row.get(0);
+    let stats: pgrx::bench::BenchStats = serde_json::from_str(&json)
+        .wrap_err_with(|| format!("benchmark `{sql_funcname}` returned invalid results"))?;
+
+    let baseline = match std::env::var_os("PGRX_BENCH_BASELINE") {
+        Some(dir) => {
+            let path = PathBuf::from(dir).join(format!("{sql_funcname}.json"));
+            match std::fs::read_to_string(&path) {
+                Ok(json) => Some(serde_json::from_str::<pgrx::bench::BenchStats>(&json)?),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e).wrap_err_with(|| format!("reading {}", path.display())),
+            }
+        }
+        None => None,
+    };
+
+    eprintln!("{}", format_bench_stats(sql_funcname, &stats, baseline.as_ref()));
+
+    if let Some(dir) = std::env::var_os("PGRX_BENCH_OUTPUT") {
+        let path = PathBuf::from(dir).join(format!("{sql_funcname}.json"));
+        std::fs::write(&path, serde_json::to_string_pretty(&stats)?)
+            .wrap_err_with(|| format!("writing {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn format_bench_stats(
+    name: &str,
+    stats: &pgrx::bench::BenchStats,
+    baseline: Option<&pgrx::bench::BenchStats>,
+) -> String {
+    let time = |ns: u64| format!("{:?}", Duration::from_nanos(ns));
+    let mut report = format!(
+        "{name:>32}  mean {}  median {}  min {}  max {}  ({} iterations)",
+        time(stats.mean_ns).bold(),
+        time(stats.median_ns),
+        time(stats.min_ns),
+        time(stats.max_ns),
+        stats.iterations,
+    );
+    if let Some(baseline) = baseline.filter(|baseline| baseline.mean_ns > 0) {
+        let change = (stats.mean_ns as f64 / baseline.mean_ns as f64 - 1.0) * 100.0;
+        let formatted = format!("{change:+.2}%");
+        // differences of a few percent are generally noise
+        let formatted = if change > 5.0 {
+            formatted.red().to_string()
+        } else if change < -5.0 {
+            formatted.green().to_string()
+        } else {
+            formatted
+        };
+        report.push_str(&format!("  [{formatted} vs baseline]"));
+    }
+    if let Some(allocated) = stats.max_mem_allocated {
+        report.push_str(&format!("\n{:>32}  allocated {allocated} bytes", ""));
+    }
+    let buffers = &stats.buffers;
+    report.push_str(&format!(
+        "\n{:>32}  shared hit {} read {} dirtied {} written {}, local hit {} read {}, temp read {} written {}",
+        "",
+        buffers.shared_blks_hit,
+        buffers.shared_blks_read,
+        buffers.shared_blks_dirtied,
+        buffers.shared_blks_written,
+        buffers.local_blks_hit,
+        buffers.local_blks_read,
+        buffers.temp_blks_read,
+        buffers.temp_blks_written,
+    ));
+    report
+}
+
 fn format_loglines(session_id: &str, loglines: &LogLines) -> String {
     let mut result = String::new();
 
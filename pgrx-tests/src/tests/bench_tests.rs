@@ -0,0 +1,60 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::bench::BenchStats;
+    use pgrx::prelude::*;
+    use pgrx::{pg_bench, pg_test};
+
+    #[pg_bench(iterations = 10)]
+    fn bench_palloc() {
+        let bytes = unsafe { pg_sys::palloc(8192) };
+        std::hint::black_box(bytes);
+    }
+
+    #[pg_bench]
+    fn bench_generate_series() -> &'static str {
+        "SELECT count(*) FROM generate_series(1, 1000)"
+    }
+
+    #[pg_test]
+    fn test_bench_runs_every_iteration() {
+        let mut count = 0;
+        let stats = pgrx::bench::run(25, || count += 1);
+        assert_eq!(count, 25);
+        assert_eq!(stats.iterations, 25);
+        assert!(stats.min_ns <= stats.median_ns && stats.median_ns <= stats.max_ns);
+    }
+
+    #[pg_test]
+    fn test_bench_measures_allocations() {
+        let stats = pgrx::bench::run(3, || unsafe {
+            pg_sys::palloc(64 * 1024);
+        });
+        if cfg!(feature = "pg12") {
+            assert_eq!(stats.max_mem_allocated, None);
+        } else {
+            assert!(stats.max_mem_allocated.unwrap() >= 64 * 1024);
+        }
+    }
+
+    #[pg_test]
+    fn test_bench_sql_returns_jsonb() -> Result<(), spi::Error> {
+        let stats = Spi::get_one::<pgrx::JsonB>("SELECT tests.bench_generate_series(5)")?
+            .expect("benchmark results should not be NULL");
+        let stats: BenchStats = serde_json::from_value(stats.0).unwrap();
+        assert_eq!(stats.iterations, 5);
+        Ok(())
+    }
+}
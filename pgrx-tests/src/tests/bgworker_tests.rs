@@ -83,6 +83,31 @@ pub extern "C" fn bgworker_sleep() {
     while BackgroundWorker::wait_latch(None) {}
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TypedBgworkerArgs {
+    table: String,
+    value: i32,
+}
+
+#[pg_bgworker(
+    database = crate::framework::get_pg_dbname(),
+    user = crate::framework::get_pg_user().as_str()
+)]
+/// Here we test that `#[pg_bgworker]` passes typed arguments, and connects to the database
+pub fn typed_bgworker(args: TypedBgworkerArgs) {
+    use pgrx::bgworkers::*;
+    BackgroundWorker::transaction(|| {
+        Spi::run(&format!("CREATE TABLE tests.{} (v INTEGER);", args.table))?;
+        Spi::run(&format!("INSERT INTO tests.{} VALUES ({});", args.table, args.value))
+    })
+    .expect("bgworker transaction failed");
+}
+
+#[pg_bgworker(name = "argumentless bgworker")]
+pub fn argumentless_bgworker() {
+    assert_eq!(pgrx::bgworkers::BackgroundWorker::get_name(), "argumentless bgworker");
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pgrx::pg_schema]
 mod tests {
@@ -148,6 +173,32 @@ mod tests {
         ));
     }
 
+    #[pg_test]
+    fn test_pg_bgworker_typed_args() {
+        // Required to avoid bgworker pool exhaustion errors, see `test_dynamic_worker_allocation_failure`
+        Spi::run("SELECT pg_advisory_xact_lock_shared(42)").unwrap();
+        let args = super::TypedBgworkerArgs { table: "bgworker_test_typed".into(), value: 42 };
+        let worker = super::typed_bgworker::launch(args).expect("Failed to start worker");
+        worker.wait_for_shutdown().expect("aborted shutdown");
+
+        assert_eq!(Ok(Some(42)), Spi::get_one::<i32>("SELECT v FROM tests.bgworker_test_typed;"));
+    }
+
+    #[pg_test]
+    fn test_pg_bgworker_without_args() {
+        // Required to avoid bgworker pool exhaustion errors, see `test_dynamic_worker_allocation_failure`
+        Spi::run("SELECT pg_advisory_xact_lock_shared(42)").unwrap();
+        let worker = super::argumentless_bgworker::launch().expect("Failed to start worker");
+        worker.wait_for_shutdown().expect("aborted shutdown");
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "more than the 128 that fit")]
+    fn test_pg_bgworker_args_too_big() {
+        let args = super::TypedBgworkerArgs { table: "x".repeat(200), value: 0 };
+        let _ = super::typed_bgworker::builder(&args);
+    }
+
     #[pg_test]
     fn test_background_worker_transaction_return() {
         // Required to avoid bgworker pool exhaustion errors, see `test_dynamic_worker_allocation_failure`
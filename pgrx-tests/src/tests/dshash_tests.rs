@@ -0,0 +1,95 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::dshash::{MapEntry, PgSharedHashMap};
+    use pgrx::dsm::DsaArea;
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_dshash_insert_get_remove() {
+        let area = DsaArea::create(c"pgrx_tests_dshash_area");
+        let mut map = PgSharedHashMap::<i64, u64>::create(&area, c"pgrx_tests_dshash");
+
+        assert_eq!(map.insert(1, 10), None);
+        assert_eq!(map.insert(2, 20), None);
+        assert_eq!(map.insert(1, 11), Some(10));
+        assert_eq!(map.get(&1).map(|v| *v), Some(11));
+        assert!(map.get(&3).is_none());
+
+        *map.get_mut(&2).unwrap() += 1;
+        assert_eq!(map.get(&2).map(|v| *v), Some(21));
+
+        assert_eq!(map.remove(&2), Some(21));
+        assert_eq!(map.remove(&2), None);
+        assert!(map.get(&2).is_none());
+    }
+
+    #[pg_test]
+    fn test_dshash_entry() {
+        let area = DsaArea::create(c"pgrx_tests_dshash_area");
+        let mut map = PgSharedHashMap::<i64, u64>::create(&area, c"pgrx_tests_dshash");
+
+        for _ in 0..3 {
+            *map.entry(7).or_default() += 1;
+        }
+        assert_eq!(map.get(&7).map(|v| *v), Some(3));
+
+        // a vacant entry that's never filled in is removed again
+        match map.entry(8) {
+            MapEntry::Vacant(vacant) => assert_eq!(*vacant.key(), 8),
+            MapEntry::Occupied(_) => panic!("key 8 should be vacant"),
+        }
+        assert!(map.get(&8).is_none());
+    }
+
+    #[pg_test]
+    fn test_dshash_attach() {
+        let area = DsaArea::create(c"pgrx_tests_dshash_area");
+        let mut map = PgSharedHashMap::<i64, u64>::create(&area, c"pgrx_tests_dshash");
+        map.insert(42, 4200);
+        let handle = map.handle();
+        drop(map);
+
+        let mut map = unsafe { PgSharedHashMap::<i64, u64>::attach(&area, handle) };
+        assert_eq!(map.get(&42).map(|v| *v), Some(4200));
+        unsafe { map.destroy() };
+    }
+
+    #[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14")))]
+    #[pg_test]
+    fn test_dshash_scan() {
+        let area = DsaArea::create(c"pgrx_tests_dshash_area");
+        let mut map = PgSharedHashMap::<i64, u64>::create(&area, c"pgrx_tests_dshash");
+        for i in 0..100 {
+            map.insert(i, i as u64 * 2);
+        }
+
+        let mut sum = 0;
+        map.for_each(|_, v| sum += *v);
+        assert_eq!(sum, (0..100).map(|i| i * 2).sum::<u64>());
+
+        map.retain(|k, v| {
+            *v += 1;
+            k % 2 == 0
+        });
+        let mut keys = Vec::new();
+        map.for_each(|k, v| {
+            assert_eq!(*v, *k as u64 * 2 + 1);
+            keys.push(*k);
+        });
+        keys.sort();
+        assert_eq!(keys, (0..100).step_by(2).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,100 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::dsm::{DsaArea, DsaPointer, DsmSegment};
+    use pgrx::prelude::*;
+    use pgrx::PGRXSharedMemory;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Default)]
+    struct Counter {
+        count: AtomicU64,
+        next: DsaPointer<Counter>,
+    }
+
+    unsafe impl PGRXSharedMemory for Counter {}
+
+    #[pg_test]
+    fn test_dsm_segment_create() {
+        let mut seg = DsmSegment::create(4096);
+        assert!(seg.len() >= 4096);
+        unsafe { seg.as_mut_slice()[..5].copy_from_slice(b"hello") };
+        assert_eq!(unsafe { &seg.as_slice()[..5] }, b"hello");
+    }
+
+    #[pg_test]
+    fn test_dsm_segment_is_destroyed_after_last_detach() {
+        let seg = DsmSegment::create(1024);
+        let handle = seg.handle();
+        drop(seg);
+        assert!(DsmSegment::attach(handle).is_none());
+    }
+
+    #[pg_test]
+    fn test_dsm_segment_pin() {
+        let seg = DsmSegment::create(1024);
+        let handle = seg.handle();
+        seg.pin();
+        drop(seg);
+
+        let seg = DsmSegment::attach(handle).expect("pinned segment should still exist");
+        assert!(seg.len() >= 1024);
+        DsmSegment::unpin(handle);
+        drop(seg);
+        assert!(DsmSegment::attach(handle).is_none());
+    }
+
+    #[pg_test]
+    fn test_dsa_allocate() {
+        let area = DsaArea::create(c"pgrx_tests_dsa");
+        let first = area.allocate(Counter::default());
+        let second = area.allocate(Counter { count: AtomicU64::new(41), next: first });
+        assert_ne!(first, second);
+
+        area.get(area.get(second).next).count.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(area.get(first).count.load(Ordering::SeqCst), 1);
+        assert!(!area.get(first).next.is_valid());
+
+        unsafe {
+            area.free(second);
+            area.free(first);
+        }
+        area.trim();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "DsaPointer is invalid")]
+    fn test_dsa_invalid_pointer() {
+        let area = DsaArea::create(c"pgrx_tests_dsa");
+        area.get(DsaPointer::<u64>::invalid());
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "DSA can't allocate zero-sized types")]
+    fn test_dsa_allocate_zero_sized() {
+        let area = DsaArea::create(c"pgrx_tests_dsa");
+        area.allocate(());
+    }
+
+    #[cfg(feature = "pg17")]
+    #[pg_test]
+    fn test_named_segment() {
+        let first = pgrx::dsm::named_segment::<Counter>(c"pgrx_tests_named_segment");
+        first.count.fetch_add(1, Ordering::SeqCst);
+        let second = pgrx::dsm::named_segment::<Counter>(c"pgrx_tests_named_segment");
+        assert!(std::ptr::eq(first, second));
+        assert!(second.count.load(Ordering::SeqCst) >= 1);
+    }
+}
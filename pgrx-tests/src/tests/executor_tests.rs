@@ -0,0 +1,164 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::executor::{self, ExecuteError, Receiver};
+    use pgrx::prelude::*;
+    use pgrx::{PgTupleDesc, PgTupleSlot};
+    use std::num::NonZeroUsize;
+
+    fn get<T: FromDatum>(slot: &mut pg_sys::TupleTableSlot, attno: usize) -> Option<T> {
+        unsafe {
+            // virtual slots, like the executor's projections, already have all their values
+            if (slot.tts_nvalid as usize) < attno {
+                pg_sys::slot_getsomeattrs_int(slot, attno as _);
+            }
+            T::from_datum(*slot.tts_values.add(attno - 1), *slot.tts_isnull.add(attno - 1))
+        }
+    }
+
+    #[pg_test]
+    fn test_execute_select() -> Result<(), ExecuteError> {
+        let mut sum = 0;
+        let processed = executor::execute(
+            "SELECT x FROM generate_series(1, $1) x",
+            &[10.into()],
+            &mut |slot: &mut pg_sys::TupleTableSlot| {
+                sum += get::<i32>(slot, 1).unwrap();
+                true
+            },
+        )?;
+        assert_eq!(sum, 55);
+        assert_eq!(processed, 10);
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_execute_null_arg() -> Result<(), ExecuteError> {
+        let mut seen = Vec::new();
+        executor::execute(
+            "SELECT $1::text",
+            &[Option::<String>::None.into()],
+            &mut |slot: &mut pg_sys::TupleTableSlot| {
+                seen.push(get::<String>(slot, 1));
+                true
+            },
+        )?;
+        assert_eq!(seen, vec![None]);
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_execute_slots() -> Result<(), ExecuteError> {
+        let attno = NonZeroUsize::new(1).unwrap();
+        let mut seen = Vec::new();
+        let mut receiver = executor::slots(|slot: &mut PgTupleSlot| {
+            seen.push(slot.get::<String>(attno).unwrap());
+            seen.len() < 2
+        });
+        executor::execute("SELECT x::text FROM generate_series(1, 10) x", &[], &mut receiver)?;
+        assert_eq!(seen, vec![Some("1".to_string()), Some("2".to_string())]);
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_execute_stops_early() -> Result<(), ExecuteError> {
+        let mut rows = 0;
+        executor::execute(
+            "SELECT * FROM generate_series(1, 1000)",
+            &[],
+            &mut |_: &mut pg_sys::TupleTableSlot| {
+                rows += 1;
+                rows < 3
+            },
+        )?;
+        assert_eq!(rows, 3);
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_execute_dml() -> Result<(), ExecuteError> {
+        Spi::run("CREATE TABLE executor_dml (id int)").unwrap();
+        let mut returned = Vec::new();
+        let processed = executor::execute(
+            "INSERT INTO executor_dml SELECT generate_series(1, 5) RETURNING id",
+            &[],
+            &mut |slot: &mut pg_sys::TupleTableSlot| {
+                returned.push(get::<i32>(slot, 1).unwrap());
+                true
+            },
+        )?;
+        assert_eq!(processed, 5);
+        assert_eq!(returned, vec![1, 2, 3, 4, 5]);
+
+        // the insert is visible to what runs after it
+        let processed = executor::execute(
+            "DELETE FROM executor_dml WHERE id > 2",
+            &[],
+            &mut |_: &mut pg_sys::TupleTableSlot| true,
+        )?;
+        assert_eq!(processed, 3);
+        assert_eq!(Spi::get_one::<i64>("SELECT count(*) FROM executor_dml"), Ok(Some(2)));
+        Ok(())
+    }
+
+    struct Names {
+        names: Vec<String>,
+        rows: usize,
+        shutdown: bool,
+    }
+
+    impl Receiver for Names {
+        fn startup(&mut self, tupdesc: &PgTupleDesc<'_>) {
+            self.names = tupdesc.iter().map(|att| att.name().to_string()).collect();
+        }
+
+        fn receive(&mut self, _slot: &mut pg_sys::TupleTableSlot) -> bool {
+            self.rows += 1;
+            true
+        }
+
+        fn shutdown(&mut self) {
+            self.shutdown = true;
+        }
+    }
+
+    #[pg_test]
+    fn test_execute_receiver_callbacks() -> Result<(), ExecuteError> {
+        let mut names = Names { names: Vec::new(), rows: 0, shutdown: false };
+        executor::execute("SELECT 1 AS a, 'b' AS b UNION ALL SELECT 2, 'c'", &[], &mut names)?;
+        assert_eq!(names.names, vec!["a", "b"]);
+        assert_eq!(names.rows, 2);
+        assert!(names.shutdown);
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_execute_rejects() {
+        let mut receiver = |_: &mut pg_sys::TupleTableSlot| true;
+        assert_eq!(
+            executor::execute("SELECT 1; SELECT 2", &[], &mut receiver),
+            Err(ExecuteError::NotOneStatement(2))
+        );
+        assert_eq!(
+            executor::execute("", &[], &mut receiver),
+            Err(ExecuteError::NotOneStatement(0))
+        );
+        assert_eq!(
+            executor::execute("CREATE TABLE executor_nope (id int)", &[], &mut receiver),
+            Err(ExecuteError::UtilityStatement)
+        );
+    }
+}
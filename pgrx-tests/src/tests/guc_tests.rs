@@ -225,4 +225,148 @@ mod tests {
             assert_eq!(GUC_NO_SHOW.get(), true, "'no_show' should reset after 'RESET ALL'");
         });
     }
+
+    struct PowerOfTwo;
+
+    impl GucHooks<i32> for PowerOfTwo {
+        const CHECK: Option<GucCheckHook<i32>> = Some(|value, _| {
+            if value.count_ones() == 1 {
+                Ok(())
+            } else {
+                Err(GucCheckError::new().message(format!("{value} is not a power of two")))
+            }
+        });
+    }
+
+    #[pg_test]
+    fn test_guc_check_hook() {
+        static GUC: GucSetting<i32> = GucSetting::<i32>::new(16);
+        GucRegistry::define_int_guc_with_hooks::<PowerOfTwo>(
+            "test.power_of_two",
+            "test check hooks",
+            "test check hooks",
+            &GUC,
+            1,
+            1024,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+        Spi::run("SET test.power_of_two = 64").expect("SPI failed");
+        assert_eq!(GUC.get(), 64);
+    }
+
+    #[pg_test(error = "3 is not a power of two")]
+    fn test_guc_check_hook_rejects() {
+        static GUC: GucSetting<i32> = GucSetting::<i32>::new(16);
+        GucRegistry::define_int_guc_with_hooks::<PowerOfTwo>(
+            "test.power_of_two_rejects",
+            "test check hooks",
+            "test check hooks",
+            &GUC,
+            1,
+            1024,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+        Spi::run("SET test.power_of_two_rejects = 3").expect("SPI failed");
+    }
+
+    struct HttpUrl;
+
+    impl<'a> GucHooks<Option<&'a CStr>> for HttpUrl {
+        const CHECK: Option<GucCheckHook<Option<&'a CStr>>> = Some(|value, _| match value {
+            Some(url) if !url.to_bytes().starts_with(b"http://") => {
+                Err(GucCheckError::new().detail("the URL must start with http://"))
+            }
+            _ => Ok(()),
+        });
+    }
+
+    #[pg_test(error = "invalid value for parameter \"test.url\": \"ftp://example.com\"")]
+    fn test_string_guc_check_hook() {
+        static GUC: GucSetting<Option<&'static CStr>> =
+            GucSetting::<Option<&'static CStr>>::new(None);
+        GucRegistry::define_string_guc_with_hooks::<HttpUrl>(
+            "test.url",
+            "test string check hooks",
+            "test string check hooks",
+            &GUC,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+        Spi::run("SET test.url = 'http://example.com'").expect("SPI failed");
+        assert_eq!(GUC.get(), Some(c"http://example.com"));
+        Spi::run("SET test.url = 'ftp://example.com'").expect("SPI failed");
+    }
+
+    static ASSIGNED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+    struct AssignAndShow;
+
+    impl GucHooks<bool> for AssignAndShow {
+        const ASSIGN: Option<GucAssignHook<bool>> =
+            Some(|value| ASSIGNED.store(value, std::sync::atomic::Ordering::Relaxed));
+        const SHOW: Option<GucShowHook> = Some(|| "shown by a hook".to_string());
+    }
+
+    #[pg_test]
+    fn test_guc_assign_and_show_hooks() {
+        static GUC: GucSetting<bool> = GucSetting::<bool>::new(false);
+        GucRegistry::define_bool_guc_with_hooks::<AssignAndShow>(
+            "test.assign_and_show",
+            "test assign and show hooks",
+            "test assign and show hooks",
+            &GUC,
+            GucContext::Userset,
+            GucFlags::default(),
+        );
+        Spi::run("SET test.assign_and_show = true").expect("SPI failed");
+        assert!(ASSIGNED.load(std::sync::atomic::Ordering::Relaxed));
+        let shown = Spi::get_one::<String>("SELECT current_setting('test.assign_and_show')")
+            .expect("SPI failed");
+        assert_eq!(shown.as_deref(), Some("shown by a hook"));
+    }
+
+    #[pg_test]
+    fn test_get_core_guc() {
+        Spi::run("SET work_mem = '8MB'").expect("SPI failed");
+        assert_eq!(Guc::get::<i32>("work_mem"), Ok(8192));
+        assert_eq!(Guc::show("work_mem").as_deref(), Some("8MB"));
+
+        Spi::run("SET enable_seqscan = off").expect("SPI failed");
+        assert_eq!(Guc::get::<bool>("enable_seqscan"), Ok(false));
+
+        Spi::run("SET search_path = tests, public").expect("SPI failed");
+        assert_eq!(Guc::get::<String>("search_path"), Ok("tests, public".to_string()));
+    }
+
+    #[pg_test]
+    fn test_get_guc_errors() {
+        assert_eq!(
+            Guc::get::<String>("test.no_such_guc"),
+            Err(GucError::Unknown("test.no_such_guc".to_string()))
+        );
+        assert_eq!(Guc::show("test.no_such_guc"), None);
+        assert!(matches!(Guc::get::<i32>("search_path"), Err(GucError::WrongType { .. })));
+    }
+
+    #[pg_test]
+    fn test_set_local_guc() {
+        Spi::run("SET work_mem = '8MB'").expect("SPI failed");
+        {
+            let _outer = Guc::set_local("work_mem", "16MB");
+            assert_eq!(Guc::get::<i32>("work_mem"), Ok(16384));
+            {
+                let _inner = Guc::set_local("work_mem", 1024);
+                assert_eq!(Guc::get::<i32>("work_mem"), Ok(1024));
+            }
+            assert_eq!(Guc::get::<i32>("work_mem"), Ok(16384));
+        }
+        assert_eq!(Guc::get::<i32>("work_mem"), Ok(8192));
+    }
+
+    #[pg_test(error = "invalid value for parameter \"work_mem\": \"lots\"")]
+    fn test_set_local_invalid_guc() {
+        let _guard = Guc::set_local("work_mem", "lots");
+    }
 }
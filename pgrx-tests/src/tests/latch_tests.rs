@@ -0,0 +1,58 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::latch::{WaitEventSet, WaitEvents};
+    use pgrx::prelude::*;
+    use std::time::Duration;
+
+    #[pg_test]
+    fn test_wait_event_set_latch() {
+        let mut set = WaitEventSet::builder().latch().postmaster_death().build();
+        unsafe { pg_sys::SetLatch(pg_sys::MyLatch) };
+
+        let events = set.wait(Some(Duration::from_secs(10)));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].position, 0);
+        assert!(events[0].events.contains(WaitEvents::LATCH_SET));
+        assert_eq!(events[0].socket, None);
+
+        // and it was reset
+        assert!(set.wait(Some(Duration::from_millis(10))).is_empty());
+    }
+
+    #[pg_test]
+    fn test_wait_event_set_socket() {
+        let (reader, mut writer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let fd = std::os::fd::AsRawFd::as_raw_fd(&reader);
+        let mut set = WaitEventSet::builder()
+            .postmaster_death()
+            .socket(fd, WaitEvents::SOCKET_READABLE)
+            .build();
+        assert!(set.wait(Some(Duration::from_millis(10))).is_empty());
+
+        std::io::Write::write_all(&mut writer, b"x").unwrap();
+        let events = set.wait(Some(Duration::from_secs(10)));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].position, 1);
+        assert!(events[0].events.contains(WaitEvents::SOCKET_READABLE));
+        assert_eq!(events[0].socket, Some(fd));
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "only socket events")]
+    fn test_wait_event_set_rejects_non_socket_events() {
+        let _ = WaitEventSet::builder().socket(0, WaitEvents::LATCH_SET);
+    }
+}
@@ -0,0 +1,103 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::lock::{LockMode, LockTag};
+    use pgrx::prelude::*;
+
+    fn advisory_locks(key: i64) -> Result<Option<i64>, spi::Error> {
+        Spi::get_one_with_args(
+            "SELECT count(*) FROM pg_locks \
+             WHERE locktype = 'advisory' AND pid = pg_backend_pid() \
+               AND ((classid::bigint << 32) | objid::bigint) = $1 AND objsubid = 1",
+            &[key.into()],
+        )
+    }
+
+    #[pg_test]
+    fn test_advisory_lock_is_released_on_drop() -> Result<(), spi::Error> {
+        let lock = LockTag::Advisory(40).lock(LockMode::Exclusive);
+        assert_eq!(advisory_locks(40)?, Some(1));
+        assert!(LockTag::Advisory(40).is_held(LockMode::Exclusive));
+        assert!(!LockTag::Advisory(40).is_held(LockMode::Share));
+
+        drop(lock);
+        assert_eq!(advisory_locks(40)?, Some(0));
+        assert!(!LockTag::Advisory(40).is_held(LockMode::Exclusive));
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_advisory_lock_matches_sql() -> Result<(), spi::Error> {
+        Spi::run("SELECT pg_advisory_xact_lock(41)")?;
+        assert!(LockTag::Advisory(41).is_held(LockMode::Exclusive));
+
+        Spi::run("SELECT pg_advisory_xact_lock_shared(1, 2)")?;
+        assert!(LockTag::AdvisoryPair(1, 2).is_held(LockMode::Share));
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_held_lock_outlives_guard() -> Result<(), spi::Error> {
+        LockTag::Advisory(42).try_lock(LockMode::Share).unwrap().hold();
+        assert_eq!(advisory_locks(42)?, Some(1));
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_session_lock() -> Result<(), spi::Error> {
+        let lock = LockTag::Advisory(43).try_lock_session(LockMode::Exclusive).unwrap();
+        assert!(lock.is_session());
+        assert_eq!(advisory_locks(43)?, Some(1));
+        drop(lock);
+        assert_eq!(advisory_locks(43)?, Some(0));
+
+        LockTag::Advisory(43).lock_session(LockMode::Exclusive).hold();
+        assert_eq!(Spi::get_one::<bool>("SELECT pg_advisory_unlock(43)")?, Some(true));
+        Ok(())
+    }
+
+    #[pg_test(error = "only advisory locks can be held by the session")]
+    fn test_session_lock_on_relation() {
+        LockTag::Relation(pg_sys::RelationRelationId).lock_session(LockMode::AccessShare);
+    }
+
+    #[pg_test]
+    fn test_relation_lock() -> Result<(), spi::Error> {
+        Spi::run("CREATE TABLE tests.lock_test (id int)")?;
+        let relid =
+            Spi::get_one::<pg_sys::Oid>("SELECT 'tests.lock_test'::regclass::oid")?.unwrap();
+        let tag = LockTag::Relation(relid);
+        assert!(!tag.is_held(LockMode::Share));
+
+        let lock = tag.try_lock(LockMode::Share).unwrap();
+        assert!(tag.is_held(LockMode::Share));
+        drop(lock);
+        assert!(!tag.is_held(LockMode::Share));
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_object_lock() {
+        let tag = LockTag::Object {
+            class: pg_sys::NamespaceRelationId,
+            object: pg_sys::PG_CATALOG_NAMESPACE.into(),
+            subid: 0,
+        };
+        let lock = tag.lock(LockMode::AccessShare);
+        assert!(tag.is_held(LockMode::AccessShare));
+        drop(lock);
+        assert!(!tag.is_held(LockMode::AccessShare));
+    }
+}
@@ -13,6 +13,7 @@ mod anyelement_tests;
 mod anynumeric_tests;
 mod array_tests;
 mod attributes_tests;
+mod bench_tests;
 mod bgworker_tests;
 #[cfg(feature = "cshim")]
 mod bindings_of_inline_fn_tests;
@@ -24,7 +25,10 @@ mod composite_type_tests;
 mod datetime_tests;
 mod default_arg_value_tests;
 mod derive_pgtype_lifetimes;
+mod dshash_tests;
+mod dsm_tests;
 mod enum_type_tests;
+mod executor_tests;
 mod fcinfo_tests;
 mod fn_call_tests;
 mod from_into_datum_tests;
@@ -37,12 +41,15 @@ mod inet_tests;
 mod internal_tests;
 mod issue1134;
 mod json_tests;
+mod latch_tests;
 mod lifetime_tests;
 mod list_tests;
+mod lock_tests;
 mod log_tests;
 mod memcxt_tests;
 mod name_tests;
 mod numeric_tests;
+mod parallel_tests;
 mod pg_cast_tests;
 mod pg_extern_tests;
 mod pg_guard_tests;
@@ -50,6 +57,7 @@ mod pg_operator_tests;
 mod pg_try_tests;
 mod pgbox_tests;
 mod pgrx_module_qualification;
+mod pgstat_tests;
 mod postgres_type_tests;
 #[cfg(feature = "proptest")]
 mod proptests;
@@ -57,14 +65,19 @@ mod range_tests;
 mod rel_tests;
 mod result_tests;
 mod roundtrip_tests;
+mod scan_tests;
 mod schema_tests;
+mod shm_mq_tests;
 mod shmem_tests;
 mod spi_tests;
 mod srf_tests;
 mod struct_type_tests;
 mod trigger_tests;
+mod tuptable_tests;
 mod uuid_tests;
 mod variadic_tests;
+mod wait_event_tests;
+mod worker_pool_tests;
 mod xact_callback_tests;
 mod xid64_tests;
 mod zero_datum_edge_cases;
@@ -0,0 +1,167 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use pgrx::parallel::{worker_main, ParallelContext};
+use pgrx::prelude::*;
+use pgrx::PGRXSharedMemory;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+pub struct SumWork {
+    next: AtomicI64,
+    end: i64,
+}
+
+unsafe impl PGRXSharedMemory for SumWork {}
+
+impl SumWork {
+    fn sum(&self) -> i64 {
+        std::iter::from_fn(|| Some(self.next.fetch_add(1, Ordering::Relaxed)))
+            .take_while(|&n| n <= self.end)
+            .sum()
+    }
+}
+
+#[pg_extern(parallel_safe)]
+fn parallel_safe_sum(end: i64) -> i64 {
+    let work = SumWork { next: AtomicI64::new(1), end };
+    let mut total = 0;
+    let partial_sums: Vec<i64> = ParallelContext::new("pgrx_tests", "parallel_sum_worker", work)
+        .workers(2)
+        .run(|work| total += work.sum());
+    total + partial_sums.iter().sum::<i64>()
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn parallel_sum_worker(seg: *mut pg_sys::dsm_segment, toc: *mut pg_sys::shm_toc) {
+    unsafe { worker_main(seg, toc, |work: &SumWork, _| work.sum()) }
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn parallel_worker_number(seg: *mut pg_sys::dsm_segment, toc: *mut pg_sys::shm_toc) {
+    unsafe {
+        worker_main(seg, toc, |_: &(), number| {
+            // workers see the leader's settings
+            let setting = Spi::get_one::<String>("SELECT current_setting('application_name')");
+            (number, setting.unwrap().unwrap())
+        })
+    }
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn parallel_error_worker(
+    _seg: *mut pg_sys::dsm_segment,
+    _toc: *mut pg_sys::shm_toc,
+) {
+    error!("parallel worker failed on purpose");
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use super::SumWork;
+    use pgrx::parallel::ParallelContext;
+    use pgrx::prelude::*;
+    use pgrx::PGRXSharedMemory;
+    use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+    #[pg_test]
+    fn test_parallel_sum() {
+        let work = SumWork { next: AtomicI64::new(1), end: 10_000 };
+        let mut total = 0;
+        let partial_sums: Vec<i64> =
+            ParallelContext::new("pgrx_tests", "parallel_sum_worker", work)
+                .workers(2)
+                .run(|work| total += work.sum());
+        assert!(partial_sums.len() <= 2);
+        assert_eq!(total + partial_sums.iter().sum::<i64>(), 10_000 * 10_001 / 2);
+    }
+
+    #[pg_test]
+    fn test_parallel_workers_share_leader_state() {
+        Spi::run("SET LOCAL application_name = 'parallel leader'").unwrap();
+        let results: Vec<(usize, String)> =
+            ParallelContext::new("pgrx_tests", "parallel_worker_number", ()).workers(2).run(|_| ());
+        for (n, (number, setting)) in results.into_iter().enumerate() {
+            assert_eq!(number, n);
+            assert_eq!(setting, "parallel leader");
+        }
+    }
+
+    #[pg_test]
+    fn test_parallel_no_workers() {
+        let results: Vec<i64> = ParallelContext::new(
+            "pgrx_tests",
+            "parallel_sum_worker",
+            SumWork { next: AtomicI64::new(1), end: 0 },
+        )
+        .workers(0)
+        .run(|_| ());
+        assert!(results.is_empty());
+    }
+
+    #[pg_test]
+    fn test_parallel_worker_error() {
+        // a launched worker always fails, so `run()` only returns if none could be launched
+        let message = PgTryBuilder::new(|| {
+            let _: Vec<()> =
+                ParallelContext::new("pgrx_tests", "parallel_error_worker", ()).run(|_| ());
+            None
+        })
+        .catch_others(|e| match e {
+            pg_sys::panic::CaughtError::PostgresError(report) => Some(report.message().to_string()),
+            e => panic!("{e:?}"),
+        })
+        .execute();
+
+        match message {
+            Some(message) => assert_eq!(message, "parallel worker failed on purpose"),
+            None => {
+                warning!("skipping test_parallel_worker_error: no parallel worker was launched")
+            }
+        }
+    }
+
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+
+    struct DropFlag;
+
+    unsafe impl PGRXSharedMemory for DropFlag {}
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            DROPPED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[pg_test]
+    fn test_parallel_state_is_dropped() {
+        DROPPED.store(false, Ordering::SeqCst);
+        let _: Vec<()> = ParallelContext::new("pgrx_tests", "parallel_error_worker", DropFlag)
+            .workers(0)
+            .run(|_| assert!(!DROPPED.load(Ordering::SeqCst), "dropped while still shared"));
+        assert!(DROPPED.load(Ordering::SeqCst));
+    }
+
+    #[pg_test]
+    fn test_parallel_safe_function_in_parallel_query() {
+        #[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14", feature = "pg15"))]
+        Spi::run("SET LOCAL force_parallel_mode = on").unwrap();
+        #[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14", feature = "pg15")))]
+        Spi::run("SET LOCAL debug_parallel_query = on").unwrap();
+        // run in the query's worker, if it got one, the function can't launch workers itself
+        let sum = Spi::get_one::<i64>("SELECT parallel_safe_sum(100)").unwrap();
+        assert_eq!(sum, Some(5050));
+    }
+}
@@ -0,0 +1,136 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use pgrx::pgstat::PgStatKind;
+use pgrx::prelude::*;
+use pgrx::{pg_stat_counters, pg_stat_function};
+
+pg_stat_counters! {
+    pub struct TestCounters {
+        hits,
+        misses,
+    }
+}
+
+pub static TEST_STATS: PgStatKind<TestCounters, 16> = PgStatKind::new("pgrx_test_stats");
+
+pg_stat_function!(fn pgrx_test_stats for TEST_STATS);
+
+/// Counts hits in a transaction of its own, which adds them to the totals when it commits
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn pgstat_bgworker(arg: pg_sys::Datum) {
+    use pgrx::bgworkers::*;
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGTERM);
+    BackgroundWorker::connect_worker_to_spi(
+        Some(crate::framework::get_pg_dbname()),
+        Some(crate::framework::get_pg_user().as_str()),
+    );
+
+    let object = unsafe { i64::from_datum(arg, false) }.expect("invalid arg") as u64;
+    BackgroundWorker::transaction(|| {
+        TEST_STATS.update(object, |c| c.hits += 6);
+        // nothing is added to the totals until the transaction ends
+        assert_eq!(TEST_STATS.get(object), TestCounters::default());
+    });
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use super::{TestCounters, TEST_STATS};
+    use pgrx::bgworkers::BackgroundWorkerBuilder;
+    use pgrx::pgstat::{StatCounters, StatKey};
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_updates_are_pending_until_flushed() {
+        TEST_STATS.update(1, |c| c.hits += 1);
+        TEST_STATS.update(1, |c| c.hits += 1);
+        TEST_STATS.update(1, |c| c.misses += 1);
+        assert_eq!(TEST_STATS.get(1), TestCounters::default());
+
+        TEST_STATS.flush();
+        assert_eq!(TEST_STATS.get(1), TestCounters { hits: 2, misses: 1 });
+    }
+
+    #[pg_test]
+    fn test_flushes_accumulate() {
+        TEST_STATS.update(2, |c| c.hits += 3);
+        TEST_STATS.flush();
+        TEST_STATS.update(2, |c| c.hits += 4);
+        TEST_STATS.flush();
+        assert_eq!(TEST_STATS.get(2).hits, 7);
+    }
+
+    #[pg_test]
+    fn test_cluster_wide_key() {
+        let key = StatKey { database: pg_sys::InvalidOid, object: 3 };
+        TEST_STATS.update_key(key, |c| c.misses += 5);
+        TEST_STATS.flush();
+        assert_eq!(TEST_STATS.get_key(key).misses, 5);
+        assert_eq!(TEST_STATS.get(3), TestCounters::default());
+    }
+
+    #[pg_test]
+    fn test_stat_function() -> Result<(), spi::Error> {
+        TEST_STATS.update(4, |c| {
+            c.hits += 10;
+            c.misses += 20;
+        });
+        TEST_STATS.flush();
+
+        let hits = Spi::get_one::<i64>(
+            "SELECT value FROM pgrx_test_stats() \
+             WHERE database = (SELECT oid FROM pg_database WHERE datname = current_database()) \
+               AND object = 4 AND counter = 'hits'",
+        )?;
+        assert_eq!(hits, Some(10));
+        let counters =
+            Spi::get_one::<i64>("SELECT count(*) FROM pgrx_test_stats() WHERE object = 4")?;
+        assert_eq!(counters, Some(TestCounters::NAMES.len() as i64));
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_committed_updates_reach_the_totals() -> Result<(), spi::Error> {
+        // Required to avoid bgworker pool exhaustion errors, see `test_dynamic_worker_allocation_failure`
+        Spi::run("SELECT pg_advisory_xact_lock_shared(42)")?;
+        let worker = BackgroundWorkerBuilder::new("pgstat_bgworker")
+            .set_library("pgrx_tests")
+            .set_function("pgstat_bgworker")
+            .set_argument(5i64.into_datum())
+            .enable_spi_access()
+            .set_notify_pid(unsafe { pg_sys::MyProcPid })
+            .load_dynamic()
+            .expect("Failed to start worker");
+        worker.wait_for_shutdown().expect("aborted shutdown");
+
+        let hits = Spi::get_one::<i64>(
+            "SELECT value FROM pgrx_test_stats() \
+             WHERE database = (SELECT oid FROM pg_database WHERE datname = current_database()) \
+               AND object = 5 AND counter = 'hits'",
+        )?;
+        assert_eq!(hits, Some(6));
+        assert_eq!(TEST_STATS.get(5).hits, 6);
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_counter_values_roundtrip() {
+        let counters = TestCounters { hits: 1, misses: 2 };
+        assert_eq!(TestCounters::NAMES, &["hits", "misses"]);
+        assert_eq!(counters.values(), vec![1, 2]);
+        assert_eq!(TestCounters::from_values(&counters.values()), counters);
+        assert_eq!(TestCounters::from_values(&[1]), TestCounters { hits: 1, misses: 0 });
+    }
+}
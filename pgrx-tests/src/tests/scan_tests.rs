@@ -0,0 +1,203 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::prelude::*;
+    use pgrx::scan::{BTreeStrategy, ScanKey, ScanKeyError};
+    use pgrx::PgRelation;
+    use std::num::NonZeroUsize;
+
+    fn column(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    fn scan_dogs() -> (PgRelation, PgRelation) {
+        Spi::run(
+            "CREATE TABLE scan_dogs (name text, age int);
+             CREATE INDEX scan_dogs_age_idx ON scan_dogs (age);
+             INSERT INTO scan_dogs VALUES ('Brandy', 42), ('Nami', 3), ('Sam', 9), ('Rex', NULL);",
+        )
+        .unwrap();
+        (
+            PgRelation::open_with_name_and_share_lock("scan_dogs").unwrap(),
+            PgRelation::open_with_name_and_share_lock("scan_dogs_age_idx").unwrap(),
+        )
+    }
+
+    /// A snapshot that sees what this test wrote, registered so it outlives the scans
+    fn with_snapshot<R>(f: impl FnOnce(pg_sys::Snapshot) -> R) -> R {
+        unsafe {
+            let snapshot = pg_sys::RegisterSnapshot(pg_sys::GetLatestSnapshot());
+            let result = f(snapshot);
+            pg_sys::UnregisterSnapshot(snapshot);
+            result
+        }
+    }
+
+    #[pg_test]
+    fn test_seq_scan() {
+        let (table, _) = scan_dogs();
+        with_snapshot(|snapshot| {
+            let mut names = unsafe { table.seq_scan(snapshot) }
+                .map(|dog| dog.get_by_name::<String>("name").unwrap().unwrap())
+                .collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(names, vec!["Brandy", "Nami", "Rex", "Sam"]);
+        });
+    }
+
+    #[pg_test]
+    fn test_seq_scan_slots() {
+        let (table, _) = scan_dogs();
+        with_snapshot(|snapshot| {
+            let mut scan = unsafe { table.seq_scan(snapshot) };
+            let mut total = 0;
+            let mut nulls = 0;
+            while let Some(slot) = scan.next_slot() {
+                match slot.get::<i32>(column(2)).unwrap() {
+                    Some(age) => total += age,
+                    None => nulls += 1,
+                }
+            }
+            assert_eq!(total, 54);
+            assert_eq!(nulls, 1);
+            assert!(scan.next_slot().is_none());
+        });
+    }
+
+    #[pg_test]
+    fn test_index_scan() -> Result<(), ScanKeyError> {
+        let (table, index) = scan_dogs();
+        with_snapshot(|snapshot| {
+            let keys = [ScanKey::new(column(1), BTreeStrategy::GreaterEqual, 9)];
+            let forward = unsafe {
+                index.index_scan(
+                    &table,
+                    snapshot,
+                    &keys,
+                    pg_sys::ScanDirection::ForwardScanDirection,
+                )
+            }?
+            .map(|dog| dog.get_by_name::<String>("name").unwrap().unwrap())
+            .collect::<Vec<_>>();
+            assert_eq!(forward, vec!["Sam", "Brandy"]);
+
+            let backward = unsafe {
+                index.index_scan(
+                    &table,
+                    snapshot,
+                    &[],
+                    pg_sys::ScanDirection::BackwardScanDirection,
+                )
+            }?
+            .map(|dog| dog.get_by_name::<i32>("age").unwrap())
+            .collect::<Vec<_>>();
+            assert_eq!(backward, vec![None, Some(42), Some(9), Some(3)]);
+
+            // a bigint key goes through the btree's cross-type int4/int8 operators
+            let keys = [
+                ScanKey::new(column(1), BTreeStrategy::Greater, 3i64),
+                ScanKey::new(column(1), BTreeStrategy::Less, 42i64),
+            ];
+            let mut scan = unsafe {
+                index.index_scan(
+                    &table,
+                    snapshot,
+                    &keys,
+                    pg_sys::ScanDirection::ForwardScanDirection,
+                )
+            }?;
+            let slot = scan.next_slot().unwrap();
+            assert_eq!(slot.get_by_name::<String>("name").unwrap(), Some("Sam".to_string()));
+            assert!(scan.next_slot().is_none());
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_index_scan_null_key() -> Result<(), ScanKeyError> {
+        let (table, index) = scan_dogs();
+        with_snapshot(|snapshot| {
+            let keys = [ScanKey::new(column(1), BTreeStrategy::Equal, Option::<i32>::None)];
+            let mut scan = unsafe {
+                index.index_scan(
+                    &table,
+                    snapshot,
+                    &keys,
+                    pg_sys::ScanDirection::ForwardScanDirection,
+                )
+            }?;
+            assert!(scan.next().is_none());
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_index_scan_key_errors() {
+        let (table, index) = scan_dogs();
+        with_snapshot(|snapshot| {
+            let scan = |keys: &[ScanKey]| {
+                unsafe {
+                    index.index_scan(
+                        &table,
+                        snapshot,
+                        keys,
+                        pg_sys::ScanDirection::ForwardScanDirection,
+                    )
+                }
+                .err()
+            };
+            assert_eq!(
+                scan(&[ScanKey::new(column(2), BTreeStrategy::Equal, 9)]),
+                Some(ScanKeyError::NoSuchColumn(column(2)))
+            );
+            assert!(matches!(
+                scan(&[ScanKey::new(column(1), BTreeStrategy::Equal, "nine")]),
+                Some(ScanKeyError::NoOperator { .. })
+            ));
+        });
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "is not an index")]
+    fn test_index_scan_not_an_index() {
+        let (table, _) = scan_dogs();
+        with_snapshot(|snapshot| {
+            let _ = unsafe {
+                table.index_scan(&table, snapshot, &[], pg_sys::ScanDirection::ForwardScanDirection)
+            };
+        });
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "is not a btree index")]
+    fn test_index_scan_hash_index() {
+        let (table, _) = scan_dogs();
+        // a hash index finds rows by hash code, so its matches have to be rechecked
+        Spi::run("CREATE INDEX scan_dogs_name_idx ON scan_dogs USING hash (name)").unwrap();
+        let index = PgRelation::open_with_name_and_share_lock("scan_dogs_name_idx").unwrap();
+        with_snapshot(|snapshot| {
+            let keys = [ScanKey::new(column(1), BTreeStrategy::Equal, "Sam")];
+            let _ = unsafe {
+                index.index_scan(
+                    &table,
+                    snapshot,
+                    &keys,
+                    pg_sys::ScanDirection::ForwardScanDirection,
+                )
+            };
+        });
+    }
+}
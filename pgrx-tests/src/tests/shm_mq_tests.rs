@@ -0,0 +1,118 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use pgrx::prelude::*;
+
+#[pg_guard]
+#[no_mangle]
+/// Background worker that sends back the length of every string it receives on queue 0, on queue 1
+pub extern "C" fn shm_mq_echo_worker(arg: pg_sys::Datum) {
+    use pgrx::bgworkers::*;
+    use pgrx::shm_mq::ShmMqSegment;
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
+
+    let handle = unsafe { u32::from_datum(arg, false) }.expect("invalid arg");
+    let channel = ShmMqSegment::attach(handle).expect("message queue segment is gone");
+    let mut replies = channel.sender::<usize>(1);
+    for message in channel.receiver::<String>(0) {
+        if replies.send(&message.len()).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::bgworkers::*;
+    use pgrx::prelude::*;
+    use pgrx::shm_mq::{ShmMqError, ShmMqSegment};
+    use pgrx::IntoDatum;
+
+    #[pg_test]
+    fn test_shm_mq_loopback() {
+        let channel = ShmMqSegment::create(1, 1024);
+        let mut sender = channel.sender::<Vec<i32>>(0);
+        let mut receiver = channel.receiver::<Vec<i32>>(0);
+
+        assert_eq!(receiver.try_recv(), Err(ShmMqError::WouldBlock));
+        sender.send(&vec![1, 2, 3]).unwrap();
+        sender.try_send(&vec![]).unwrap();
+        assert_eq!(receiver.recv(), Ok(vec![1, 2, 3]));
+        assert_eq!(receiver.try_recv(), Ok(vec![]));
+
+        drop(sender);
+        assert_eq!(receiver.recv(), Err(ShmMqError::Detached));
+    }
+
+    #[pg_test]
+    fn test_shm_mq_try_send_keeps_message_when_full() {
+        let channel = ShmMqSegment::create(1, 1024);
+        let mut sender = channel.sender::<String>(0);
+        let mut receiver = channel.receiver::<String>(0);
+
+        // this is too big for the queue, so it only goes partway
+        let big = "x".repeat(4096);
+        assert_eq!(sender.try_send(&big), Err(ShmMqError::WouldBlock));
+        assert_eq!(sender.try_send(&"small".to_string()), Err(ShmMqError::WouldBlock));
+        assert_eq!(sender.pending(), 2);
+
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            let _ = sender.try_flush();
+            match receiver.try_recv() {
+                Ok(message) => received.push(message),
+                Err(ShmMqError::WouldBlock) => {}
+                Err(e) => panic!("{e}"),
+            }
+        }
+        assert_eq!(received, [big, "small".to_string()]);
+        assert_eq!(sender.pending(), 0);
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "queue 1 is out of range")]
+    fn test_shm_mq_queue_out_of_range() {
+        let channel = ShmMqSegment::create(1, 1024);
+        channel.sender::<i32>(1);
+    }
+
+    #[pg_test]
+    fn test_shm_mq_bgworker() {
+        // Required to avoid bgworker pool exhaustion errors, see `test_dynamic_worker_allocation_failure`
+        Spi::run("SELECT pg_advisory_xact_lock_shared(42)").unwrap();
+        let channel = ShmMqSegment::create(2, 1024);
+        let worker = BackgroundWorkerBuilder::new("shm_mq_echo_worker")
+            .set_library("pgrx_tests")
+            .set_function("shm_mq_echo_worker")
+            .set_argument(channel.handle().into_datum())
+            .enable_shmem_access(None)
+            .set_notify_pid(unsafe { pg_sys::MyProcPid })
+            .load_dynamic()
+            .expect("Failed to start worker");
+
+        let mut requests = channel.sender::<String>(0);
+        let mut replies = channel.receiver::<usize>(1);
+        requests.watch_worker(&worker);
+        replies.watch_worker(&worker);
+
+        for message in ["a", "bb", &"c".repeat(5000)] {
+            requests.send(&message.to_string()).unwrap();
+            assert_eq!(replies.recv(), Ok(message.len()));
+        }
+
+        drop(requests);
+        assert_eq!(replies.recv(), Err(ShmMqError::Detached));
+        drop(replies);
+        worker.wait_for_shutdown().expect("aborted shutdown");
+    }
+}
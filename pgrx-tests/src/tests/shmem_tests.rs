@@ -8,17 +8,23 @@
 //LICENSE
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
 use pgrx::prelude::*;
-use pgrx::{pg_shmem_init, PgAtomic, PgLwLock, PgSharedMemoryInitialization};
+use pgrx::{pg_shmem_init, PgAtomic, PgConditionVariable, PgLwLock, PgSharedMemoryInitialization};
 use std::sync::atomic::AtomicBool;
 
 static ATOMIC: PgAtomic<AtomicBool> = PgAtomic::new();
 static LWLOCK: PgLwLock<bool> = PgLwLock::new();
+static NAMED_LWLOCK: PgLwLock<u64> = PgLwLock::with_name("pgrx_tests_named_lock");
+static CONDVAR: PgConditionVariable = PgConditionVariable::new();
 
 #[pg_guard]
 pub extern "C" fn _PG_init() {
     // This ensures that this functionality works across PostgreSQL versions
     pg_shmem_init!(ATOMIC);
     pg_shmem_init!(LWLOCK);
+    pg_shmem_init!(NAMED_LWLOCK);
+    pg_shmem_init!(CONDVAR);
+    pg_shmem_init!(crate::tests::worker_pool_tests::POOL);
+    pg_shmem_init!(crate::tests::pgstat_tests::TEST_STATS);
 }
 #[cfg(any(test, feature = "pg_test"))]
 #[pgrx::pg_schema]
@@ -26,8 +32,9 @@ mod tests {
     #[allow(unused_imports)]
     use crate as pgrx_tests;
 
-    use super::LWLOCK;
+    use super::{ATOMIC, CONDVAR, LWLOCK, NAMED_LWLOCK};
     use pgrx::prelude::*;
+    use std::sync::atomic::Ordering;
 
     #[pg_test]
     #[should_panic(expected = "cache lookup failed for type 0")]
@@ -53,4 +60,33 @@ mod tests {
         });
         let _lock = LWLOCK.exclusive();
     }
+
+    #[pg_test]
+    pub fn test_named_lock() {
+        assert_eq!(NAMED_LWLOCK.get_name(), "pgrx_tests_named_lock");
+        *NAMED_LWLOCK.exclusive() += 1;
+        assert!(*NAMED_LWLOCK.share() > 0);
+    }
+
+    #[pg_test]
+    pub fn test_condition_variable_returns_when_condition_holds() {
+        let mut checks = 0;
+        CONDVAR.signal();
+        CONDVAR.broadcast();
+        CONDVAR.wait_until(|| {
+            checks += 1;
+            true
+        });
+        assert_eq!(checks, 1);
+    }
+
+    #[cfg(not(feature = "pg12"))]
+    #[pg_test]
+    pub fn test_condition_variable_timeout() {
+        ATOMIC.get().store(false, Ordering::SeqCst);
+        let became_true = CONDVAR.wait_until_timeout(std::time::Duration::from_millis(50), || {
+            ATOMIC.get().load(Ordering::SeqCst)
+        });
+        assert!(!became_true);
+    }
 }
@@ -17,7 +17,7 @@ mod tests {
     use std::error::Error;
 
     use pgrx::prelude::*;
-    use pgrx::spi::{self, Query};
+    use pgrx::spi::{self, NamedQuery, Query};
 
     #[pg_test(error = "syntax error at or near \"THIS\"")]
     fn test_spi_failure() -> Result<(), spi::Error> {
@@ -553,4 +553,254 @@ mod tests {
         assert_eq!(Some("hello".to_string()), value);
         Ok(())
     }
+
+    #[derive(FromSpiRow, Debug, PartialEq)]
+    struct Dog {
+        name: String,
+        #[spi(name = "age_in_years")]
+        age: Option<i32>,
+    }
+
+    #[derive(FromSpiRow, Debug, PartialEq)]
+    struct Pair(i64, #[spi(position = 3)] Option<String>);
+
+    #[pg_test]
+    fn test_select_as() -> Result<(), spi::Error> {
+        let dogs = Spi::connect(|client| {
+            client
+                .select_as::<Dog, _>(
+                    "SELECT * FROM (VALUES (3, 'Brandy'), (NULL, 'Nami')) AS dogs (age_in_years, name) ORDER BY name",
+                    &[],
+                )?
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        assert_eq!(
+            dogs,
+            vec![
+                Dog { name: "Brandy".into(), age: Some(3) },
+                Dog { name: "Nami".into(), age: None }
+            ]
+        );
+
+        let pairs = Spi::connect(|client| {
+            client
+                .select(
+                    "SELECT x::bigint, 'skipped', x::text FROM generate_series(1, 3) x",
+                    None,
+                    &[],
+                )?
+                .rows_as::<Pair>()?
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        assert_eq!(pairs, (1..=3).map(|x| Pair(x, Some(x.to_string()))).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_select_as_mismatches() {
+        let missing = Spi::connect(|client| {
+            client.select_as::<Dog, _>("SELECT 'Nami' AS name", &[]).map(|_| ())
+        });
+        assert_eq!(missing, Err(spi::Error::NoSuchColumn("column \"age_in_years\"".into())));
+
+        let wrong_type = Spi::connect(|client| {
+            client
+                .select_as::<Dog, _>("SELECT 'Nami' AS name, 'old' AS age_in_years", &[])
+                .map(|_| ())
+        });
+        assert!(matches!(
+            wrong_type,
+            Err(spi::Error::ColumnTypeMismatch { column, .. }) if column == "age_in_years"
+        ));
+
+        let null = Spi::connect(|client| {
+            client
+                .select_as::<Dog, _>("SELECT NULL::text AS name, 1 AS age_in_years", &[])?
+                .collect::<Result<Vec<_>, _>>()
+        });
+        assert_eq!(null, Err(spi::Error::UnexpectedNull("name".into())));
+    }
+
+    #[pg_test]
+    fn test_named_query() -> Result<(), spi::Error> {
+        let query = NamedQuery::new("SELECT :a::text || ':a' || :b || :a, '--' || :b");
+        assert_eq!(query.sql(), "SELECT $1::text || ':a' || $2 || $1, '--' || $2");
+        assert_eq!(query.names(), ["a", "b"]);
+
+        for _ in 0..2 {
+            let (joined, b) = Spi::connect(|client| {
+                NamedQuery::new("SELECT :a::text || ':a' || :b || :a, '--' || :b")
+                    .bind("a", "x")
+                    .bind("b", 42)
+                    .select(&client, None)?
+                    .first()
+                    .get_two::<String, String>()
+            })?;
+            assert_eq!(joined.as_deref(), Some("x:a42x"));
+            assert_eq!(b.as_deref(), Some("--42"));
+        }
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_named_query_array_slices() -> Result<(), spi::Error> {
+        let sql = "SELECT (ARRAY[:a, 20, 30])[lo:hi], (ARRAY[10, 20, 30])[:i] \
+                   FROM (SELECT 2 AS lo, 3 AS hi) bounds";
+        let query = NamedQuery::new(sql);
+        assert_eq!(
+            query.sql(),
+            "SELECT (ARRAY[$1, 20, 30])[lo:hi], (ARRAY[10, 20, 30])[$2] \
+             FROM (SELECT 2 AS lo, 3 AS hi) bounds"
+        );
+        assert_eq!(query.names(), ["a", "i"]);
+
+        let (slice, element) = Spi::connect(|client| {
+            query
+                .bind("a", 10)
+                .bind("i", 1)
+                .select(&client, None)?
+                .first()
+                .get_two::<Vec<i32>, i32>()
+        })?;
+        assert_eq!(slice, Some(vec![20, 30]));
+        assert_eq!(element, Some(10));
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_named_query_update() -> Result<(), spi::Error> {
+        Spi::run("CREATE TABLE named_query_test (id bigint, name text)")?;
+        for (id, name) in [(1_i64, Some("one")), (2, None)] {
+            NamedQuery::new("INSERT INTO named_query_test VALUES (:id, :name)")
+                .bind("id", id)
+                .bind("name", name)
+                .run()?;
+        }
+        let count = Spi::connect(|mut client| {
+            NamedQuery::new(
+                "UPDATE named_query_test SET name = :name WHERE name IS NULL RETURNING id",
+            )
+            .bind("name", "two")
+            .update(&mut client, None)
+            .map(|table| table.len())
+        })?;
+        assert_eq!(count, 1);
+        assert_eq!(
+            Spi::get_one::<String>(
+                "SELECT string_agg(name, ',' ORDER BY id) FROM named_query_test"
+            ),
+            Ok(Some("one,two".into()))
+        );
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_named_query_binding_errors() {
+        let unbound = NamedQuery::new("SELECT :a, :b").bind("a", 1).run();
+        assert_eq!(unbound, Err(spi::Error::UnboundParameter("b".into())));
+        let unknown = NamedQuery::new("SELECT :a").bind("a", 1).bind("c", 2).run();
+        assert_eq!(unknown, Err(spi::Error::UnknownParameter("c".into())));
+    }
+
+    #[pg_test]
+    fn test_cached_plan() -> Result<(), spi::Error> {
+        let before = Spi::plan_cache_stats();
+        let add = |x: i32| -> Result<Option<i32>, spi::Error> {
+            let plan = Spi::cached_plan(
+                "test_cached_plan",
+                "SELECT $1 + 1",
+                &[PgBuiltInOids::INT4OID.oid()],
+            )?;
+            Spi::connect(|client| client.select(&*plan, None, &[x.into()])?.first().get_one())
+        };
+        assert_eq!(add(1)?, Some(2));
+        assert_eq!(add(2)?, Some(3));
+
+        let after = Spi::plan_cache_stats();
+        assert_eq!(after.misses - before.misses, 1);
+        assert_eq!(after.hits - before.hits, 1);
+
+        // the same key with another query replaces the plan
+        let plan =
+            Spi::cached_plan("test_cached_plan", "SELECT $1 * 2", &[PgBuiltInOids::INT4OID.oid()])?;
+        let doubled = Spi::connect(|client| {
+            client.select(&*plan, None, &[4_i32.into()])?.first().get_one::<i32>()
+        })?;
+        assert_eq!(doubled, Some(8));
+        assert_eq!(Spi::plan_cache_stats().misses - after.misses, 1);
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_cached_plan_is_replanned() -> Result<(), spi::Error> {
+        Spi::run("CREATE TABLE cached_plan_test AS SELECT 1 AS a")?;
+        let columns = || -> Result<usize, spi::Error> {
+            let plan = Spi::cached_plan(
+                "test_cached_plan_is_replanned",
+                "SELECT * FROM cached_plan_test",
+                &[],
+            )?;
+            Spi::connect(|client| client.select(&*plan, None, &[])?.columns())
+        };
+        assert_eq!(columns()?, 1);
+        Spi::run("ALTER TABLE cached_plan_test ADD COLUMN b int")?;
+        assert_eq!(columns()?, 2);
+
+        Spi::clear_plan_cache();
+        assert_eq!(Spi::plan_cache_stats().plans, 0);
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_stream() -> Result<(), spi::Error> {
+        for (rows, batch_size) in [(0, 10), (9, 10), (10, 10), (25, 10), (25, 1)] {
+            let (count, sum) = Spi::connect(|client| {
+                let mut stream = client.stream(
+                    "SELECT * FROM generate_series(1, $1)",
+                    &[rows.into()],
+                    batch_size,
+                )?;
+                let (mut count, mut sum) = (0, 0);
+                while let Some(row) = stream.next_row()? {
+                    count += 1;
+                    sum += row.get::<i32>(1)?.unwrap();
+                }
+                // the stream stays finished
+                assert!(stream.next_row()?.is_none());
+                Ok::<_, spi::Error>((count, sum))
+            })?;
+            assert_eq!(count, rows);
+            assert_eq!(sum, rows * (rows + 1) / 2);
+        }
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_stream_rows_as() -> Result<(), spi::Error> {
+        let dogs = Spi::connect(|client| {
+            client
+                .stream(
+                    "SELECT 'dog' || x AS name, x AS age_in_years FROM generate_series(1, 5) x",
+                    &[],
+                    2,
+                )?
+                .rows_as::<Dog>()
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        assert_eq!(dogs.len(), 5);
+        assert_eq!(dogs[4], Dog { name: "dog5".into(), age: Some(5) });
+
+        let wrong = Spi::connect(|client| {
+            client.stream("SELECT 'x' AS name", &[], 2)?.rows_as::<Dog>().next().transpose()
+        });
+        assert!(matches!(wrong, Err(spi::Error::NoSuchColumn(_))));
+
+        // the error ends the rows, rather than being returned forever
+        let errors = Spi::connect(|client| {
+            let rows = client.stream("SELECT 'x' AS name", &[], 2)?.rows_as::<Dog>();
+            Ok::<_, spi::Error>(rows.map(|dog| dog.unwrap_err()).collect::<Vec<_>>())
+        })?;
+        assert!(matches!(errors[..], [spi::Error::NoSuchColumn(_)]));
+        Ok(())
+    }
 }
@@ -142,6 +142,31 @@ fn one_col_result_option(
     Ok(TableIterator::once((42,)))
 }
 
+#[pg_extern]
+fn materialized_series(start: i32, end: i32) -> MaterializedSetOf<'static, i32> {
+    MaterializedSetOf::new(move |rows| rows.extend(start..=end))
+}
+
+#[pg_extern]
+fn materialized_split_table<'a>(
+    input: &'a str,
+    pattern: &'a str,
+) -> MaterializedTable<'a, (name!(i, i32), name!(s, Option<&'a str>))> {
+    MaterializedTable::new(move |rows| {
+        for (i, s) in input.split_terminator(pattern).enumerate() {
+            rows.push((i as i32, Some(s).filter(|s| !s.is_empty())));
+        }
+    })
+}
+
+#[pg_extern]
+fn materialized_one_col() -> MaterializedTable<'static, (name!(a, String),)> {
+    MaterializedTable::new(|rows| {
+        rows.push(("forty-two".to_string(),));
+        assert_eq!(rows.len(), 1);
+    })
+}
+
 #[cfg(any(test, feature = "pg_test"))]
 #[pgrx::pg_schema]
 mod tests {
@@ -344,4 +369,61 @@ mod tests {
         assert_eq!(Spi::get_one::<i32>("SELECT * from one_col_result()"), Ok(Some(42)));
         assert_eq!(Spi::get_one::<i32>("SELECT * from one_col_result_option()"), Ok(Some(42)));
     }
+
+    #[pg_test]
+    fn test_materialized_setof() {
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT sum(x) FROM materialized_series(1, 10) x"),
+            Ok(Some(55))
+        );
+        // in the target list, materialize mode goes through ProjectSet
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM (SELECT materialized_series(1, 10)) x"),
+            Ok(Some(10))
+        );
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT count(*) FROM materialized_series(1, 0)"),
+            Ok(Some(0))
+        );
+    }
+
+    #[pg_test]
+    fn test_materialized_setof_larger_than_work_mem() {
+        Spi::run("SET LOCAL work_mem = '64kB'").unwrap();
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT sum(x) FROM materialized_series(1, 100000) x"),
+            Ok(Some(5000050000))
+        );
+    }
+
+    #[pg_test(error = "temporary file size exceeds temp_file_limit (0kB)")]
+    fn test_materialized_setof_spills_past_work_mem() {
+        // with no room for temporary files, writing the rows past `work_mem` to disk fails
+        Spi::run("SET LOCAL work_mem = '64kB'").unwrap();
+        Spi::run("SET LOCAL temp_file_limit = 0").unwrap();
+        Spi::run("SELECT sum(x) FROM materialized_series(1, 100000) x").unwrap();
+    }
+
+    #[pg_test]
+    fn test_materialized_table() {
+        let rows = Spi::connect(|client| {
+            client
+                .select("SELECT i, s FROM materialized_split_table('a,,c', ',')", None, &[])?
+                .map(|row| Ok((row["i"].value::<i32>()?, row["s"].value::<String>()?)))
+                .collect::<Result<Vec<_>, spi::Error>>()
+        })
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![(Some(0), Some("a".into())), (Some(1), None), (Some(2), Some("c".into()))]
+        );
+    }
+
+    #[pg_test]
+    fn test_materialized_one_col_table() {
+        assert_eq!(
+            Spi::get_one::<String>("SELECT a FROM materialized_one_col()"),
+            Ok(Some("forty-two".into()))
+        );
+    }
 }
@@ -0,0 +1,112 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::datum::TryFromDatumError;
+    use pgrx::prelude::*;
+    use pgrx::{PgTupleDesc, PgTupleSlot};
+    use std::num::NonZeroUsize;
+
+    fn attno(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    fn slot_dog() -> PgTupleDesc<'static> {
+        Spi::run("CREATE TYPE slot_dog AS (name text, age int)").unwrap();
+        PgTupleDesc::for_composite_type("slot_dog").unwrap()
+    }
+
+    #[pg_test]
+    fn test_virtual_slot() -> Result<(), TryFromDatumError> {
+        let tupdesc = slot_dog();
+        let mut slot = PgTupleSlot::new_virtual(&tupdesc);
+        assert!(slot.is_virtual());
+        assert!(!slot.is_empty());
+        assert!(slot.is_null(attno(1))?);
+        assert_eq!(slot.get::<String>(attno(1))?, None);
+
+        slot.set_by_name("name", "Brandy")?;
+        slot.set(attno(2), 42)?;
+        assert!(!slot.is_null(attno(1))?);
+        assert_eq!(slot.get_by_name::<String>("name")?, Some("Brandy".to_string()));
+        assert_eq!(slot.get::<i32>(attno(2))?, Some(42));
+
+        slot.set(attno(2), Option::<i32>::None)?;
+        assert_eq!(slot.get_by_name::<i32>("age")?, None);
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_slot_errors() {
+        let tupdesc = slot_dog();
+        let mut slot = PgTupleSlot::new_virtual(&tupdesc);
+        assert_eq!(
+            slot.get::<i32>(attno(3)),
+            Err(TryFromDatumError::NoSuchAttributeNumber(attno(3)))
+        );
+        assert_eq!(
+            slot.get_by_name::<i32>("owner"),
+            Err(TryFromDatumError::NoSuchAttributeName("owner".to_string()))
+        );
+        assert!(matches!(
+            slot.get::<i32>(attno(1)),
+            Err(TryFromDatumError::IncompatibleTypes { .. })
+        ));
+        assert!(matches!(
+            slot.set(attno(2), "forty-two"),
+            Err(TryFromDatumError::IncompatibleTypes { .. })
+        ));
+    }
+
+    #[pg_test]
+    fn test_slot_to_heap_tuple() -> Result<(), TryFromDatumError> {
+        let tupdesc = slot_dog();
+        let mut slot = PgTupleSlot::new_virtual(&tupdesc);
+        slot.set_by_name("name", "Nami")?;
+        slot.set_by_name("age", 3)?;
+        slot.materialize();
+
+        let tuple = slot.to_heap_tuple();
+        drop(slot);
+        assert_eq!(tuple.get_by_name::<String>("name")?, Some("Nami".to_string()));
+        assert_eq!(tuple.get_by_name::<i32>("age")?, Some(3));
+        Ok(())
+    }
+
+    #[pg_test]
+    fn test_slot_from_heap_tuple() -> Result<(), Box<dyn std::error::Error>> {
+        slot_dog();
+        let mut tuple = PgHeapTuple::new_composite_type("slot_dog")?;
+        tuple.set_by_name("name", "Brandy")?;
+        tuple.set_by_name("age", 42)?;
+
+        let mut slot = PgTupleSlot::from_heap_tuple(&tuple);
+        assert!(!slot.is_virtual());
+        assert_eq!(slot.get::<String>(attno(1))?, Some("Brandy".to_string()));
+        assert_eq!(slot.get_by_name::<i32>("age")?, Some(42));
+        slot.materialize();
+        assert_eq!(slot.get_by_name::<i32>("age")?, Some(42));
+        Ok(())
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "only a virtual slot's values can be set")]
+    fn test_slot_set_not_virtual() {
+        slot_dog();
+        let tuple = PgHeapTuple::new_composite_type("slot_dog").unwrap();
+        let mut slot = PgTupleSlot::from_heap_tuple(&tuple);
+        let _ = slot.set(attno(2), 42);
+    }
+}
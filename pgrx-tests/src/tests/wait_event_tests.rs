@@ -0,0 +1,67 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use pgrx::prelude::*;
+    use pgrx::wait_event::{CustomWaitEvent, WaitEventGuard};
+    use std::ffi::CStr;
+
+    static PGRX_TEST_WAIT: CustomWaitEvent = CustomWaitEvent::new(c"PgrxTestWait");
+
+    fn my_wait_event() -> Option<(String, String)> {
+        unsafe {
+            let info = (*pg_sys::MyProc).wait_event_info;
+            let name = |s: *const std::ffi::c_char| CStr::from_ptr(s).to_str().unwrap().to_string();
+            (info != 0).then(|| {
+                (
+                    name(pg_sys::pgstat_get_wait_event_type(info)),
+                    name(pg_sys::pgstat_get_wait_event(info)),
+                )
+            })
+        }
+    }
+
+    #[pg_test]
+    fn test_custom_wait_event_is_reported() {
+        let waiting = PGRX_TEST_WAIT.report();
+        let (wait_event_type, wait_event) = my_wait_event().unwrap();
+        assert_eq!(wait_event_type, "Extension");
+        if cfg!(feature = "pg17") {
+            assert_eq!(wait_event, "PgrxTestWait");
+        } else {
+            assert_eq!(wait_event, "Extension");
+        }
+
+        drop(waiting);
+        assert_eq!(my_wait_event(), None);
+    }
+
+    #[pg_test]
+    fn test_custom_wait_event_info_is_stable() {
+        assert_eq!(PGRX_TEST_WAIT.info(), PGRX_TEST_WAIT.info());
+        assert_eq!(PGRX_TEST_WAIT.info() & 0xFF00_0000, pg_sys::PG_WAIT_EXTENSION);
+        assert_eq!(PGRX_TEST_WAIT.name(), c"PgrxTestWait");
+    }
+
+    #[pg_test]
+    fn test_wait_event_guards_nest() {
+        let outer = WaitEventGuard::start(pg_sys::PG_WAIT_EXTENSION);
+        {
+            let _inner = PGRX_TEST_WAIT.report();
+        }
+        assert_eq!(my_wait_event(), Some(("Extension".into(), "Extension".into())));
+        drop(outer);
+        assert_eq!(my_wait_event(), None);
+    }
+}
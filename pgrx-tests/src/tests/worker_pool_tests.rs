@@ -0,0 +1,93 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+use pgrx::bgworkers::pool::{Backoff, WorkerPool};
+use pgrx::bgworkers::BackgroundWorker;
+use pgrx::prelude::*;
+use pgrx::worker_pool_status_function;
+use std::time::Duration;
+
+pub static POOL: WorkerPool<2> = WorkerPool::new();
+
+worker_pool_status_function!(pub fn pool_status for POOL);
+
+#[pg_bgworker]
+pub fn pool_worker() {
+    while BackgroundWorker::wait_latch(None) {}
+}
+
+#[pg_bgworker]
+pub fn pool_supervisor() {
+    let backoff = Backoff {
+        initial: Duration::from_millis(10),
+        max: Duration::from_millis(100),
+        reset_after: Duration::from_secs(60),
+    };
+    POOL.supervise(backoff, |_| pool_worker::builder());
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    #[allow(unused_imports)]
+    use crate as pgrx_tests;
+
+    use super::POOL;
+    use pgrx::bgworkers::pool::{WorkerState, WorkerStatus};
+    use pgrx::prelude::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_for(what: &str, condition: impl Fn(&[WorkerStatus]) -> bool) -> Vec<WorkerStatus> {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        loop {
+            let status = POOL.status();
+            if condition(&status) {
+                return status;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for {what}: {status:?}");
+            std::thread::sleep(Duration::from_millis(10));
+            pg_sys::check_for_interrupts!();
+        }
+    }
+
+    #[pg_test]
+    fn test_worker_pool_restarts_workers() {
+        // Required to avoid bgworker pool exhaustion errors, see `test_dynamic_worker_allocation_failure`
+        Spi::run("SELECT pg_advisory_xact_lock_shared(42)").unwrap();
+        let supervisor = super::pool_supervisor::launch().expect("Failed to start supervisor");
+
+        let running = |status: &[WorkerStatus]| {
+            status.iter().all(|w| w.state == WorkerState::Running && w.pid.is_some())
+        };
+        let status = wait_for("workers to start", running);
+        assert_eq!(status.len(), 2);
+        assert!(status.iter().all(|w| w.restarts == 0 && w.started_at.is_some()));
+        let running_in_sql = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pool_status() \
+             WHERE state = 'running' AND pid IS NOT NULL AND restarts = 0 AND restart_at IS NULL",
+        );
+        assert_eq!(running_in_sql, Ok(Some(2)));
+
+        let first_pid = status[0].pid;
+        POOL.terminate(0);
+        let status = wait_for("worker 0 to restart", |s| running(s) && s[0].pid != first_pid);
+        assert_eq!(status[0].restarts, 1);
+        assert_eq!(status[1].restarts, 0);
+
+        supervisor.terminate().wait_for_shutdown().expect("aborted shutdown");
+        let status = POOL.status();
+        assert!(status.iter().all(|w| w.state == WorkerState::Stopped && w.pid.is_none()));
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "out of range")]
+    fn test_worker_pool_terminate_out_of_range() {
+        POOL.terminate(2);
+    }
+}
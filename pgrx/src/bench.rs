@@ -0,0 +1,157 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! The in-backend half of `#[pg_bench]`
+//!
+//! `#[pg_bench]` functions are wrapped in a `#[pg_extern]` that calls [`run`] or [`run_sql`], which
+//! execute the benchmark repeatedly inside the backend and return a [`BenchStats`] as `jsonb`.
+//! `cargo pgrx bench` then collects those results on the client side.
+use crate::datum::JsonB;
+use crate::memcxt::PgMemoryContexts;
+use crate::pg_sys;
+use crate::spi::Spi;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Measurements from running a single benchmark
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BenchStats {
+    /// How many times the benchmark was run
+    pub iterations: u32,
+    /// Wall time of the fastest iteration, in nanoseconds
+    pub min_ns: u64,
+    /// Wall time of the slowest iteration, in nanoseconds
+    pub max_ns: u64,
+    /// Mean wall time of an iteration, in nanoseconds
+    pub mean_ns: u64,
+    /// Median wall time of an iteration, in nanoseconds
+    pub median_ns: u64,
+    /// The most memory, in bytes, that the benchmark's memory context held at the end of an
+    /// iteration, according to `MemoryContextMemAllocated()`.  Always `None` on Postgres 12,
+    /// which doesn't have that function.
+    pub max_mem_allocated: Option<u64>,
+    /// Buffer usage across all iterations
+    pub buffers: BenchBufferUsage,
+}
+
+/// The difference in `pgBufferUsage` from before to after a benchmark ran
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BenchBufferUsage {
+    pub shared_blks_hit: i64,
+    pub shared_blks_read: i64,
+    pub shared_blks_dirtied: i64,
+    pub shared_blks_written: i64,
+    pub local_blks_hit: i64,
+    pub local_blks_read: i64,
+    pub temp_blks_read: i64,
+    pub temp_blks_written: i64,
+}
+
+impl From<BenchStats> for JsonB {
+    fn from(stats: BenchStats) -> Self {
+        JsonB(serde_json::to_value(stats).expect("BenchStats should always serialize"))
+    }
+}
+
+impl BenchBufferUsage {
+    fn current() -> Self {
+        // SAFETY: `pgBufferUsage` is a backend-local global that Postgres only ever updates from
+        // this thread
+        let usage = unsafe { pg_sys::pgBufferUsage };
+        Self {
+            shared_blks_hit: usage.shared_blks_hit,
+            shared_blks_read: usage.shared_blks_read,
+            shared_blks_dirtied: usage.shared_blks_dirtied,
+            shared_blks_written: usage.shared_blks_written,
+            local_blks_hit: usage.local_blks_hit,
+            local_blks_read: usage.local_blks_read,
+            temp_blks_read: usage.temp_blks_read,
+            temp_blks_written: usage.temp_blks_written,
+        }
+    }
+
+    fn since(self, before: Self) -> Self {
+        Self {
+            shared_blks_hit: self.shared_blks_hit - before.shared_blks_hit,
+            shared_blks_read: self.shared_blks_read - before.shared_blks_read,
+            shared_blks_dirtied: self.shared_blks_dirtied - before.shared_blks_dirtied,
+            shared_blks_written: self.shared_blks_written - before.shared_blks_written,
+            local_blks_hit: self.local_blks_hit - before.local_blks_hit,
+            local_blks_read: self.local_blks_read - before.local_blks_read,
+            temp_blks_read: self.temp_blks_read - before.temp_blks_read,
+            temp_blks_written: self.temp_blks_written - before.temp_blks_written,
+        }
+    }
+}
+
+/// Run `f` `iterations` times, each in a fresh memory context, and measure it.
+///
+/// The memory context is reset after every iteration, so anything `f` allocates in
+/// `CurrentMemoryContext` doesn't accumulate across iterations.
+pub fn run<F: FnMut()>(iterations: u32, mut f: F) -> BenchStats {
+    let mut memcxt = PgMemoryContexts::new("pg_bench");
+    let mut times = Vec::with_capacity(iterations as usize);
+    let mut max_mem_allocated = None;
+
+    let buffers_before = BenchBufferUsage::current();
+    for _ in 0..iterations {
+        let start = Instant::now();
+        // SAFETY: our memory context is valid and nothing escapes the closure
+        unsafe { memcxt.switch_to(|_| f()) };
+        times.push(start.elapsed());
+
+        if let Some(allocated) = mem_allocated(&memcxt) {
+            max_mem_allocated = Some(max_mem_allocated.unwrap_or(0).max(allocated));
+        }
+        // SAFETY: we're done with everything `f` allocated in this iteration
+        unsafe { memcxt.reset() };
+    }
+    let buffers = BenchBufferUsage::current().since(buffers_before);
+
+    BenchStats { max_mem_allocated, buffers, ..summarize(times) }
+}
+
+/// Run the SQL `query`, via [`Spi`], `iterations` times and measure it.
+///
+/// Memory measurements only cover what's allocated in `CurrentMemoryContext`, not SPI's own
+/// per-query memory contexts.
+pub fn run_sql(iterations: u32, query: &str) -> BenchStats {
+    run(iterations, || Spi::run(query).unwrap_or_else(|e| panic!("{e}")))
+}
+
+fn mem_allocated(memcxt: &PgMemoryContexts) -> Option<u64> {
+    #[cfg(feature = "pg12")]
+    {
+        let _ = memcxt;
+        None
+    }
+    #[cfg(not(feature = "pg12"))]
+    {
+        // SAFETY: our memory context is valid
+        Some(unsafe { pg_sys::MemoryContextMemAllocated(memcxt.value(), true) } as u64)
+    }
+}
+
+fn summarize(mut times: Vec<Duration>) -> BenchStats {
+    if times.is_empty() {
+        return BenchStats::default();
+    }
+    times.sort();
+
+    let nanos = |d: &Duration| d.as_nanos() as u64;
+    let total: u64 = times.iter().map(nanos).sum();
+    BenchStats {
+        iterations: times.len() as u32,
+        min_ns: nanos(&times[0]),
+        max_ns: nanos(&times[times.len() - 1]),
+        mean_ns: total / times.len() as u64,
+        median_ns: nanos(&times[times.len() / 2]),
+        ..Default::default()
+    }
+}
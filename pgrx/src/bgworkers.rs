@@ -14,12 +14,16 @@
 #![allow(clippy::useless_transmute)]
 use crate::pg_sys;
 use pgrx_pg_sys::PgTryBuilder;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 use std::ptr::null_mut;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
+pub mod pool;
+
 pub static mut PREV_SHMEM_STARTUP_HOOK: Option<unsafe extern "C" fn()> = None;
 static GOT_SIGHUP: AtomicBool = AtomicBool::new(false);
 static GOT_SIGTERM: AtomicBool = AtomicBool::new(false);
@@ -108,6 +112,19 @@ impl BackgroundWorker {
         .expect("'extra' is not valid UTF8")
     }
 
+    /// Retrieve the arguments provided to [`BackgroundWorkerBuilder::set_extra_args`]
+    pub fn get_extra_args<T: DeserializeOwned>() -> T {
+        let extra = unsafe {
+            assert!(!pg_sys::MyBgworkerEntry.is_null(), "BackgroundWorker associated functions can only be called from a registered background worker");
+            let extra = &(*pg_sys::MyBgworkerEntry).bgw_extra;
+            std::slice::from_raw_parts(extra.as_ptr().cast::<u8>(), extra.len())
+        };
+        // the encoded arguments are followed by zeros, which the deserializer never gets to
+        let mut deserializer = serde_cbor::Deserializer::from_slice(extra);
+        <T as Deserialize>::deserialize(&mut deserializer)
+            .expect("'extra' does not contain the expected arguments")
+    }
+
     /// Have we received a SIGHUP since previously calling this function? This resets the
     /// internal boolean that tracks if SIGHUP was received. So when calling this twice in
     /// in a row, the second time will always return false.
@@ -158,7 +175,8 @@ impl BackgroundWorker {
 
     /// Wait for the specified amount of time on the background worker's latch
     ///
-    /// Returns true if we're still supposed to be alive and haven't received a SIGTERM
+    /// Returns true if we're still supposed to be alive and haven't received a SIGTERM.  To wait
+    /// on sockets too, see [`crate::latch::WaitEventSet`].
     pub fn wait_latch(timeout: Option<Duration>) -> bool {
         unsafe {
             assert!(!pg_sys::MyBgworkerEntry.is_null(), "BackgroundWorker associated functions can only be called from a registered background worker");
@@ -316,6 +334,10 @@ impl From<pg_sys::BgwHandleStatus::Type> for BackgroundWorkerStatus {
 }
 
 impl DynamicBackgroundWorker {
+    pub(crate) fn as_raw(&self) -> *mut pg_sys::BackgroundWorkerHandle {
+        self.handle
+    }
+
     /// Return dynamic background worker's PID if the worker is successfully registered,
     /// otherwise it return worker's status as an error.
     pub fn pid(&self) -> Result<Pid, BackgroundWorkerStatus> {
@@ -432,7 +454,7 @@ pub struct BackgroundWorkerBuilder {
     bgw_library_name: String,
     bgw_function_name: String,
     bgw_main_arg: pg_sys::Datum,
-    bgw_extra: String,
+    bgw_extra: Vec<u8>,
     bgw_notify_pid: pg_sys::pid_t,
     shared_memory_startup_fn: Option<unsafe extern "C" fn()>,
 }
@@ -454,7 +476,7 @@ impl BackgroundWorkerBuilder {
             bgw_library_name: name.to_string(),
             bgw_function_name: name.to_string(),
             bgw_main_arg: pg_sys::Datum::from(0),
-            bgw_extra: "".to_string(),
+            bgw_extra: Vec::new(),
             bgw_notify_pid: 0,
             shared_memory_startup_fn: None,
         }
@@ -572,7 +594,24 @@ impl BackgroundWorkerBuilder {
     /// data is not passed as an argument to the worker's main function, but it can be
     /// accessed via the `BackgroundWorker` struct.
     pub fn set_extra(mut self, input: &str) -> Self {
-        self.bgw_extra = input.to_string();
+        self.bgw_extra = input.as_bytes().to_vec();
+        self
+    }
+
+    /// Typed arguments to be passed to the background worker through its `extra` data, which
+    /// it retrieves with [`BackgroundWorker::get_extra_args`].  This replaces anything given to
+    /// [`BackgroundWorkerBuilder::set_extra`].
+    ///
+    /// `args` are serialized as CBOR, and must fit in the 128 bytes Postgres has room for.
+    pub fn set_extra_args<T: Serialize>(mut self, args: &T) -> Self {
+        let extra = serde_cbor::to_vec(args).expect("failed to encode background worker arguments");
+        assert!(
+            extra.len() <= pg_sys::BGW_EXTRALEN as usize,
+            "background worker arguments are {} bytes, which is more than the {} that fit",
+            extra.len(),
+            pg_sys::BGW_EXTRALEN
+        );
+        self.bgw_extra = extra;
         self
     }
 
@@ -714,10 +753,10 @@ impl<'a> From<&'a str> for RpgffiChar96 {
 
 struct RpgffiChar128([c_char; 128]);
 
-impl<'a> From<&'a str> for RpgffiChar128 {
-    fn from(string: &str) -> Self {
+impl<'a> From<&'a [u8]> for RpgffiChar128 {
+    fn from(bytes: &[u8]) -> Self {
         let mut r = [0; 128];
-        for (dest, src) in r.iter_mut().zip(string.as_bytes()) {
+        for (dest, src) in r.iter_mut().zip(bytes) {
             *dest = *src as c_char;
         }
         RpgffiChar128(r)
@@ -0,0 +1,447 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Keeping a pool of dynamic background workers running
+//!
+//! A [`WorkerPool`] is a registry of up to `N` workers in `pg_shmem_init!` shared memory.  One
+//! background worker, the supervisor, calls [`WorkerPool::supervise`], which starts a worker in
+//! every slot, and restarts any that exit, waiting longer each time one exits soon after
+//! starting.  Any backend can see how the workers are doing with [`WorkerPool::status`], or stop
+//! one with [`WorkerPool::terminate`], and if the supervisor itself is restarted, it picks up
+//! the workers that are still running rather than starting more.  [`worker_pool_status_function!`]
+//! makes a set-returning function that shows the status in SQL.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pgrx::bgworkers::pool::{Backoff, WorkerPool};
+//! use pgrx::bgworkers::BackgroundWorker;
+//! use pgrx::prelude::*;
+//! use pgrx::{pg_shmem_init, worker_pool_status_function, PgSharedMemoryInitialization};
+//!
+//! static JOB_RUNNERS: WorkerPool<4> = WorkerPool::new();
+//!
+//! #[pg_bgworker(database = "postgres")]
+//! fn job_runner() {
+//!     while BackgroundWorker::wait_latch(Some(std::time::Duration::from_secs(1))) {
+//!         // run some jobs
+//!     }
+//! }
+//!
+//! #[pg_bgworker(restart = 5)]
+//! fn job_supervisor() {
+//!     JOB_RUNNERS.supervise(Backoff::default(), |_slot| job_runner::builder());
+//! }
+//!
+//! worker_pool_status_function!(fn job_runner_status for JOB_RUNNERS);
+//!
+//! #[pg_guard]
+//! pub extern "C" fn _PG_init() {
+//!     pg_shmem_init!(JOB_RUNNERS);
+//!     job_supervisor::register();
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate::bgworkers::{BackgroundWorker, BackgroundWorkerBuilder, Pid};
+use crate::datum::{IntoDatum, TimestampWithTimeZone};
+use crate::lwlock::PgLwLock;
+use crate::pg_sys;
+use crate::shmem::{PGRXSharedMemory, PgSharedMemoryInitialization};
+use std::fmt;
+use std::time::Duration;
+
+/// How often the supervisor checks on workers it won't be told about, such as those started by
+/// a previous supervisor
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the supervisor waits before restarting a worker that exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// The wait after a worker first exits
+    pub initial: Duration,
+    /// The longest wait, which the wait doubles up to each time a worker exits again
+    pub max: Duration,
+    /// How long a worker must run for its wait to go back to `initial`
+    pub reset_after: Duration,
+}
+
+impl Default for Backoff {
+    /// Wait a second at first, up to five minutes, until a worker runs for a minute
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(300),
+            reset_after: Duration::from_secs(60),
+        }
+    }
+}
+
+/// What a worker in a [`WorkerPool`] is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerState {
+    /// The supervisor hasn't started it yet
+    NotStarted,
+    /// It's been started, and hasn't exited
+    Running,
+    /// It exited, and the supervisor will restart it at [`WorkerStatus::restart_at`]
+    Restarting,
+    /// The supervisor has shut down, and stopped it
+    Stopped,
+}
+
+impl fmt::Display for WorkerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WorkerState::NotStarted => "not started",
+            WorkerState::Running => "running",
+            WorkerState::Restarting => "restarting",
+            WorkerState::Stopped => "stopped",
+        })
+    }
+}
+
+/// A snapshot of one of a [`WorkerPool`]'s workers
+#[derive(Debug, Clone)]
+pub struct WorkerStatus {
+    /// Its slot in the pool, which is also its main function's `Datum` argument
+    pub slot: usize,
+    pub state: WorkerState,
+    /// Its process ID, once it's running
+    pub pid: Option<Pid>,
+    /// How many times it's been restarted after exiting.  Attempts to start it that found no
+    /// free background worker slot don't count.
+    pub restarts: u32,
+    /// When it was last started
+    pub started_at: Option<TimestampWithTimeZone>,
+    /// When it will be restarted, if it's [`WorkerState::Restarting`]
+    pub restart_at: Option<TimestampWithTimeZone>,
+}
+
+/// Create a set-returning function that shows a [`WorkerPool`]'s status, one row for each worker
+///
+/// Its columns are `slot integer, state text, pid integer, restarts bigint, started_at
+/// timestamptz, restart_at timestamptz`.  See the [module documentation](crate::bgworkers::pool)
+/// for an example.
+#[macro_export]
+macro_rules! worker_pool_status_function {
+    ($vis:vis fn $name:ident for $pool:path) => {
+        #[::pgrx::pg_extern]
+        $vis fn $name() -> ::pgrx::iter::TableIterator<
+            'static,
+            (
+                ::pgrx::name!(slot, i32),
+                ::pgrx::name!(state, String),
+                ::pgrx::name!(pid, Option<i32>),
+                ::pgrx::name!(restarts, i64),
+                ::pgrx::name!(started_at, Option<::pgrx::datum::TimestampWithTimeZone>),
+                ::pgrx::name!(restart_at, Option<::pgrx::datum::TimestampWithTimeZone>),
+            ),
+        > {
+            ::pgrx::iter::TableIterator::new($pool.rows())
+        }
+    };
+}
+
+/// A pool of `N` background workers, kept running by a supervisor
+///
+/// It must be a `static` passed to `pg_shmem_init!()` during `_PG_init()`.
+pub struct WorkerPool<const N: usize> {
+    registry: PgLwLock<Registry<N>>,
+}
+
+impl<const N: usize> WorkerPool<N> {
+    /// Create an uninitialized pool, to be passed to `pg_shmem_init!()`
+    pub const fn new() -> Self {
+        WorkerPool { registry: PgLwLock::new() }
+    }
+
+    /// Keep a worker running in every slot, each built by `worker` given its slot, until this
+    /// background worker receives `SIGTERM` or the postmaster dies.  The pool's workers are
+    /// then terminated too.
+    ///
+    /// Each worker's main function is given its slot as its `Datum` argument.  This must be
+    /// called from a background worker that has attached its signal handlers, such as a
+    /// `#[pg_bgworker]`, and there must only be one supervisor at a time.  `worker` is called
+    /// with the pool locked, so it mustn't use the pool itself.
+    pub fn supervise<F>(&self, backoff: Backoff, mut worker: F)
+    where
+        F: FnMut(usize) -> BackgroundWorkerBuilder,
+    {
+        // SAFETY: reading our own PID
+        let my_pid = unsafe { pg_sys::MyProcPid };
+        {
+            let mut registry = self.registry.exclusive();
+            let previous = registry.supervisor_pid;
+            assert!(
+                previous == 0 || previous == my_pid || !is_backend_alive(previous),
+                "worker pool is already supervised by process {previous}"
+            );
+            registry.supervisor_pid = my_pid;
+            for entry in registry.entries.iter_mut() {
+                if entry.state == WorkerState::Stopped {
+                    entry.state = WorkerState::NotStarted;
+                }
+            }
+        }
+
+        let _shutdown = Shutdown { registry: &self.registry };
+        loop {
+            // SAFETY: just reads the clock
+            let now = unsafe { pg_sys::GetCurrentTimestamp() };
+            let mut wake_at = now + micros(POLL_INTERVAL);
+            {
+                let mut registry = self.registry.exclusive();
+                for (slot, entry) in registry.entries.iter_mut().enumerate() {
+                    if entry.state == WorkerState::Running {
+                        entry.check(now, &backoff);
+                    }
+                    if matches!(entry.state, WorkerState::NotStarted | WorkerState::Restarting)
+                        && entry.restart_at <= now
+                    {
+                        entry.start(slot, now, &backoff, worker(slot));
+                    }
+                    if entry.state == WorkerState::Restarting {
+                        wake_at = wake_at.min(entry.restart_at);
+                    }
+                }
+            }
+
+            let timeout = Duration::from_micros((wake_at - now).max(0) as u64);
+            if !BackgroundWorker::wait_latch(Some(timeout)) {
+                break;
+            }
+        }
+    }
+
+    /// A snapshot of every worker in the pool
+    pub fn status(&self) -> Vec<WorkerStatus> {
+        let registry = self.registry.share();
+        registry
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(slot, entry)| WorkerStatus {
+                slot,
+                state: entry.state,
+                pid: (entry.pid != 0).then_some(entry.pid),
+                restarts: entry.restarts,
+                started_at: timestamp(entry.started_at),
+                restart_at: (entry.state == WorkerState::Restarting)
+                    .then(|| timestamp(entry.restart_at))
+                    .flatten(),
+            })
+            .collect()
+    }
+
+    /// Every worker's status, as `(slot, state, pid, restarts, started_at, restart_at)`, which is
+    /// what [`worker_pool_status_function!`] returns
+    #[allow(clippy::type_complexity)]
+    pub fn rows(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            i32,
+            String,
+            Option<Pid>,
+            i64,
+            Option<TimestampWithTimeZone>,
+            Option<TimestampWithTimeZone>,
+        ),
+    > {
+        self.status().into_iter().map(|worker| {
+            (
+                worker.slot as i32,
+                worker.state.to_string(),
+                worker.pid,
+                worker.restarts.into(),
+                worker.started_at,
+                worker.restart_at,
+            )
+        })
+    }
+
+    /// Terminate the worker in `slot`, if it's running.  Its supervisor restarts it, like any
+    /// other worker that exits.
+    pub fn terminate(&self, slot: usize) {
+        assert!(slot < N, "slot {slot} is out of range for a pool of {N}");
+        let registry = self.registry.share();
+        if let Some(mut handle) = registry.entries[slot].handle {
+            // SAFETY: the handle identifies the worker's slot in Postgres' registry, which
+            // ignores it if the slot has been reused since
+            unsafe { pg_sys::TerminateBackgroundWorker(handle.as_ptr()) }
+        }
+    }
+}
+
+impl<const N: usize> Default for WorkerPool<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> PgSharedMemoryInitialization for WorkerPool<N> {
+    fn pg_init(&'static self) {
+        self.registry.pg_init();
+    }
+
+    /// SAFETY: Must only be called from inside the Postgres shared memory init hook
+    unsafe fn shmem_init(&'static self) {
+        unsafe { self.registry.shmem_init() }
+    }
+}
+
+/// Where the pool keeps track of its supervisor and workers
+struct Registry<const N: usize> {
+    supervisor_pid: Pid,
+    entries: [Entry; N],
+}
+
+unsafe impl<const N: usize> PGRXSharedMemory for Registry<N> {}
+
+impl<const N: usize> Default for Registry<N> {
+    fn default() -> Self {
+        Registry { supervisor_pid: 0, entries: [Entry::EMPTY; N] }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    state: WorkerState,
+    handle: Option<WorkerHandle>,
+    pid: Pid,
+    restarts: u32,
+    started_at: pg_sys::TimestampTz,
+    restart_at: pg_sys::TimestampTz,
+    /// How long to wait before restarting the worker, the next time it exits soon after starting
+    delay: i64,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        state: WorkerState::NotStarted,
+        handle: None,
+        pid: 0,
+        restarts: 0,
+        started_at: 0,
+        restart_at: 0,
+        delay: 0,
+    };
+
+    /// See whether our running worker has exited, and if so, when to restart it
+    fn check(&mut self, now: pg_sys::TimestampTz, backoff: &Backoff) {
+        let Some(mut handle) = self.handle else { return };
+        let mut pid = 0;
+        // SAFETY: the handle identifies the worker's slot in Postgres' registry, which reports
+        // it stopped if the slot has been reused since
+        let status = unsafe { pg_sys::GetBackgroundWorkerPid(handle.as_ptr(), &mut pid) };
+        match status {
+            pg_sys::BgwHandleStatus::BGWH_STARTED => self.pid = pid,
+            pg_sys::BgwHandleStatus::BGWH_NOT_YET_STARTED => (),
+            _ => {
+                if now - self.started_at >= micros(backoff.reset_after) {
+                    self.delay = micros(backoff.initial);
+                }
+                self.schedule_restart(now, backoff);
+            }
+        }
+    }
+
+    fn start(
+        &mut self,
+        slot: usize,
+        now: pg_sys::TimestampTz,
+        backoff: &Backoff,
+        builder: BackgroundWorkerBuilder,
+    ) {
+        // a worker that has never started isn't restarted, even if starting it has been retried
+        let restarting = self.state == WorkerState::Restarting && self.started_at != 0;
+        let worker = builder
+            .set_argument((slot as i32).into_datum())
+            // SAFETY: reading our own PID
+            .set_notify_pid(unsafe { pg_sys::MyProcPid })
+            .load_dynamic();
+        match worker {
+            Ok(worker) => {
+                // SAFETY: the handle was allocated by `RegisterDynamicBackgroundWorker()`, and
+                // we've no further use for it once it's copied
+                self.handle = Some(unsafe {
+                    let handle = *worker.as_raw().cast::<WorkerHandle>();
+                    pg_sys::pfree(worker.as_raw().cast());
+                    handle
+                });
+                self.state = WorkerState::Running;
+                self.pid = 0;
+                self.started_at = now;
+                if restarting {
+                    self.restarts += 1;
+                }
+            }
+            // there are no free background worker slots, so try again later
+            Err(()) => self.schedule_restart(now, backoff),
+        }
+    }
+
+    fn schedule_restart(&mut self, now: pg_sys::TimestampTz, backoff: &Backoff) {
+        self.delay = self.delay.clamp(micros(backoff.initial), micros(backoff.max));
+        self.state = WorkerState::Restarting;
+        self.handle = None;
+        self.pid = 0;
+        self.restart_at = now + self.delay;
+        self.delay = self.delay.saturating_mul(2).min(micros(backoff.max));
+    }
+}
+
+/// Marks the pool unsupervised, and terminates its workers, when its supervisor stops
+struct Shutdown<'a, const N: usize> {
+    registry: &'a PgLwLock<Registry<N>>,
+}
+
+impl<const N: usize> Drop for Shutdown<'_, N> {
+    fn drop(&mut self) {
+        let mut registry = self.registry.exclusive();
+        registry.supervisor_pid = 0;
+        for entry in registry.entries.iter_mut() {
+            if let Some(mut handle) = entry.handle.take() {
+                // SAFETY: as in `WorkerPool::terminate()`
+                unsafe { pg_sys::TerminateBackgroundWorker(handle.as_ptr()) }
+            }
+            entry.state = WorkerState::Stopped;
+            entry.pid = 0;
+        }
+    }
+}
+
+/// A copy of a `BackgroundWorkerHandle`, which is opaque in `bgworker.h` but, in `bgworker.c`,
+/// only names a slot in the postmaster's shared registry of workers.  Unlike the palloc'd
+/// original, it can be kept in shared memory and used from any backend.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct WorkerHandle {
+    slot: std::ffi::c_int,
+    generation: u64,
+}
+
+impl WorkerHandle {
+    fn as_ptr(&mut self) -> *mut pg_sys::BackgroundWorkerHandle {
+        (self as *mut WorkerHandle).cast()
+    }
+}
+
+fn micros(duration: Duration) -> i64 {
+    duration.as_micros().try_into().unwrap_or(i64::MAX)
+}
+
+fn timestamp(ts: pg_sys::TimestampTz) -> Option<TimestampWithTimeZone> {
+    (ts != 0).then(|| TimestampWithTimeZone::try_from(ts).ok()).flatten()
+}
+
+fn is_backend_alive(pid: Pid) -> bool {
+    // SAFETY: signal 0 only checks that the process exists
+    unsafe { libc::kill(pid, 0) == 0 }
+}
@@ -0,0 +1,149 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate::pg_sys;
+use std::cell::UnsafeCell;
+
+/// A Postgres condition variable in shared memory, which lets backends sleep until another
+/// backend tells them that something they're waiting for may have happened
+///
+/// It protects no data of its own.  Whatever the condition is about is generally in a
+/// [`crate::PgLwLock`] or a [`crate::PgAtomic`] next to it.  Like those, it must be a `static`
+/// passed to `pg_shmem_init!()` during `_PG_init()`.
+///
+/// While a backend sleeps, `pg_stat_activity` shows it waiting on the variable's wait event,
+/// `Extension` unless set with [`PgConditionVariable::with_wait_event`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use pgrx::prelude::*;
+/// use pgrx::{pg_shmem_init, PgAtomic, PgConditionVariable, PgSharedMemoryInitialization};
+/// use std::sync::atomic::{AtomicBool, Ordering};
+///
+/// static READY: PgAtomic<AtomicBool> = PgAtomic::new();
+/// static READY_CHANGED: PgConditionVariable = PgConditionVariable::new();
+///
+/// #[pg_guard]
+/// pub extern "C" fn _PG_init() {
+///     pg_shmem_init!(READY);
+///     pg_shmem_init!(READY_CHANGED);
+/// }
+///
+/// fn wait_until_ready() {
+///     READY_CHANGED.wait_until(|| READY.get().load(Ordering::SeqCst));
+/// }
+///
+/// fn set_ready() {
+///     READY.get().store(true, Ordering::SeqCst);
+///     READY_CHANGED.broadcast();
+/// }
+/// ```
+pub struct PgConditionVariable {
+    inner: UnsafeCell<*mut pg_sys::ConditionVariable>,
+    wait_event_info: u32,
+}
+
+unsafe impl Send for PgConditionVariable {}
+unsafe impl Sync for PgConditionVariable {}
+
+impl PgConditionVariable {
+    /// Create an uninitialized condition variable, to be passed to `pg_shmem_init!()`
+    pub const fn new() -> Self {
+        Self::with_wait_event(pg_sys::PG_WAIT_EXTENSION)
+    }
+
+    /// Like [`PgConditionVariable::new`], but report `wait_event_info` while sleeping on it
+    pub const fn with_wait_event(wait_event_info: u32) -> Self {
+        Self { inner: UnsafeCell::new(std::ptr::null_mut()), wait_event_info }
+    }
+
+    /// Attach to a condition variable in shared memory, which must already be initialized
+    ///
+    /// SAFETY: Must only be called from inside the Postgres shared memory init hook
+    pub unsafe fn attach(&self, cv: *mut pg_sys::ConditionVariable) {
+        unsafe {
+            *self.inner.get() = cv;
+        }
+    }
+
+    fn as_ptr(&self) -> *mut pg_sys::ConditionVariable {
+        let cv = unsafe { *self.inner.get() };
+        assert!(!cv.is_null(), "PgConditionVariable was not initialized");
+        cv
+    }
+
+    /// Wake up one backend sleeping on this condition variable, if any
+    pub fn signal(&self) {
+        unsafe { pg_sys::ConditionVariableSignal(self.as_ptr()) }
+    }
+
+    /// Wake up every backend sleeping on this condition variable
+    pub fn broadcast(&self) {
+        unsafe { pg_sys::ConditionVariableBroadcast(self.as_ptr()) }
+    }
+
+    /// Sleep until `condition` returns `true`, checking it again each time the variable is
+    /// signalled.
+    ///
+    /// Interrupts are checked while sleeping, so a query cancel or termination request ends the
+    /// wait with an `ERROR`.
+    pub fn wait_until<F: FnMut() -> bool>(&self, mut condition: F) {
+        let _sleep = self.prepare_to_sleep();
+        while !condition() {
+            unsafe { pg_sys::ConditionVariableSleep(self.as_ptr(), self.wait_event_info) }
+        }
+    }
+
+    /// Like [`PgConditionVariable::wait_until`], but give up after `timeout`.  Returns whether
+    /// `condition` became `true`.
+    ///
+    /// Requires Postgres 13 or later.
+    #[cfg(not(feature = "pg12"))]
+    pub fn wait_until_timeout<F: FnMut() -> bool>(
+        &self,
+        timeout: std::time::Duration,
+        mut condition: F,
+    ) -> bool {
+        let _sleep = self.prepare_to_sleep();
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            if condition() {
+                return true;
+            }
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            let millis = remaining.as_millis().try_into().unwrap_or(libc::c_long::MAX);
+            let timed_out = unsafe {
+                pg_sys::ConditionVariableTimedSleep(self.as_ptr(), millis, self.wait_event_info)
+            };
+            if timed_out {
+                return condition();
+            }
+        }
+    }
+
+    fn prepare_to_sleep(&self) -> SleepGuard {
+        // checking the condition before sleeping the first time is what makes wakeups between
+        // the caller deciding to wait and actually sleeping impossible to miss
+        unsafe { pg_sys::ConditionVariablePrepareToSleep(self.as_ptr()) };
+        SleepGuard
+    }
+}
+
+/// Takes us off a condition variable's wait list, once we're done waiting or we unwind
+struct SleepGuard;
+
+impl Drop for SleepGuard {
+    fn drop(&mut self) {
+        unsafe {
+            pg_sys::ConditionVariableCancelSleep();
+        }
+    }
+}
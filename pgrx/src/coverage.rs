@@ -0,0 +1,80 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Support for `cargo pgrx test --coverage`
+//!
+//! When `cargo-pgrx` builds an extension for coverage it compiles everything with
+//! `-C instrument-coverage --cfg pgrx_coverage`.  The LLVM profiling runtime normally writes its
+//! counters from an `atexit()` handler, but a Postgres backend doesn't always get that far, and
+//! backends forked from the postmaster would all share the postmaster's `%p` in `LLVM_PROFILE_FILE`.
+//!
+//! To make sure each backend produces its own `.profraw` file, [`register_profile_flush`] installs
+//! an `on_proc_exit` callback that re-evaluates `LLVM_PROFILE_FILE` for the exiting process and
+//! dumps the counters.  Without `--cfg pgrx_coverage` it does nothing.
+#![allow(unexpected_cfgs)]
+
+/// Arrange for this backend's code coverage counters to be written when it exits.
+///
+/// This is called from [`pg_magic_func!`](crate::pg_magic_func), so extensions never need to call
+/// it themselves.  It only has an effect when the extension was built by `cargo pgrx test --coverage`.
+#[inline]
+pub fn register_profile_flush() {
+    #[cfg(pgrx_coverage)]
+    imp::register();
+}
+
+#[cfg(pgrx_coverage)]
+mod imp {
+    use crate as pgrx; // for #[pg_guard] support from within ourself
+    use crate::pg_sys;
+    use crate::prelude::*;
+    use std::ffi::{c_char, c_int, CString};
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    // provided by the LLVM profiler runtime that rustc links in with `-C instrument-coverage`
+    extern "C" {
+        fn __llvm_profile_set_filename(name: *const c_char);
+        fn __llvm_profile_dump() -> c_int;
+    }
+
+    /// The pid of the process that last registered our callback.  The postmaster resets its
+    /// `on_proc_exit` list in each forked child, so a library loaded via `shared_preload_libraries`
+    /// can see `Pg_magic_func` called once in the postmaster and then never again.  Such backends
+    /// still get the profiler's own `atexit()` handler.
+    static REGISTERED_PID: AtomicI32 = AtomicI32::new(0);
+
+    pub(super) fn register() {
+        let pid = std::process::id() as i32;
+        if REGISTERED_PID.swap(pid, Ordering::SeqCst) == pid {
+            return;
+        }
+
+        unsafe {
+            pg_sys::on_proc_exit(Some(flush), pg_sys::Datum::from(0));
+        }
+    }
+
+    #[pg_guard]
+    unsafe extern "C" fn flush(_code: c_int, _arg: pg_sys::Datum) {
+        // re-parsing the filename pattern makes `%p` expand to *our* pid rather than whichever
+        // process first loaded the profiler runtime
+        if let Some(pattern) = std::env::var_os("LLVM_PROFILE_FILE") {
+            use std::os::unix::ffi::OsStrExt;
+            if let Ok(pattern) = CString::new(pattern.as_bytes()) {
+                __llvm_profile_set_filename(pattern.as_ptr());
+            }
+        }
+
+        // `__llvm_profile_dump()` also marks the profile as written so the runtime's `atexit()`
+        // handler won't write it a second time
+        if __llvm_profile_dump() != 0 {
+            warning!("failed to write code coverage profile for pid {}", std::process::id());
+        }
+    }
+}
@@ -145,7 +145,7 @@ pub trait FromDatum: Sized {
     }
 }
 
-fn is_binary_coercible<T: IntoDatum>(type_oid: pg_sys::Oid) -> bool {
+pub(crate) fn is_binary_coercible<T: IntoDatum>(type_oid: pg_sys::Oid) -> bool {
     T::is_compatible_with(type_oid) || unsafe { pg_sys::IsBinaryCoercible(type_oid, T::type_oid()) }
 }
 
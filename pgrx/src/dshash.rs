@@ -0,0 +1,465 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! A concurrent hash map in dynamic shared memory, built on Postgres' `dshash`
+//!
+//! Unlike a `heapless` map behind a [`crate::PgLwLock`], a [`PgSharedHashMap`] grows as needed
+//! and locks only the partition an entry lives in, so backends working on different keys rarely
+//! wait on each other.  Its memory comes from a [`DsaArea`].
+//!
+//! Every lookup returns a guard that holds its partition's lock until it's dropped.  Postgres
+//! doesn't allow a backend to hold more than one of these locks at a time, so everything that
+//! locks a partition takes the map by `&mut`, and the borrow checker won't let a guard live past
+//! the next use of the map.  The following code will not compile:
+//!
+//! ```rust,compile_fail
+//! use pgrx::dshash::PgSharedHashMap;
+//!
+//! fn both(map: &mut PgSharedHashMap<i64, i64>) -> i64 {
+//!     let one = map.get(&1).unwrap();
+//!     let two = map.get(&2).unwrap();
+//!     *one + *two
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate as pgrx; // for #[pg_guard] support from within ourself
+use crate::dsm::{lwlock_tranche, DsaArea};
+use crate::pg_sys;
+use crate::shmem::PGRXSharedMemory;
+use pgrx_macros::pg_guard;
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::{c_int, c_void, CStr};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+/// A hash map from `K` to `V` in a [`DsaArea`], shared by every backend attached to it
+///
+/// Keys are hashed with Rust's [`Hash`], which is the same in every backend running the same
+/// build of the extension.
+pub struct PgSharedHashMap<'a, K, V> {
+    table: NonNull<pg_sys::dshash_table>,
+    _area: PhantomData<&'a DsaArea>,
+    _marker: PhantomData<*mut (K, V)>,
+}
+
+/// How entries are laid out in the table.  `dshash` expects the key at the start.
+#[repr(C)]
+struct Entry<K, V> {
+    key: K,
+    value: V,
+}
+
+impl<'a, K, V> PgSharedHashMap<'a, K, V>
+where
+    K: PGRXSharedMemory + Eq + Hash,
+    V: PGRXSharedMemory,
+{
+    /// Create a new, empty map in `area`.  Its partition locks are registered under
+    /// `tranche_name` in this backend.
+    pub fn create(area: &'a DsaArea, tranche_name: &'static CStr) -> Self {
+        let params = Self::parameters(lwlock_tranche(tranche_name));
+        // SAFETY: `dshash_create()` raises an ERROR rather than return NULL
+        unsafe {
+            Self::from_raw(pg_sys::dshash_create(area.as_raw(), &params, std::ptr::null_mut()))
+        }
+    }
+
+    /// Attach to the existing map identified by `handle`, which must be in `area` and have the
+    /// same key and value types.
+    ///
+    /// # Safety
+    ///
+    /// The map must have been created with these `K` and `V`.  Nothing in shared memory says
+    /// which types those were.
+    pub unsafe fn attach(area: &'a DsaArea, handle: pg_sys::dshash_table_handle) -> Self {
+        // `dshash_attach()` doesn't use the tranche, as the locks already exist
+        let params = Self::parameters(0);
+        // SAFETY: the caller guarantees the types match, and `dshash_attach()` raises an ERROR
+        // rather than return NULL
+        unsafe {
+            Self::from_raw(pg_sys::dshash_attach(
+                area.as_raw(),
+                &params,
+                handle,
+                std::ptr::null_mut(),
+            ))
+        }
+    }
+
+    fn parameters(tranche_id: c_int) -> pg_sys::dshash_parameters {
+        pg_sys::dshash_parameters {
+            key_size: std::mem::size_of::<K>(),
+            entry_size: std::mem::size_of::<Entry<K, V>>(),
+            compare_function: Some(compare::<K>),
+            hash_function: Some(hash::<K>),
+            #[cfg(feature = "pg17")]
+            copy_function: Some(copy),
+            tranche_id,
+        }
+    }
+
+    unsafe fn from_raw(table: *mut pg_sys::dshash_table) -> Self {
+        assert!(
+            std::mem::align_of::<Entry<K, V>>() <= pg_sys::MAXIMUM_ALIGNOF as usize,
+            "shared hash map entries can't be aligned to more than MAXIMUM_ALIGNOF"
+        );
+        Self {
+            table: NonNull::new(table).expect("dshash_table should not be NULL"),
+            _area: PhantomData,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The handle other backends can use to [`PgSharedHashMap::attach`] to this map
+    pub fn handle(&self) -> pg_sys::dshash_table_handle {
+        // SAFETY: our table is attached
+        unsafe { pg_sys::dshash_get_hash_table_handle(self.table.as_ptr()) }
+    }
+
+    /// Look up `key`, holding its partition's lock in shared mode while the returned guard lives
+    pub fn get(&mut self, key: &K) -> Option<SharedEntryGuard<'_, K, V>> {
+        let entry = self.find(key, false)?;
+        Some(SharedEntryGuard { table: self.table, entry, _marker: PhantomData })
+    }
+
+    /// Look up `key`, holding its partition's lock in exclusive mode while the returned guard lives
+    pub fn get_mut(&mut self, key: &K) -> Option<ExclusiveEntryGuard<'_, K, V>> {
+        let entry = self.find(key, true)?;
+        Some(ExclusiveEntryGuard { table: self.table, entry, _marker: PhantomData })
+    }
+
+    fn find(&self, key: &K, exclusive: bool) -> Option<NonNull<Entry<K, V>>> {
+        // SAFETY: our table is attached, and `key` is a `key_size`d `K`
+        let entry = unsafe {
+            pg_sys::dshash_find(self.table.as_ptr(), (key as *const K).cast(), exclusive)
+        };
+        NonNull::new(entry.cast())
+    }
+
+    /// Get the entry for `key`, for in-place insertion or modification.  Its partition is locked
+    /// in exclusive mode until the entry, or the guard it becomes, is dropped.
+    pub fn entry(&mut self, key: K) -> MapEntry<'_, K, V> {
+        let mut found = false;
+        // SAFETY: our table is attached, and `key` is a `key_size`d `K`.  `dshash_find_or_insert()`
+        // raises an ERROR rather than return NULL
+        let entry = unsafe {
+            let entry = pg_sys::dshash_find_or_insert(
+                self.table.as_ptr(),
+                (&key as *const K).cast(),
+                &mut found,
+            );
+            NonNull::new_unchecked(entry.cast::<Entry<K, V>>())
+        };
+        let guard = ExclusiveEntryGuard { table: self.table, entry, _marker: PhantomData };
+        if found {
+            MapEntry::Occupied(guard)
+        } else {
+            // the new entry's key is a bitwise copy of ours, so it's now the entry's to drop
+            std::mem::forget(key);
+            MapEntry::Vacant(VacantEntry { guard: Some(guard) })
+        }
+    }
+
+    /// Insert `value` for `key`, returning the value it replaced, if any
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.entry(key) {
+            MapEntry::Occupied(mut guard) => Some(std::mem::replace(&mut *guard, value)),
+            MapEntry::Vacant(vacant) => {
+                vacant.insert(value);
+                None
+            }
+        }
+    }
+
+    /// Remove `key` from the map, returning its value, if it had one
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.get_mut(key).map(ExclusiveEntryGuard::remove)
+    }
+
+    /// Call `f` with every entry in the map, holding each partition's lock in shared mode while
+    /// `f` looks at its entries.  Entries added or removed during the scan may or may not be seen.
+    ///
+    /// Requires Postgres 15 or later.
+    #[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14")))]
+    pub fn for_each<F: FnMut(&K, &V)>(&mut self, mut f: F) {
+        let mut scan = Scan::new(self.table, false);
+        while let Some(entry) = scan.next::<K, V>() {
+            // SAFETY: the entry's partition is locked until the next call to `next()`
+            let entry = unsafe { entry.as_ref() };
+            f(&entry.key, &entry.value);
+        }
+    }
+
+    /// Keep only the entries for which `f` returns `true`, holding each partition's lock in
+    /// exclusive mode while `f` looks at its entries.
+    ///
+    /// Requires Postgres 15 or later.
+    #[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14")))]
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
+        let mut scan = Scan::new(self.table, true);
+        while let Some(mut entry) = scan.next::<K, V>() {
+            // SAFETY: the entry's partition is exclusively locked until the next call to `next()`
+            let keep = unsafe {
+                let entry = entry.as_mut();
+                f(&entry.key, &mut entry.value)
+            };
+            if !keep {
+                // SAFETY: `dshash_delete_current()` frees the entry without dropping it
+                unsafe {
+                    entry.as_ptr().drop_in_place();
+                    scan.delete_current();
+                }
+            }
+        }
+    }
+
+    /// Destroy the map and free its memory.  Its entries are not dropped.
+    ///
+    /// # Safety
+    ///
+    /// No other backend may be using, or later attach to, the map.
+    pub unsafe fn destroy(self) {
+        let table = self.table;
+        std::mem::forget(self);
+        // SAFETY: the caller guarantees we're the only ones left using the table
+        unsafe { pg_sys::dshash_destroy(table.as_ptr()) }
+    }
+}
+
+impl<K, V> Drop for PgSharedHashMap<'_, K, V> {
+    fn drop(&mut self) {
+        // SAFETY: we're done with the table, and no guards can outlive us
+        unsafe { pg_sys::dshash_detach(self.table.as_ptr()) }
+    }
+}
+
+/// An entry in a [`PgSharedHashMap`], from [`PgSharedHashMap::entry`]
+pub enum MapEntry<'m, K, V> {
+    Occupied(ExclusiveEntryGuard<'m, K, V>),
+    Vacant(VacantEntry<'m, K, V>),
+}
+
+impl<'m, K, V> MapEntry<'m, K, V> {
+    /// Get the entry's value, first inserting `value` if it's vacant
+    pub fn or_insert(self, value: V) -> ExclusiveEntryGuard<'m, K, V> {
+        self.or_insert_with(|| value)
+    }
+
+    /// Get the entry's value, first inserting the result of `f` if it's vacant
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> ExclusiveEntryGuard<'m, K, V> {
+        match self {
+            MapEntry::Occupied(guard) => guard,
+            MapEntry::Vacant(vacant) => vacant.insert(f()),
+        }
+    }
+
+    /// Get the entry's value, first inserting `V::default()` if it's vacant
+    pub fn or_default(self) -> ExclusiveEntryGuard<'m, K, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn key(&self) -> &K {
+        match self {
+            MapEntry::Occupied(guard) => guard.key(),
+            MapEntry::Vacant(vacant) => vacant.key(),
+        }
+    }
+}
+
+/// A key that's not yet in a [`PgSharedHashMap`].  Its partition stays locked until a value is
+/// inserted, or until it's dropped, which removes the key again.
+pub struct VacantEntry<'m, K, V> {
+    // the entry's value is uninitialized until `insert()`
+    guard: Option<ExclusiveEntryGuard<'m, K, V>>,
+}
+
+impl<'m, K, V> VacantEntry<'m, K, V> {
+    pub fn key(&self) -> &K {
+        self.guard.as_ref().expect("VacantEntry has already been inserted").key()
+    }
+
+    /// Set the entry's value, returning a guard for it
+    pub fn insert(mut self, value: V) -> ExclusiveEntryGuard<'m, K, V> {
+        let guard = self.guard.take().expect("VacantEntry has already been inserted");
+        // SAFETY: the entry's partition is exclusively locked, and its value is uninitialized
+        unsafe { std::ptr::addr_of_mut!((*guard.entry.as_ptr()).value).write(value) };
+        guard
+    }
+}
+
+impl<K, V> Drop for VacantEntry<'_, K, V> {
+    fn drop(&mut self) {
+        if let Some(guard) = self.guard.take() {
+            let (table, entry) = (guard.table, guard.entry);
+            std::mem::forget(guard);
+            // SAFETY: the entry's value was never initialized, so only its key needs dropping.
+            // `dshash_delete_entry()` also releases the partition lock
+            unsafe {
+                std::ptr::addr_of_mut!((*entry.as_ptr()).key).drop_in_place();
+                pg_sys::dshash_delete_entry(table.as_ptr(), entry.as_ptr().cast());
+            }
+        }
+    }
+}
+
+/// A value in a [`PgSharedHashMap`], whose partition is locked in shared mode until it's dropped
+pub struct SharedEntryGuard<'m, K, V> {
+    table: NonNull<pg_sys::dshash_table>,
+    entry: NonNull<Entry<K, V>>,
+    _marker: PhantomData<&'m (K, V)>,
+}
+
+impl<K, V> SharedEntryGuard<'_, K, V> {
+    pub fn key(&self) -> &K {
+        // SAFETY: the entry's partition is locked
+        unsafe { &self.entry.as_ref().key }
+    }
+}
+
+impl<K, V> Deref for SharedEntryGuard<'_, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // SAFETY: the entry's partition is locked
+        unsafe { &self.entry.as_ref().value }
+    }
+}
+
+impl<K, V> Drop for SharedEntryGuard<'_, K, V> {
+    fn drop(&mut self) {
+        // SAFETY: we hold the entry's partition lock
+        unsafe { pg_sys::dshash_release_lock(self.table.as_ptr(), self.entry.as_ptr().cast()) }
+    }
+}
+
+/// A value in a [`PgSharedHashMap`], whose partition is locked in exclusive mode until it's dropped
+pub struct ExclusiveEntryGuard<'m, K, V> {
+    table: NonNull<pg_sys::dshash_table>,
+    entry: NonNull<Entry<K, V>>,
+    _marker: PhantomData<&'m (K, V)>,
+}
+
+impl<K, V> ExclusiveEntryGuard<'_, K, V> {
+    pub fn key(&self) -> &K {
+        // SAFETY: the entry's partition is locked
+        unsafe { &self.entry.as_ref().key }
+    }
+
+    /// Remove the entry from the map, returning its value
+    pub fn remove(self) -> V {
+        let (table, entry) = (self.table, self.entry);
+        std::mem::forget(self);
+        // SAFETY: the entry's partition is exclusively locked.  `dshash_delete_entry()` frees
+        // the entry without dropping it, and releases the lock
+        unsafe {
+            let value = std::ptr::addr_of!((*entry.as_ptr()).value).read();
+            std::ptr::addr_of_mut!((*entry.as_ptr()).key).drop_in_place();
+            pg_sys::dshash_delete_entry(table.as_ptr(), entry.as_ptr().cast());
+            value
+        }
+    }
+}
+
+impl<K, V> Deref for ExclusiveEntryGuard<'_, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // SAFETY: the entry's partition is locked
+        unsafe { &self.entry.as_ref().value }
+    }
+}
+
+impl<K, V> DerefMut for ExclusiveEntryGuard<'_, K, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        // SAFETY: the entry's partition is exclusively locked
+        unsafe { &mut self.entry.as_mut().value }
+    }
+}
+
+impl<K, V> Drop for ExclusiveEntryGuard<'_, K, V> {
+    fn drop(&mut self) {
+        // SAFETY: we hold the entry's partition lock
+        unsafe { pg_sys::dshash_release_lock(self.table.as_ptr(), self.entry.as_ptr().cast()) }
+    }
+}
+
+/// A sequential scan over a table, which ends the scan, releasing any lock it holds, when dropped
+#[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14")))]
+struct Scan {
+    status: pg_sys::dshash_seq_status,
+}
+
+#[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14")))]
+impl Scan {
+    fn new(table: NonNull<pg_sys::dshash_table>, exclusive: bool) -> Self {
+        let mut scan = Self { status: Default::default() };
+        // SAFETY: the table is attached
+        unsafe { pg_sys::dshash_seq_init(&mut scan.status, table.as_ptr(), exclusive) };
+        scan
+    }
+
+    fn next<K, V>(&mut self) -> Option<NonNull<Entry<K, V>>> {
+        // SAFETY: the scan has been initialized and not terminated
+        NonNull::new(unsafe { pg_sys::dshash_seq_next(&mut self.status) }.cast())
+    }
+
+    /// # Safety
+    ///
+    /// The scan must be exclusive, and positioned on an entry
+    unsafe fn delete_current(&mut self) {
+        // SAFETY: upheld by the caller
+        unsafe { pg_sys::dshash_delete_current(&mut self.status) }
+    }
+}
+
+#[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14")))]
+impl Drop for Scan {
+    fn drop(&mut self) {
+        // SAFETY: the scan has been initialized, and this is the only place we terminate it
+        unsafe { pg_sys::dshash_seq_term(&mut self.status) }
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn compare<K: Eq>(
+    a: *const c_void,
+    b: *const c_void,
+    _size: usize,
+    _arg: *mut c_void,
+) -> c_int {
+    // SAFETY: `dshash` only ever compares keys, which are `K`s
+    let (a, b) = unsafe { (&*a.cast::<K>(), &*b.cast::<K>()) };
+    if a == b {
+        0
+    } else {
+        1
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn hash<K: Hash>(v: *const c_void, _size: usize, _arg: *mut c_void) -> u32 {
+    // `DefaultHasher::new()` always uses the same keys, so every backend agrees on the hash
+    let mut hasher = DefaultHasher::new();
+    // SAFETY: `dshash` only ever hashes keys, which are `K`s
+    unsafe { &*v.cast::<K>() }.hash(&mut hasher);
+    let hash = hasher.finish();
+    (hash ^ (hash >> 32)) as u32
+}
+
+#[cfg(feature = "pg17")]
+#[pg_guard]
+unsafe extern "C" fn copy(dest: *mut c_void, src: *const c_void, size: usize, _arg: *mut c_void) {
+    // SAFETY: `dshash` copies keys into new entries, which don't overlap them
+    unsafe { std::ptr::copy_nonoverlapping(src.cast::<u8>(), dest.cast::<u8>(), size) }
+}
@@ -0,0 +1,520 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Dynamic shared memory, which can be created at any time rather than only during
+//! `shared_preload_libraries`
+//!
+//! A [`DsmSegment`] is a single, fixed-size block of shared memory, and a [`DsaArea`] is a
+//! shared-memory allocator built on top of DSM segments, handing out [`DsaPointer`]s which are
+//! valid in every backend attached to the area.  Other backends find a segment or area by its
+//! handle, which can be passed along through a [`crate::PgAtomic`] in `pg_shmem_init!` shared
+//! memory, a table, a background worker's argument, or on Postgres 17, a [`named_segment`].
+//!
+//! Like in Postgres, a new mapping belongs to `CurrentResourceOwner`, so it's detached at the end
+//! of the transaction, even if the Rust value is leaked.  Accessing a segment or area after
+//! that panics.  Call `pin_mapping()` to keep it mapped until it's dropped, or until the backend
+//! exits.  Independently, a segment or area is destroyed when the last backend detaches from it,
+//! unless it's been `pin()`ed.
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate as pgrx; // for #[pg_guard] support from within ourself
+use crate::memcxt::PgMemoryContexts;
+use crate::pg_sys;
+use crate::shmem::PGRXSharedMemory;
+use pgrx_macros::pg_guard;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+
+/// A mapping of a dynamic shared memory segment into this backend
+pub struct DsmSegment {
+    seg: NonNull<pg_sys::dsm_segment>,
+    attachment: Attachment,
+}
+
+impl DsmSegment {
+    /// Create a new segment of `size` bytes.
+    pub fn create(size: usize) -> Self {
+        // SAFETY: `dsm_create()` will `ereport(ERROR)` rather than return NULL without
+        // `DSM_CREATE_NULL_IF_MAXSEGMENTS`
+        unsafe { Self::from_raw(pg_sys::dsm_create(size, 0)) }
+    }
+
+    /// Attach to the existing segment identified by `handle`.
+    ///
+    /// Returns `None` if the segment no longer exists.  If this backend is already attached
+    /// to it, another [`DsmSegment`] can't be created for it, so Postgres raises an `ERROR`.
+    pub fn attach(handle: pg_sys::dsm_handle) -> Option<Self> {
+        // SAFETY: `dsm_attach()` returns NULL if the segment is gone
+        let seg = unsafe { pg_sys::dsm_attach(handle) };
+        if seg.is_null() {
+            None
+        } else {
+            // SAFETY: we just attached to `seg`
+            Some(unsafe { Self::from_raw(seg) })
+        }
+    }
+
+    unsafe fn from_raw(seg: *mut pg_sys::dsm_segment) -> Self {
+        let seg = NonNull::new(seg).expect("dsm_segment should not be NULL");
+        // SAFETY: the caller gives us a segment that's currently attached
+        let attachment = unsafe { Attachment::track(seg.as_ptr()) };
+        Self { seg, attachment }
+    }
+
+    pub(crate) fn is_attached(&self) -> bool {
+        self.attachment.is_attached()
+    }
+
+    pub(crate) fn as_raw(&self) -> *mut pg_sys::dsm_segment {
+        assert!(
+            self.attachment.is_attached(),
+            "dynamic shared memory segment was detached when its resource owner was released"
+        );
+        self.seg.as_ptr()
+    }
+
+    /// The handle other backends can use to [`DsmSegment::attach`] to this segment
+    pub fn handle(&self) -> pg_sys::dsm_handle {
+        // SAFETY: the segment is attached
+        unsafe { pg_sys::dsm_segment_handle(self.as_raw()) }
+    }
+
+    /// The size of the segment, in bytes
+    pub fn len(&self) -> usize {
+        // SAFETY: the segment is attached
+        unsafe { pg_sys::dsm_segment_map_length(self.as_raw()) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The address of the start of the segment in this backend.  It's at least
+    /// `MAXIMUM_ALIGNOF`-aligned and generally differs between backends.
+    pub fn as_ptr(&self) -> *mut u8 {
+        // SAFETY: the segment is attached
+        unsafe { pg_sys::dsm_segment_address(self.as_raw()).cast() }
+    }
+
+    /// View the segment as bytes.
+    ///
+    /// # Safety
+    ///
+    /// Other backends may be writing to the segment at the same time.  The caller must
+    /// coordinate with them, e.g. with a lock or atomics.
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        // SAFETY: the segment is attached and `len()` bytes long
+        unsafe { std::slice::from_raw_parts(self.as_ptr(), self.len()) }
+    }
+
+    /// View the segment as mutable bytes.
+    ///
+    /// # Safety
+    ///
+    /// Other backends may be reading or writing the segment at the same time.  The caller must
+    /// coordinate with them, e.g. with a lock or atomics.
+    pub unsafe fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: the segment is attached and `len()` bytes long
+        unsafe { std::slice::from_raw_parts_mut(self.as_ptr(), self.len()) }
+    }
+
+    /// Keep the segment mapped until this value is dropped, rather than only until the current
+    /// resource owner is released.
+    pub fn pin_mapping(&mut self) {
+        // SAFETY: the segment is attached
+        unsafe { pg_sys::dsm_pin_mapping(self.as_raw()) }
+    }
+
+    /// Keep the segment in existence even after every backend has detached from it, until
+    /// [`DsmSegment::unpin`] or the server restarts.
+    pub fn pin(&self) {
+        // SAFETY: the segment is attached
+        unsafe { pg_sys::dsm_pin_segment(self.as_raw()) }
+    }
+
+    /// Undo a [`DsmSegment::pin`], which may have happened in another backend.  The segment
+    /// is destroyed once no backend is attached to it.
+    pub fn unpin(handle: pg_sys::dsm_handle) {
+        // SAFETY: Postgres raises an ERROR if `handle` isn't a pinned segment
+        unsafe { pg_sys::dsm_unpin_segment(handle) }
+    }
+}
+
+impl Drop for DsmSegment {
+    fn drop(&mut self) {
+        if self.attachment.is_attached() {
+            // SAFETY: the segment is still attached, and we're done with it
+            unsafe {
+                self.attachment.untrack(self.seg.as_ptr());
+                pg_sys::dsm_detach(self.seg.as_ptr());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DsmSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("DsmSegment");
+        if self.attachment.is_attached() {
+            debug.field("handle", &self.handle()).field("len", &self.len());
+        } else {
+            debug.field("attached", &false);
+        }
+        debug.finish()
+    }
+}
+
+/// A shared memory allocator, whose memory is spread over any number of DSM segments
+pub struct DsaArea {
+    area: NonNull<pg_sys::dsa_area>,
+    attachment: Attachment,
+}
+
+impl DsaArea {
+    /// Create a new area.  Its LWLocks are registered under `tranche_name` in this backend,
+    /// which is what `pg_stat_activity` reports backends waiting on them as.
+    pub fn create(tranche_name: &'static CStr) -> Self {
+        let tranche_id = lwlock_tranche(tranche_name);
+        // SAFETY: `dsa_create()` raises an ERROR rather than return NULL, and the `dsa_area`
+        // it allocates must outlive the current memory context
+        unsafe {
+            PgMemoryContexts::TopMemoryContext.switch_to(|_| {
+                #[cfg(not(feature = "pg17"))]
+                let area = pg_sys::dsa_create(tranche_id);
+                #[cfg(feature = "pg17")]
+                let area = pg_sys::dsa_create_ext(
+                    tranche_id,
+                    DSA_DEFAULT_INIT_SEGMENT_SIZE,
+                    DSA_MAX_SEGMENT_SIZE,
+                );
+                Self::from_raw(area)
+            })
+        }
+    }
+
+    /// Attach to the existing area identified by `handle`.  Postgres raises an `ERROR` if the
+    /// area no longer exists, or if this backend is already attached to it.
+    pub fn attach(handle: pg_sys::dsa_handle) -> Self {
+        // SAFETY: `dsa_attach()` raises an ERROR rather than return NULL, and the `dsa_area`
+        // it allocates must outlive the current memory context
+        unsafe {
+            PgMemoryContexts::TopMemoryContext
+                .switch_to(|_| Self::from_raw(pg_sys::dsa_attach(handle)))
+        }
+    }
+
+    unsafe fn from_raw(area: *mut pg_sys::dsa_area) -> Self {
+        let area = NonNull::new(area).expect("dsa_area should not be NULL");
+        // SAFETY: an area's handle is the handle of its control segment, which stays mapped
+        // exactly as long as the area does
+        let attachment = unsafe {
+            let control = pg_sys::dsm_find_mapping(pg_sys::dsa_get_handle(area.as_ptr()));
+            Attachment::track(control)
+        };
+        Self { area, attachment }
+    }
+
+    pub(crate) fn as_raw(&self) -> *mut pg_sys::dsa_area {
+        assert!(
+            self.attachment.is_attached(),
+            "dynamic shared memory area was detached when its resource owner was released"
+        );
+        self.area.as_ptr()
+    }
+
+    /// The handle other backends can use to [`DsaArea::attach`] to this area
+    pub fn handle(&self) -> pg_sys::dsa_handle {
+        // SAFETY: the area is attached
+        unsafe { pg_sys::dsa_get_handle(self.as_raw()) }
+    }
+
+    /// Keep the area mapped until this value is dropped, rather than only until the current
+    /// resource owner is released.
+    pub fn pin_mapping(&mut self) {
+        // SAFETY: the area is attached
+        unsafe { pg_sys::dsa_pin_mapping(self.as_raw()) }
+    }
+
+    /// Keep the area in existence even after every backend has detached from it, until
+    /// [`DsaArea::unpin`] or the server restarts.  Postgres raises an `ERROR` if it's
+    /// already pinned.
+    pub fn pin(&self) {
+        // SAFETY: the area is attached
+        unsafe { pg_sys::dsa_pin(self.as_raw()) }
+    }
+
+    /// Undo a [`DsaArea::pin`], which may have happened in another backend
+    pub fn unpin(&self) {
+        // SAFETY: the area is attached
+        unsafe { pg_sys::dsa_unpin(self.as_raw()) }
+    }
+
+    /// Limit the total size of the area's segments to `limit` bytes, after which allocations fail
+    pub fn set_size_limit(&self, limit: usize) {
+        // SAFETY: the area is attached
+        unsafe { pg_sys::dsa_set_size_limit(self.as_raw(), limit) }
+    }
+
+    /// Give memory that's no longer in use back to the operating system
+    pub fn trim(&self) {
+        // SAFETY: the area is attached
+        unsafe { pg_sys::dsa_trim(self.as_raw()) }
+    }
+
+    /// Move `value` into the area, returning a pointer that's valid in every backend attached
+    /// to it.  Postgres raises an `ERROR` if the area is out of memory.
+    ///
+    /// # Panics
+    ///
+    /// If `T` is zero-sized, as DSA can't allocate nothing, or must be aligned to more than
+    /// `MAXIMUM_ALIGNOF`
+    pub fn allocate<T: PGRXSharedMemory>(&self, value: T) -> DsaPointer<T> {
+        assert!(std::mem::size_of::<T>() > 0, "DSA can't allocate zero-sized types");
+        assert!(
+            std::mem::align_of::<T>() <= pg_sys::MAXIMUM_ALIGNOF as usize,
+            "DSA allocations can't be aligned to more than MAXIMUM_ALIGNOF"
+        );
+        // SAFETY: the area is attached, and Postgres raises an ERROR rather than returning an
+        // invalid pointer.  The new allocation is suitably sized and aligned for a `T`
+        unsafe {
+            let dp = pg_sys::dsa_allocate_extended(self.as_raw(), std::mem::size_of::<T>(), 0);
+            let pointer = DsaPointer::from_raw(dp);
+            self.as_ptr(pointer).write(value);
+            pointer
+        }
+    }
+
+    /// Get a reference to the value `pointer` points to.  Other backends may be using it at the
+    /// same time, so it's only ever shared, and anything mutable needs to be behind atomics
+    /// or a lock.
+    ///
+    /// # Panics
+    ///
+    /// If `pointer` is [`DsaPointer::invalid`]
+    pub fn get<T: PGRXSharedMemory>(&self, pointer: DsaPointer<T>) -> &T {
+        assert!(pointer.is_valid(), "DsaPointer is invalid");
+        // SAFETY: `pointer` came from `allocate()` on this area, which initialized it
+        unsafe { &*self.as_ptr(pointer) }
+    }
+
+    /// The address in this backend of the value `pointer` points to, or NULL if it's
+    /// [`DsaPointer::invalid`]
+    pub fn as_ptr<T>(&self, pointer: DsaPointer<T>) -> *mut T {
+        // SAFETY: the area is attached, and `dsa_get_address()` returns NULL for an invalid pointer
+        unsafe { pg_sys::dsa_get_address(self.as_raw(), pointer.into_raw()).cast() }
+    }
+
+    /// Drop the value `pointer` points to and return its memory to the area.
+    ///
+    /// # Safety
+    ///
+    /// `pointer` must have come from this area, and no copies of it can be used afterwards, in
+    /// this backend or any other.
+    pub unsafe fn free<T: PGRXSharedMemory>(&self, pointer: DsaPointer<T>) {
+        // SAFETY: the caller guarantees `pointer` is live and ours
+        unsafe {
+            self.as_ptr(pointer).drop_in_place();
+            pg_sys::dsa_free(self.as_raw(), pointer.into_raw());
+        }
+    }
+}
+
+impl Drop for DsaArea {
+    fn drop(&mut self) {
+        // SAFETY: we're done with the area.  If it's already been detached, its segments are
+        // gone and only the backend-local `dsa_area` is left to free
+        unsafe {
+            if self.attachment.is_attached() {
+                let control = pg_sys::dsm_find_mapping(pg_sys::dsa_get_handle(self.area.as_ptr()));
+                self.attachment.untrack(control);
+                pg_sys::dsa_detach(self.area.as_ptr());
+            } else {
+                pg_sys::pfree(self.area.as_ptr().cast());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DsaArea {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("DsaArea");
+        if self.attachment.is_attached() {
+            debug.field("handle", &self.handle());
+        } else {
+            debug.field("attached", &false);
+        }
+        debug.finish()
+    }
+}
+
+/// A pointer to a `T` in a [`DsaArea`], which is valid in any backend attached to the area.
+///
+/// It's `Copy` and can itself be stored in shared memory, e.g. to build linked structures.
+#[repr(transparent)]
+pub struct DsaPointer<T> {
+    dp: pg_sys::dsa_pointer,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> DsaPointer<T> {
+    /// The equivalent of Postgres' `InvalidDsaPointer`
+    pub const fn invalid() -> Self {
+        Self { dp: 0, _marker: PhantomData }
+    }
+
+    /// Wrap a raw `dsa_pointer`, which must point to a `T` (or be invalid)
+    pub const fn from_raw(dp: pg_sys::dsa_pointer) -> Self {
+        Self { dp, _marker: PhantomData }
+    }
+
+    pub const fn into_raw(self) -> pg_sys::dsa_pointer {
+        self.dp
+    }
+
+    pub const fn is_valid(&self) -> bool {
+        self.dp != 0
+    }
+}
+
+impl<T> Clone for DsaPointer<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for DsaPointer<T> {}
+
+impl<T> PartialEq for DsaPointer<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.dp == other.dp
+    }
+}
+
+impl<T> Eq for DsaPointer<T> {}
+
+impl<T> Default for DsaPointer<T> {
+    fn default() -> Self {
+        Self::invalid()
+    }
+}
+
+impl<T> fmt::Debug for DsaPointer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DsaPointer({:#x})", self.dp)
+    }
+}
+
+// SAFETY: a `dsa_pointer` is an offset into the area, not an address, so it means the same thing
+// in every backend
+unsafe impl<T> PGRXSharedMemory for DsaPointer<T> {}
+unsafe impl<T> Send for DsaPointer<T> {}
+unsafe impl<T> Sync for DsaPointer<T> {}
+
+/// Get the `T` in the segment named `name`, creating it with `T::default()` if this is the first
+/// time any backend has asked for it since the server started.
+///
+/// Unlike `pg_shmem_init!`, this doesn't require `shared_preload_libraries`.  The segment stays
+/// mapped for the rest of this backend's life.  Postgres raises an `ERROR` if a segment named
+/// `name` exists with a different size, or if `name` is longer than 63 bytes.
+#[cfg(feature = "pg17")]
+pub fn named_segment<T: PGRXSharedMemory + Default>(name: &CStr) -> &'static T {
+    #[pg_guard]
+    unsafe extern "C" fn init<T: Default>(ptr: *mut std::ffi::c_void) {
+        // SAFETY: Postgres gives us a new, suitably sized and aligned segment, while holding
+        // `DSMRegistryLock` so no other backend sees it yet
+        unsafe { ptr.cast::<T>().write(T::default()) }
+    }
+
+    assert!(
+        std::mem::align_of::<T>() <= pg_sys::MAXIMUM_ALIGNOF as usize,
+        "named segments can't be aligned to more than MAXIMUM_ALIGNOF"
+    );
+    let mut found = false;
+    // SAFETY: Postgres either finds the segment or creates and initializes it with `init`, and
+    // pins its mapping, so the `T` lives as long as this backend
+    unsafe {
+        let ptr = pg_sys::GetNamedDSMSegment(
+            name.as_ptr(),
+            std::mem::size_of::<T>(),
+            Some(init::<T>),
+            &mut found,
+        );
+        &*ptr.cast::<T>()
+    }
+}
+
+thread_local! {
+    static TRANCHES: RefCell<HashMap<&'static CStr, i32>> = RefCell::new(HashMap::new());
+}
+
+/// The LWLock tranche id named `name` in this backend, allocating it the first time
+///
+/// Tranche ids come from a counter in shared memory that never goes down, so each name only
+/// takes one per backend, however many areas and maps use it.
+pub(crate) fn lwlock_tranche(name: &'static CStr) -> i32 {
+    TRANCHES.with_borrow_mut(|tranches| {
+        *tranches.entry(name).or_insert_with(|| {
+            // SAFETY: the tranche id is new, and `name` lives forever
+            unsafe {
+                let tranche_id = pg_sys::LWLockNewTrancheId();
+                pg_sys::LWLockRegisterTranche(tranche_id, name.as_ptr());
+                tranche_id
+            }
+        })
+    })
+}
+
+// `dsa.h` defines these as macros
+#[cfg(feature = "pg17")]
+const DSA_DEFAULT_INIT_SEGMENT_SIZE: usize = 1024 * 1024;
+#[cfg(feature = "pg17")]
+const DSA_MAX_SEGMENT_SIZE: usize = 1 << pg_sys::DSA_OFFSET_WIDTH;
+
+/// Tracks whether Postgres has detached a segment out from under us, which it does when the
+/// resource owner the mapping belongs to is released
+struct Attachment(Box<Cell<bool>>);
+
+impl Attachment {
+    /// # Safety
+    ///
+    /// `seg` must be attached
+    unsafe fn track(seg: *mut pg_sys::dsm_segment) -> Self {
+        let attachment = Self(Box::new(Cell::new(true)));
+        // SAFETY: the `Cell` is boxed, so its address is stable until we're dropped, and we
+        // `untrack()` before then if the segment is still attached
+        unsafe { pg_sys::on_dsm_detach(seg, Some(mark_detached), attachment.arg()) };
+        attachment
+    }
+
+    /// # Safety
+    ///
+    /// `seg` must be the still-attached segment we were tracking
+    unsafe fn untrack(&self, seg: *mut pg_sys::dsm_segment) {
+        // SAFETY: the caller guarantees `seg` is attached
+        unsafe { pg_sys::cancel_on_dsm_detach(seg, Some(mark_detached), self.arg()) };
+    }
+
+    fn is_attached(&self) -> bool {
+        self.0.get()
+    }
+
+    fn arg(&self) -> pg_sys::Datum {
+        pg_sys::Datum::from(&*self.0 as *const Cell<bool>)
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn mark_detached(_seg: *mut pg_sys::dsm_segment, arg: pg_sys::Datum) {
+    // SAFETY: `arg` is the `Cell` from `Attachment::track()`, which is still alive or we'd have
+    // cancelled this callback
+    unsafe { (*arg.cast_mut_ptr::<Cell<bool>>()).set(false) }
+}
@@ -0,0 +1,322 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Running queries through the planner and executor directly, without SPI
+//!
+//! [`Spi`](crate::spi::Spi) copies every row of a result into a tuple table before handing it
+//! back.  [`execute`] instead plans the query with `pg_plan_query` and runs it with
+//! `ExecutorStart`/`ExecutorRun`/`ExecutorEnd`, handing each row to a [`Receiver`] as a
+//! `TupleTableSlot` the moment the executor produces it.  Nothing is copied unless the receiver
+//! copies it, and the receiver can stop the query early.
+//!
+//! This is a lower-level API than SPI: there is no connection, no plan caching, and a slot is only
+//! valid until the receiver returns.  Errors raised while the query runs abort the transaction, as
+//! they would anywhere else.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pgrx::executor;
+//! use pgrx::PgTupleSlot;
+//! use std::num::NonZeroUsize;
+//!
+//! let mut pages = 0;
+//! let relpages = NonZeroUsize::new(1).unwrap();
+//! let mut receiver = executor::slots(|slot: &mut PgTupleSlot| {
+//!     pages += slot.get::<i32>(relpages).unwrap().unwrap_or_default() as i64;
+//!     true
+//! });
+//! executor::execute("SELECT relpages FROM pg_class", &[], &mut receiver).unwrap();
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+
+use alloc::ffi::CString;
+use core::ffi::c_int;
+use core::ptr;
+
+use pgrx_macros::pg_guard;
+
+use crate as pgrx;
+use crate::datum::DatumWithOid;
+use crate::list::List;
+use crate::memcx;
+use crate::{pg_sys, PgMemoryContexts, PgTupleDesc, PgTupleSlot};
+
+/// Errors that keep [`execute`] from running a query
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteError {
+    /// The query string held no statements, or more than one
+    #[error("expected exactly one statement, found {0}")]
+    NotOneStatement(usize),
+
+    /// Utility statements, such as DDL or `COPY`, don't go through the executor
+    #[error("utility statements can't be run by the executor")]
+    UtilityStatement,
+}
+
+/// Receives the rows of a query from [`execute`] as the executor produces them
+///
+/// A closure taking `&mut pg_sys::TupleTableSlot` and returning `bool` is a `Receiver`.  To read
+/// rows as [`PgTupleSlot`]s instead, wrap a closure with [`slots`].
+pub trait Receiver {
+    /// Called once before the first row, with the shape of the rows to come
+    fn startup(&mut self, _tupdesc: &PgTupleDesc<'_>) {}
+
+    /// Called for each row
+    ///
+    /// The slot, and any pass-by-reference values read from it, are only valid until this
+    /// returns.  Returning `false` stops the query: no more rows are produced.
+    fn receive(&mut self, slot: &mut pg_sys::TupleTableSlot) -> bool;
+
+    /// Called once after the last row, unless the query raised an error
+    fn shutdown(&mut self) {}
+}
+
+impl<F> Receiver for F
+where
+    F: FnMut(&mut pg_sys::TupleTableSlot) -> bool,
+{
+    fn receive(&mut self, slot: &mut pg_sys::TupleTableSlot) -> bool {
+        self(slot)
+    }
+}
+
+/// A [`Receiver`] that hands each row to a closure as a [`PgTupleSlot`], from [`slots`]
+pub struct SlotReceiver<F>(F);
+
+/// Receive each row as a [`PgTupleSlot`], passing it to `f`
+///
+/// `f` returns `false` to stop the query, just as [`Receiver::receive`] does.
+pub fn slots<F>(f: F) -> SlotReceiver<F>
+where
+    F: FnMut(&mut PgTupleSlot<'_>) -> bool,
+{
+    SlotReceiver(f)
+}
+
+impl<F> Receiver for SlotReceiver<F>
+where
+    F: FnMut(&mut PgTupleSlot<'_>) -> bool,
+{
+    fn receive(&mut self, slot: &mut pg_sys::TupleTableSlot) -> bool {
+        // SAFETY: the executor's slot is valid until we return, and we don't take ownership of it
+        (self.0)(&mut unsafe { PgTupleSlot::from_pg(slot) })
+    }
+}
+
+/// Plan and run `query`, passing each row it produces to `receiver`
+///
+/// `args` are bound to `$1`, `$2`, and so on, and are planned as constants.  The query must be a
+/// single, non-utility statement; it may modify data, and if it has a `RETURNING` clause, the
+/// returned rows go to `receiver`.
+///
+/// Returns the number of rows the statement processed, as SPI would count them.  That may be more
+/// than `receiver` saw, if it stopped the query early.
+pub fn execute<R: Receiver>(
+    query: &str,
+    args: &[DatumWithOid<'_>],
+    receiver: &mut R,
+) -> Result<u64, ExecuteError> {
+    // the parse trees, plans, and parameters all live in this context, and go away with it
+    let mut cx = PgMemoryContexts::new("pgrx::executor::execute");
+
+    // SAFETY: we only switch to a context we own, and nothing allocated in it escapes the closure
+    unsafe { cx.switch_to(|_| execute_in_context(query, args, receiver)) }
+}
+
+/// # Safety
+/// Allocates in, and expects to be called in, a memory context that lasts only as long as this call
+unsafe fn execute_in_context(
+    query: &str,
+    args: &[DatumWithOid<'_>],
+    receiver: &mut dyn Receiver,
+) -> Result<u64, ExecuteError> {
+    let sql = CString::new(query).expect("query contained a null byte");
+    let mut oids = args.iter().map(DatumWithOid::oid).collect::<Vec<_>>();
+    let nargs = args.len() as c_int;
+
+    // SAFETY: each call below is given pointers it expects, allocated in the current context,
+    // which lives until we return.  Lists are only downcast to the node pointers they hold.
+    unsafe {
+        let params = if args.is_empty() {
+            ptr::null_mut()
+        } else {
+            let params = pg_sys::makeParamList(nargs);
+            let slots = (*params).params.as_mut_slice(args.len());
+            for (slot, arg) in slots.iter_mut().zip(args) {
+                let (value, isnull) = match arg.datum() {
+                    Some(datum) => (datum.sans_lifetime(), false),
+                    None => (pg_sys::Datum::from(0), true),
+                };
+                *slot = pg_sys::ParamExternData {
+                    value,
+                    isnull,
+                    pflags: pg_sys::PARAM_FLAG_CONST as _,
+                    ptype: arg.oid(),
+                };
+            }
+            params
+        };
+
+        let raw = memcx::current_context(|cx| {
+            let stmts = List::<*mut core::ffi::c_void>::downcast_ptr_in_memcx(
+                pg_sys::pg_parse_query(sql.as_ptr()),
+                cx,
+            )
+            .expect("pg_parse_query returned a List of non-pointers");
+            match stmts.len() {
+                1 => Ok(stmts.get(0).copied().unwrap().cast::<pg_sys::RawStmt>()),
+                n => Err(ExecuteError::NotOneStatement(n)),
+            }
+        })?;
+
+        #[cfg(any(feature = "pg12", feature = "pg13", feature = "pg14"))]
+        let queries = pg_sys::pg_analyze_and_rewrite(
+            raw,
+            sql.as_ptr(),
+            oids.as_mut_ptr(),
+            nargs,
+            ptr::null_mut(),
+        );
+        #[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14")))]
+        let queries = pg_sys::pg_analyze_and_rewrite_fixedparams(
+            raw,
+            sql.as_ptr(),
+            oids.as_mut_ptr(),
+            nargs,
+            ptr::null_mut(),
+        );
+
+        let queries = memcx::current_context(|cx| {
+            List::<*mut core::ffi::c_void>::downcast_ptr_in_memcx(queries, cx)
+                .expect("pg_analyze_and_rewrite returned a List of non-pointers")
+                .iter()
+                .map(|query| query.cast::<pg_sys::Query>())
+                .collect::<Vec<_>>()
+        });
+
+        // a rule may rewrite one statement into several, but none may be a utility statement
+        if queries.iter().any(|&query| (*query).commandType == pg_sys::CmdType::CMD_UTILITY) {
+            return Err(ExecuteError::UtilityStatement);
+        }
+
+        let mut dest = RustDestReceiver {
+            dest: pg_sys::DestReceiver {
+                receiveSlot: Some(receive_slot),
+                rStartup: Some(startup),
+                rShutdown: Some(shutdown),
+                rDestroy: Some(destroy),
+                mydest: pg_sys::CommandDest::DestNone,
+            },
+            receiver,
+        };
+
+        let mut processed = 0;
+        for query in queries {
+            let can_set_tag = (*query).canSetTag;
+            #[cfg(feature = "pg12")]
+            let stmt =
+                pg_sys::pg_plan_query(query, pg_sys::CURSOR_OPT_PARALLEL_OK as c_int, params);
+            #[cfg(not(feature = "pg12"))]
+            let stmt = pg_sys::pg_plan_query(
+                query,
+                sql.as_ptr(),
+                pg_sys::CURSOR_OPT_PARALLEL_OK as c_int,
+                params,
+            );
+
+            let read_only = pg_sys::CommandIsReadOnly(stmt);
+            if !read_only {
+                if pg_sys::IsInParallelMode() {
+                    pg_sys::PreventCommandIfParallelMode(c"pgrx::executor::execute".as_ptr());
+                }
+                // let this statement see what the ones before it did
+                pg_sys::CommandCounterIncrement();
+            }
+
+            pg_sys::PushActiveSnapshot(pg_sys::GetTransactionSnapshot());
+            let desc = pg_sys::CreateQueryDesc(
+                stmt,
+                sql.as_ptr(),
+                pg_sys::GetActiveSnapshot(),
+                ptr::null_mut(),
+                ptr::addr_of_mut!(dest.dest),
+                params,
+                ptr::null_mut(),
+                0,
+            );
+
+            pg_sys::ExecutorStart(desc, 0);
+            pg_sys::ExecutorRun(desc, pg_sys::ScanDirection::ForwardScanDirection, 0, true);
+            pg_sys::ExecutorFinish(desc);
+            if can_set_tag {
+                processed = (*(*desc).estate).es_processed;
+            }
+            pg_sys::ExecutorEnd(desc);
+            pg_sys::FreeQueryDesc(desc);
+            pg_sys::PopActiveSnapshot();
+
+            if !read_only {
+                pg_sys::CommandCounterIncrement();
+            }
+        }
+
+        Ok(processed)
+    }
+}
+
+/// A `DestReceiver` that Postgres can call, forwarding to a Rust [`Receiver`]
+///
+/// Postgres only ever sees a pointer to `dest`, which, being the first field, is also a pointer
+/// to the whole struct.
+#[repr(C)]
+struct RustDestReceiver<'a> {
+    dest: pg_sys::DestReceiver,
+    receiver: &'a mut dyn Receiver,
+}
+
+/// # Safety
+/// `self_` must be the `dest` of a live [`RustDestReceiver`]
+unsafe fn receiver_of<'a>(self_: *mut pg_sys::DestReceiver) -> &'a mut dyn Receiver {
+    // SAFETY: the caller guarantees this is a RustDestReceiver, which is repr(C) with dest first
+    unsafe { &mut *(*self_.cast::<RustDestReceiver>()).receiver }
+}
+
+#[pg_guard]
+unsafe extern "C" fn receive_slot(
+    slot: *mut pg_sys::TupleTableSlot,
+    self_: *mut pg_sys::DestReceiver,
+) -> bool {
+    // SAFETY: the executor only calls us with the receiver we gave it, and a valid slot
+    unsafe { receiver_of(self_).receive(&mut *slot) }
+}
+
+#[pg_guard]
+unsafe extern "C" fn startup(
+    self_: *mut pg_sys::DestReceiver,
+    _operation: c_int,
+    typeinfo: pg_sys::TupleDesc,
+) {
+    // SAFETY: as above, and the executor owns `typeinfo`, so we mustn't release it
+    unsafe {
+        let tupdesc = PgTupleDesc::from_pg_unchecked(typeinfo);
+        receiver_of(self_).startup(&tupdesc);
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn shutdown(self_: *mut pg_sys::DestReceiver) {
+    // SAFETY: the executor only calls us with the receiver we gave it
+    unsafe { receiver_of(self_).shutdown() }
+}
+
+#[pg_guard]
+unsafe extern "C" fn destroy(_self: *mut pg_sys::DestReceiver) {
+    // the receiver lives on the stack of `execute_in_context`, so there's nothing to free
+}
@@ -8,10 +8,15 @@
 //LICENSE
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
 //! Provides a safe interface into Postgres' Configuration System (GUC)
+use crate::lock::local_transaction_id;
 use crate::{pg_sys, PgMemoryContexts};
 use core::ffi::CStr;
+use pgrx_macros::pg_guard;
 pub use pgrx_macros::PostgresGucEnum;
 use std::cell::Cell;
+use std::ffi::{c_char, c_int, c_void};
+
+use crate as pgrx;
 
 /// Defines at what level this GUC can be set
 pub enum GucContext {
@@ -113,6 +118,231 @@ where
     unsafe fn config_matrix(&self) -> *const pg_sys::config_enum_entry;
 }
 
+/// Checks a new value for a GUC, returning why it's invalid if it is
+///
+/// The [`pg_sys::GucSource`] says where the value comes from.  Values from
+/// `PGC_S_TEST`, such as `ALTER DATABASE ... SET`, may be checked more leniently, as Postgres' own
+/// hooks do for names of things that don't exist yet.
+pub type GucCheckHook<T> = fn(T, pg_sys::GucSource::Type) -> Result<(), GucCheckError>;
+
+/// Called with a GUC's new value just before it's set, which must not fail
+pub type GucAssignHook<T> = fn(T);
+
+/// What `SHOW` and `current_setting()` display for a GUC, instead of its value
+pub type GucShowHook = fn() -> String;
+
+/// Hooks that Postgres calls when a GUC is checked, assigned, or shown
+///
+/// Implement it for a type of your own, providing only the hooks the GUC needs, and register the
+/// GUC with one of the `GucRegistry::define_*_guc_with_hooks()` functions.  `T` is the GUC's
+/// value type: `bool`, `i32`, `f64`, `Option<&CStr>`, or a [`GucEnum`].
+///
+/// ```rust,no_run
+/// use pgrx::guc::*;
+///
+/// static CHUNK_SIZE: GucSetting<i32> = GucSetting::<i32>::new(1024);
+///
+/// struct PowerOfTwo;
+///
+/// impl GucHooks<i32> for PowerOfTwo {
+///     const CHECK: Option<GucCheckHook<i32>> = Some(|value, _| {
+///         if value.count_ones() == 1 {
+///             Ok(())
+///         } else {
+///             Err(GucCheckError::new().detail(format!("{value} is not a power of two")))
+///         }
+///     });
+/// }
+///
+/// GucRegistry::define_int_guc_with_hooks::<PowerOfTwo>(
+///     "my_extension.chunk_size",
+///     "the size of each chunk",
+///     "the size of each chunk, which must be a power of two",
+///     &CHUNK_SIZE,
+///     1,
+///     1 << 20,
+///     GucContext::Userset,
+///     GucFlags::default(),
+/// );
+/// ```
+///
+/// A string GUC's values only live as long as each call to its hooks, so their hooks implement
+/// `GucHooks<Option<&'a CStr>>` for every `'a`, as in `impl<'a> GucHooks<Option<&'a CStr>> for
+/// MyHooks`.
+///
+/// Check hooks also run for values in the configuration file, when the postmaster reloads it,
+/// so they should return an error rather than raise one.
+pub trait GucHooks<T> {
+    const CHECK: Option<GucCheckHook<T>> = None;
+    const ASSIGN: Option<GucAssignHook<T>> = None;
+    const SHOW: Option<GucShowHook> = None;
+}
+
+/// The [`GucHooks`] of a GUC without any
+pub struct NoGucHooks;
+
+impl<T> GucHooks<T> for NoGucHooks {}
+
+/// Why a [`GucCheckHook`] rejected a value
+///
+/// Without a message, Postgres reports `invalid value for parameter`, with the detail and hint,
+/// if any.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GucCheckError {
+    message: Option<String>,
+    detail: Option<String>,
+    hint: Option<String>,
+}
+
+impl GucCheckError {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report `message` instead of Postgres' own, as `GUC_check_errmsg()` does
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Add a detail to the error, as `GUC_check_errdetail()` does
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Add a hint to the error, as `GUC_check_errhint()` does
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+/// Hand a check hook's result to Postgres, which reports the error after the hook returns
+fn check_result(result: Result<(), GucCheckError>) -> bool {
+    let Err(error) = result else {
+        return true;
+    };
+    let copy = |s: Option<String>| match s {
+        Some(s) => PgMemoryContexts::CurrentMemoryContext.pstrdup(&s),
+        None => std::ptr::null_mut(),
+    };
+    // SAFETY: Postgres clears these before calling a check hook and reads them after, so they
+    // only need to outlive the call
+    unsafe {
+        pg_sys::GUC_check_errmsg_string = copy(error.message);
+        pg_sys::GUC_check_errdetail_string = copy(error.detail);
+        pg_sys::GUC_check_errhint_string = copy(error.hint);
+    }
+    false
+}
+
+#[pg_guard]
+unsafe extern "C" fn check_bool<H: GucHooks<bool>>(
+    newval: *mut bool,
+    _extra: *mut *mut c_void,
+    source: pg_sys::GucSource::Type,
+) -> bool {
+    // SAFETY: Postgres gives us the value to check
+    let value = unsafe { *newval };
+    H::CHECK.map_or(true, |check| check_result(check(value, source)))
+}
+
+#[pg_guard]
+unsafe extern "C" fn check_int<H: GucHooks<i32>>(
+    newval: *mut c_int,
+    _extra: *mut *mut c_void,
+    source: pg_sys::GucSource::Type,
+) -> bool {
+    // SAFETY: as above
+    let value = unsafe { *newval };
+    H::CHECK.map_or(true, |check| check_result(check(value, source)))
+}
+
+#[pg_guard]
+unsafe extern "C" fn check_real<H: GucHooks<f64>>(
+    newval: *mut f64,
+    _extra: *mut *mut c_void,
+    source: pg_sys::GucSource::Type,
+) -> bool {
+    // SAFETY: as above
+    let value = unsafe { *newval };
+    H::CHECK.map_or(true, |check| check_result(check(value, source)))
+}
+
+#[pg_guard]
+unsafe extern "C" fn check_string<H: for<'a> GucHooks<Option<&'a CStr>>>(
+    newval: *mut *mut c_char,
+    _extra: *mut *mut c_void,
+    source: pg_sys::GucSource::Type,
+) -> bool {
+    // SAFETY: as above, and the string, if any, is valid until Postgres is done with it
+    let value = unsafe { (!(*newval).is_null()).then(|| CStr::from_ptr(*newval)) };
+    <H as GucHooks<Option<&CStr>>>::CHECK.map_or(true, |check| check_result(check(value, source)))
+}
+
+#[pg_guard]
+unsafe extern "C" fn check_enum<T: GucEnum<T> + Copy, H: GucHooks<T>>(
+    newval: *mut c_int,
+    _extra: *mut *mut c_void,
+    source: pg_sys::GucSource::Type,
+) -> bool {
+    // SAFETY: as above
+    let value = T::from_ordinal(unsafe { *newval });
+    H::CHECK.map_or(true, |check| check_result(check(value, source)))
+}
+
+#[pg_guard]
+unsafe extern "C" fn assign_bool<H: GucHooks<bool>>(newval: bool, _extra: *mut c_void) {
+    if let Some(assign) = H::ASSIGN {
+        assign(newval)
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn assign_int<H: GucHooks<i32>>(newval: c_int, _extra: *mut c_void) {
+    if let Some(assign) = H::ASSIGN {
+        assign(newval)
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn assign_real<H: GucHooks<f64>>(newval: f64, _extra: *mut c_void) {
+    if let Some(assign) = H::ASSIGN {
+        assign(newval)
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn assign_string<H: for<'a> GucHooks<Option<&'a CStr>>>(
+    newval: *const c_char,
+    _extra: *mut c_void,
+) {
+    if let Some(assign) = <H as GucHooks<Option<&CStr>>>::ASSIGN {
+        // SAFETY: the string, if any, is the GUC's new value
+        assign(unsafe { (!newval.is_null()).then(|| CStr::from_ptr(newval)) })
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn assign_enum<T: GucEnum<T> + Copy, H: GucHooks<T>>(
+    newval: c_int,
+    _extra: *mut c_void,
+) {
+    if let Some(assign) = H::ASSIGN {
+        assign(T::from_ordinal(newval))
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn show<T, H: GucHooks<T>>() -> *const c_char {
+    match H::SHOW {
+        // Postgres copies it right away
+        Some(show) => PgMemoryContexts::CurrentMemoryContext.pstrdup(&show()).cast_const(),
+        None => std::ptr::null(),
+    }
+}
+
 /// A safe wrapper around a global variable that can be edited through a GUC
 pub struct GucSetting<T> {
     value: Cell<usize>,
@@ -209,6 +439,9 @@ where
 }
 
 /// A struct that has associated functions to register new GUCs
+///
+/// Each kind of GUC can also be registered with [`GucHooks`], which check, react to, or change
+/// how Postgres shows its values.
 pub struct GucRegistry {}
 impl GucRegistry {
     pub fn define_bool_guc(
@@ -219,6 +452,28 @@ impl GucRegistry {
         context: GucContext,
         flags: GucFlags,
     ) {
+        Self::define_bool_guc_with_hooks::<NoGucHooks>(
+            name,
+            short_description,
+            long_description,
+            setting,
+            context,
+            flags,
+        )
+    }
+
+    pub fn define_bool_guc_with_hooks<H: GucHooks<bool>>(
+        name: &str,
+        short_description: &str,
+        long_description: &str,
+        setting: &GucSetting<bool>,
+        context: GucContext,
+        flags: GucFlags,
+    ) {
+        let check: pg_sys::GucBoolCheckHook =
+            if H::CHECK.is_some() { Some(check_bool::<H>) } else { None };
+        let assign: pg_sys::GucBoolAssignHook =
+            if H::ASSIGN.is_some() { Some(assign_bool::<H>) } else { None };
         unsafe {
             pg_sys::DefineCustomBoolVariable(
                 PgMemoryContexts::TopMemoryContext.pstrdup(name),
@@ -228,9 +483,9 @@ impl GucRegistry {
                 setting.get(),
                 context as isize as _,
                 flags.bits(),
-                None,
-                None,
-                None,
+                check,
+                assign,
+                show_hook::<bool, H>(),
             );
         }
     }
@@ -245,6 +500,32 @@ impl GucRegistry {
         context: GucContext,
         flags: GucFlags,
     ) {
+        Self::define_int_guc_with_hooks::<NoGucHooks>(
+            name,
+            short_description,
+            long_description,
+            setting,
+            min_value,
+            max_value,
+            context,
+            flags,
+        )
+    }
+
+    pub fn define_int_guc_with_hooks<H: GucHooks<i32>>(
+        name: &str,
+        short_description: &str,
+        long_description: &str,
+        setting: &GucSetting<i32>,
+        min_value: i32,
+        max_value: i32,
+        context: GucContext,
+        flags: GucFlags,
+    ) {
+        let check: pg_sys::GucIntCheckHook =
+            if H::CHECK.is_some() { Some(check_int::<H>) } else { None };
+        let assign: pg_sys::GucIntAssignHook =
+            if H::ASSIGN.is_some() { Some(assign_int::<H>) } else { None };
         unsafe {
             pg_sys::DefineCustomIntVariable(
                 PgMemoryContexts::TopMemoryContext.pstrdup(name),
@@ -256,9 +537,9 @@ impl GucRegistry {
                 max_value,
                 context as isize as _,
                 flags.bits(),
-                None,
-                None,
-                None,
+                check,
+                assign,
+                show_hook::<i32, H>(),
             )
         }
     }
@@ -271,6 +552,35 @@ impl GucRegistry {
         context: GucContext,
         flags: GucFlags,
     ) {
+        Self::define_string_guc_with_hooks::<NoGucHooks>(
+            name,
+            short_description,
+            long_description,
+            setting,
+            context,
+            flags,
+        )
+    }
+
+    pub fn define_string_guc_with_hooks<H: for<'a> GucHooks<Option<&'a CStr>>>(
+        name: &str,
+        short_description: &str,
+        long_description: &str,
+        setting: &GucSetting<Option<&'static CStr>>,
+        context: GucContext,
+        flags: GucFlags,
+    ) {
+        let check: pg_sys::GucStringCheckHook = if <H as GucHooks<Option<&CStr>>>::CHECK.is_some() {
+            Some(check_string::<H>)
+        } else {
+            None
+        };
+        let assign: pg_sys::GucStringAssignHook =
+            if <H as GucHooks<Option<&CStr>>>::ASSIGN.is_some() {
+                Some(assign_string::<H>)
+            } else {
+                None
+            };
         unsafe {
             let boot_val = setting.boot_val.map_or(std::ptr::null(), |s| s.as_ptr());
             *setting.as_ptr() = boot_val as *mut _;
@@ -282,9 +592,9 @@ impl GucRegistry {
                 boot_val,
                 context as isize as _,
                 flags.bits(),
-                None,
-                None,
-                None,
+                check,
+                assign,
+                show_hook::<Option<&CStr>, H>(),
             );
         }
     }
@@ -299,6 +609,32 @@ impl GucRegistry {
         context: GucContext,
         flags: GucFlags,
     ) {
+        Self::define_float_guc_with_hooks::<NoGucHooks>(
+            name,
+            short_description,
+            long_description,
+            setting,
+            min_value,
+            max_value,
+            context,
+            flags,
+        )
+    }
+
+    pub fn define_float_guc_with_hooks<H: GucHooks<f64>>(
+        name: &str,
+        short_description: &str,
+        long_description: &str,
+        setting: &GucSetting<f64>,
+        min_value: f64,
+        max_value: f64,
+        context: GucContext,
+        flags: GucFlags,
+    ) {
+        let check: pg_sys::GucRealCheckHook =
+            if H::CHECK.is_some() { Some(check_real::<H>) } else { None };
+        let assign: pg_sys::GucRealAssignHook =
+            if H::ASSIGN.is_some() { Some(assign_real::<H>) } else { None };
         unsafe {
             pg_sys::DefineCustomRealVariable(
                 PgMemoryContexts::TopMemoryContext.pstrdup(name),
@@ -310,9 +646,9 @@ impl GucRegistry {
                 max_value,
                 context as isize as _,
                 flags.bits(),
-                None,
-                None,
-                None,
+                check,
+                assign,
+                show_hook::<f64, H>(),
             );
         }
     }
@@ -327,6 +663,31 @@ impl GucRegistry {
     ) where
         T: GucEnum<T> + Copy,
     {
+        Self::define_enum_guc_with_hooks::<T, NoGucHooks>(
+            name,
+            short_description,
+            long_description,
+            setting,
+            context,
+            flags,
+        )
+    }
+
+    pub fn define_enum_guc_with_hooks<T, H>(
+        name: &str,
+        short_description: &str,
+        long_description: &str,
+        setting: &GucSetting<T>,
+        context: GucContext,
+        flags: GucFlags,
+    ) where
+        T: GucEnum<T> + Copy,
+        H: GucHooks<T>,
+    {
+        let check: pg_sys::GucEnumCheckHook =
+            if H::CHECK.is_some() { Some(check_enum::<T, H>) } else { None };
+        let assign: pg_sys::GucEnumAssignHook =
+            if H::ASSIGN.is_some() { Some(assign_enum::<T, H>) } else { None };
         unsafe {
             let boot_val = setting.boot_val.to_ordinal();
             (*setting.as_ptr()) = boot_val;
@@ -339,10 +700,177 @@ impl GucRegistry {
                 setting.get().config_matrix(),
                 context as isize as _,
                 flags.bits(),
-                None,
-                None,
-                None,
+                check,
+                assign,
+                show_hook::<T, H>(),
+            );
+        }
+    }
+}
+
+fn show_hook<T, H: GucHooks<T>>() -> pg_sys::GucShowHook {
+    if H::SHOW.is_some() {
+        Some(show::<T, H>)
+    } else {
+        None
+    }
+}
+
+/// Reads and sets any GUC by name, whether it's Postgres' own, another extension's, or ours
+///
+/// ```rust,no_run
+/// use pgrx::guc::Guc;
+///
+/// // in kilobytes, `work_mem`'s unit
+/// let work_mem = Guc::get::<i32>("work_mem").unwrap();
+/// {
+///     let _guard = Guc::set_local("work_mem", "1GB");
+///     // ... run something that needs more memory ...
+/// }
+/// assert_eq!(Guc::get::<i32>("work_mem"), Ok(work_mem));
+/// ```
+pub struct Guc;
+
+impl Guc {
+    /// The value of GUC `name`, in its base unit, if it has one, rather than as `SHOW` shows it
+    ///
+    /// Raises an ERROR if the user isn't allowed to see the value, as `SHOW` would.
+    pub fn get<T: GucValue>(name: &str) -> Result<T, GucError> {
+        let cname = std::ffi::CString::new(name).expect("GUC names can't contain NUL");
+        // SAFETY: the returned value, if any, is valid until the GUC is next set, and copied
+        // before then
+        let value = unsafe {
+            let value = pg_sys::GetConfigOption(cname.as_ptr(), true, true);
+            (!value.is_null()).then(|| CStr::from_ptr(value).to_string_lossy().into_owned())
+        };
+        let value = match value {
+            Some(value) => value,
+            // string GUCs can be NULL, which `SHOW` shows as empty
+            None if Self::show(name).is_some() => String::new(),
+            None => return Err(GucError::Unknown(name.to_string())),
+        };
+        T::from_guc(&value).ok_or_else(|| GucError::WrongType {
+            name: name.to_string(),
+            value,
+            expected: std::any::type_name::<T>(),
+        })
+    }
+
+    /// The value of GUC `name` as `SHOW` shows it, with its unit, or `None` if there's no such
+    /// GUC
+    pub fn show(name: &str) -> Option<String> {
+        let cname = std::ffi::CString::new(name).expect("GUC names can't contain NUL");
+        // SAFETY: the returned value is palloc'd, and Postgres raises an ERROR if the user isn't
+        // allowed to see it
+        unsafe {
+            let value = pg_sys::GetConfigOptionByName(cname.as_ptr(), std::ptr::null_mut(), true);
+            if value.is_null() {
+                return None;
+            }
+            let shown = CStr::from_ptr(value).to_string_lossy().into_owned();
+            pg_sys::pfree(value.cast());
+            Some(shown)
+        }
+    }
+
+    /// Set GUC `name` to `value` until the returned guard is dropped, when its previous value is
+    /// restored, as a function's `SET` clause does
+    ///
+    /// Postgres raises an ERROR if the value is invalid or the user isn't allowed to set it.
+    /// Guards should be dropped in the reverse order they were made, since dropping one also
+    /// restores whatever was set after it.  A guard dropped after its transaction ends does
+    /// nothing, as the end of the transaction already restored the value.
+    pub fn set_local(name: &str, value: impl std::fmt::Display) -> GucLocalGuard {
+        let cname = std::ffi::CString::new(name).expect("GUC names can't contain NUL");
+        let cvalue =
+            std::ffi::CString::new(value.to_string()).expect("GUC values can't contain NUL");
+        // SAFETY: the new nest level is ended when the guard drops, and Postgres raises an ERROR
+        // rather than return if the value can't be set
+        unsafe {
+            let nest_level = pg_sys::NewGUCNestLevel();
+            let guard = GucLocalGuard {
+                nest_level,
+                transaction: local_transaction_id(),
+                _not_send: std::marker::PhantomData,
+            };
+            let context = if pg_sys::superuser() {
+                pg_sys::GucContext::PGC_SUSET
+            } else {
+                pg_sys::GucContext::PGC_USERSET
+            };
+            pg_sys::set_config_option(
+                cname.as_ptr(),
+                cvalue.as_ptr(),
+                context,
+                pg_sys::GucSource::PGC_S_SESSION,
+                pg_sys::GucAction::GUC_ACTION_SAVE,
+                true,
+                pg_sys::ERROR as _,
+                false,
             );
+            guard
+        }
+    }
+}
+
+/// Restores the GUCs set by [`Guc::set_local`] when dropped
+#[must_use = "the setting is restored as soon as the guard is dropped"]
+pub struct GucLocalGuard {
+    nest_level: c_int,
+    transaction: pg_sys::LocalTransactionId,
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+impl Drop for GucLocalGuard {
+    fn drop(&mut self) {
+        // the transaction the nest level belonged to has ended, and restored the GUCs itself
+        if self.transaction != local_transaction_id() {
+            return;
         }
+        // SAFETY: the nest level is ours, and Postgres pops everything set since it began
+        unsafe { pg_sys::AtEOXact_GUC(true, self.nest_level) }
+    }
+}
+
+/// A type [`Guc::get`] can read a GUC's value as
+pub trait GucValue: Sized {
+    /// Parse the value as `GetConfigOption()` formats it
+    fn from_guc(value: &str) -> Option<Self>;
+}
+
+impl GucValue for bool {
+    fn from_guc(value: &str) -> Option<Self> {
+        match value {
+            "on" | "true" | "yes" | "1" => Some(true),
+            "off" | "false" | "no" | "0" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl GucValue for i32 {
+    fn from_guc(value: &str) -> Option<Self> {
+        value.parse().ok()
     }
 }
+
+impl GucValue for f64 {
+    fn from_guc(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+}
+
+impl GucValue for String {
+    fn from_guc(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+/// Why [`Guc::get`] couldn't read a GUC
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum GucError {
+    #[error("unrecognized configuration parameter \"{0}\"")]
+    Unknown(String),
+    #[error("configuration parameter \"{name}\" is \"{value}\", which isn't a {expected}")]
+    WrongType { name: String, value: String, expected: &'static str },
+}
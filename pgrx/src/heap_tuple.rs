@@ -348,37 +348,7 @@ impl<'mcx> PgHeapTuple<'mcx, AllocatedByRust> {
         unsafe {
             match self.get_attribute_by_index(attno) {
                 None => return Err(TryFromDatumError::NoSuchAttributeNumber(attno)),
-                Some(att) => {
-                    // if the attribute's type is an array and our incoming value is a composite
-                    // then we need to ensure that the `att.atttypid` is the same as the array type
-                    // of `value`'s type
-                    if pg_sys::type_is_array(att.atttypid) && value.composite_type_oid().is_some() {
-                        let array_of_composite_type_oid = value.composite_type_oid().unwrap();
-
-                        if att.atttypid != array_of_composite_type_oid {
-                            return Err(TryFromDatumError::IncompatibleTypes {
-                                rust_type: std::any::type_name::<T>(),
-                                rust_oid: att.atttypid,
-                                datum_type: lookup_type_name(array_of_composite_type_oid),
-                                datum_oid: array_of_composite_type_oid,
-                            });
-                        }
-                    } else {
-                        // it's not an array type, so we'll do standard type compatibility checks
-                        let type_oid = T::type_oid();
-                        let composite_type_oid = value.composite_type_oid();
-                        let is_compatible_composite_types = type_oid == pg_sys::RECORDOID
-                            && composite_type_oid == Some(att.atttypid);
-                        if !is_compatible_composite_types && !T::is_compatible_with(att.atttypid) {
-                            return Err(TryFromDatumError::IncompatibleTypes {
-                                rust_type: std::any::type_name::<T>(),
-                                rust_oid: att.atttypid,
-                                datum_type: lookup_type_name(type_oid),
-                                datum_oid: type_oid,
-                            });
-                        }
-                    }
-                }
+                Some(att) => check_attribute_type(att, &value)?,
             }
 
             let mut datums = (0..self.tupdesc.len()).map(pg_sys::Datum::from).collect::<Vec<_>>();
@@ -457,6 +427,18 @@ impl<'mcx, AllocatedBy: WhoAllocated> PgHeapTuple<'mcx, AllocatedBy> {
         self.tuple.into_pg()
     }
 
+    /// The tuple descriptor describing this [`PgHeapTuple`]'s attributes
+    #[inline]
+    pub(crate) fn tupdesc(&self) -> &PgTupleDesc<'mcx> {
+        &self.tupdesc
+    }
+
+    /// A pointer to the underlying [`pg_sys::HeapTupleData`], still owned by this [`PgHeapTuple`]
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *mut pg_sys::HeapTupleData {
+        self.tuple.as_ptr()
+    }
+
     /// Returns the number of attributes in this [`PgHeapTuple`].
     #[inline]
     pub fn len(&self) -> usize {
@@ -564,6 +546,43 @@ impl<'mcx, AllocatedBy: WhoAllocated> PgHeapTuple<'mcx, AllocatedBy> {
     }
 }
 
+/// Checks that `value` can be stored in the attribute `att`
+pub(crate) fn check_attribute_type<T: IntoDatum>(
+    att: &pg_sys::FormData_pg_attribute,
+    value: &T,
+) -> Result<(), TryFromDatumError> {
+    // if the attribute's type is an array and our incoming value is a composite
+    // then we need to ensure that the `att.atttypid` is the same as the array type
+    // of `value`'s type
+    if unsafe { pg_sys::type_is_array(att.atttypid) } && value.composite_type_oid().is_some() {
+        let array_of_composite_type_oid = value.composite_type_oid().unwrap();
+
+        if att.atttypid != array_of_composite_type_oid {
+            return Err(TryFromDatumError::IncompatibleTypes {
+                rust_type: std::any::type_name::<T>(),
+                rust_oid: att.atttypid,
+                datum_type: lookup_type_name(array_of_composite_type_oid),
+                datum_oid: array_of_composite_type_oid,
+            });
+        }
+    } else {
+        // it's not an array type, so we'll do standard type compatibility checks
+        let type_oid = T::type_oid();
+        let composite_type_oid = value.composite_type_oid();
+        let is_compatible_composite_types =
+            type_oid == pg_sys::RECORDOID && composite_type_oid == Some(att.atttypid);
+        if !is_compatible_composite_types && !T::is_compatible_with(att.atttypid) {
+            return Err(TryFromDatumError::IncompatibleTypes {
+                rust_type: std::any::type_name::<T>(),
+                rust_oid: att.atttypid,
+                datum_type: lookup_type_name(type_oid),
+                datum_oid: type_oid,
+            });
+        }
+    }
+    Ok(())
+}
+
 /** Composite type support
 
 Support for working with types defined by SQL statements like:
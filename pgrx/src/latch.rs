@@ -0,0 +1,215 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Waiting on this backend's latch, sockets, and postmaster death all at once
+//!
+//! A [`WaitEventSet`] wraps the Postgres API of the same name, which `WaitLatch()` itself is
+//! built on.  Build one with [`WaitEventSet::builder`] and [`WaitEventSet::wait`] on it as often as
+//! needed, instead of polling with a timeout.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pgrx::latch::{WaitEventSet, WaitEvents};
+//! # let socket: std::os::fd::RawFd = 0;
+//!
+//! let mut set = WaitEventSet::builder()
+//!     .latch()
+//!     .postmaster_death()
+//!     .socket(socket, WaitEvents::SOCKET_READABLE)
+//!     .build();
+//! loop {
+//!     for event in set.wait(None) {
+//!         if event.events.contains(WaitEvents::POSTMASTER_DEATH) {
+//!             return;
+//!         }
+//!         if event.events.contains(WaitEvents::SOCKET_READABLE) {
+//!             // read from `socket`
+//!         }
+//!     }
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate::pg_sys;
+use bitflags::bitflags;
+use std::os::fd::RawFd;
+use std::ptr::NonNull;
+use std::time::Duration;
+
+bitflags! {
+    /// What to wait for, and what happened
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct WaitEvents: u32 {
+        const LATCH_SET         = pg_sys::WL_LATCH_SET;
+        const SOCKET_READABLE   = pg_sys::WL_SOCKET_READABLE;
+        const SOCKET_WRITEABLE  = pg_sys::WL_SOCKET_WRITEABLE;
+        const TIMEOUT           = pg_sys::WL_TIMEOUT;
+        const POSTMASTER_DEATH  = pg_sys::WL_POSTMASTER_DEATH;
+        const EXIT_ON_PM_DEATH  = pg_sys::WL_EXIT_ON_PM_DEATH;
+        const SOCKET_CONNECTED  = pg_sys::WL_SOCKET_CONNECTED;
+    }
+}
+
+/// Something that happened while waiting on a [`WaitEventSet`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OccurredEvent {
+    /// The position of the event in the set, in the order the builder added them
+    pub position: usize,
+    /// What happened
+    pub events: WaitEvents,
+    /// The socket it happened on, for socket events
+    pub socket: Option<RawFd>,
+}
+
+/// Builds a [`WaitEventSet`]
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct WaitEventSetBuilder {
+    events: Vec<(WaitEvents, RawFd)>,
+    wait_event_info: u32,
+}
+
+impl WaitEventSetBuilder {
+    /// Wake up when this backend's latch is set, which is how other backends, signal handlers,
+    /// and interrupts wake it
+    pub fn latch(mut self) -> Self {
+        self.events.push((WaitEvents::LATCH_SET, pg_sys::PGINVALID_SOCKET));
+        self
+    }
+
+    /// Wake up if the postmaster dies, so the caller can clean up and exit
+    pub fn postmaster_death(mut self) -> Self {
+        self.events.push((WaitEvents::POSTMASTER_DEATH, pg_sys::PGINVALID_SOCKET));
+        self
+    }
+
+    /// Exit the backend immediately if the postmaster dies
+    pub fn exit_on_postmaster_death(mut self) -> Self {
+        self.events.push((WaitEvents::EXIT_ON_PM_DEATH, pg_sys::PGINVALID_SOCKET));
+        self
+    }
+
+    /// Wake up when any of `events`, which must be socket events, happen on `socket`
+    pub fn socket(mut self, socket: RawFd, events: WaitEvents) -> Self {
+        assert!(
+            WaitEvents::from_bits_truncate(pg_sys::WL_SOCKET_MASK).contains(events),
+            "only socket events can be waited for on a socket"
+        );
+        self.events.push((events, socket));
+        self
+    }
+
+    /// Report `wait_event_info` in `pg_stat_activity` while waiting, rather than `Extension`
+    pub fn wait_event(mut self, wait_event_info: u32) -> Self {
+        self.wait_event_info = wait_event_info;
+        self
+    }
+
+    pub fn build(self) -> WaitEventSet {
+        let nevents = self.events.len().try_into().expect("too many wait events");
+        // SAFETY: the set lives in `TopMemoryContext` and isn't owned by any resource owner, so
+        // it's ours to free.  Each event is added once, and Postgres raises an ERROR if it
+        // doesn't like one
+        unsafe {
+            #[cfg(not(feature = "pg17"))]
+            let set = pg_sys::CreateWaitEventSet(pg_sys::TopMemoryContext, nevents);
+            // without a resource owner, it's allocated in `TopMemoryContext`
+            #[cfg(feature = "pg17")]
+            let set = pg_sys::CreateWaitEventSet(std::ptr::null_mut(), nevents);
+            let set = WaitEventSet {
+                set: NonNull::new(set).expect("WaitEventSet should not be NULL"),
+                capacity: self.events.len(),
+                wait_event_info: self.wait_event_info,
+                has_latch: self
+                    .events
+                    .iter()
+                    .any(|(events, _)| events.contains(WaitEvents::LATCH_SET)),
+            };
+            for (events, socket) in self.events {
+                let latch = if events.contains(WaitEvents::LATCH_SET) {
+                    pg_sys::MyLatch
+                } else {
+                    std::ptr::null_mut()
+                };
+                pg_sys::AddWaitEventToSet(
+                    set.set.as_ptr(),
+                    events.bits(),
+                    socket,
+                    latch,
+                    std::ptr::null_mut(),
+                );
+            }
+            set
+        }
+    }
+}
+
+/// A set of events to wait for together
+pub struct WaitEventSet {
+    set: NonNull<pg_sys::WaitEventSet>,
+    capacity: usize,
+    wait_event_info: u32,
+    has_latch: bool,
+}
+
+impl WaitEventSet {
+    pub fn builder() -> WaitEventSetBuilder {
+        WaitEventSetBuilder { events: Vec::new(), wait_event_info: pg_sys::PG_WAIT_EXTENSION }
+    }
+
+    /// Wait until at least one event happens, or `timeout` passes, in which case this returns
+    /// no events.
+    ///
+    /// If the latch was set, it's reset and interrupts are checked before returning, so a query
+    /// cancel or termination request ends the wait with an `ERROR`.  Whatever the caller was
+    /// waiting for must be re-checked after that, as with any latch.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Vec<OccurredEvent> {
+        let timeout = match timeout {
+            Some(timeout) => timeout.as_millis().try_into().unwrap_or(libc::c_long::MAX),
+            None => -1,
+        };
+        let mut occurred = vec![pg_sys::WaitEvent::default(); self.capacity];
+        // SAFETY: the set is valid, and `occurred` has room for every event in it
+        let n = unsafe {
+            pg_sys::WaitEventSetWait(
+                self.set.as_ptr(),
+                timeout,
+                occurred.as_mut_ptr(),
+                occurred.len() as _,
+                self.wait_event_info,
+            )
+        };
+        occurred.truncate(n.try_into().unwrap_or(0));
+
+        let events = occurred
+            .into_iter()
+            .map(|event| OccurredEvent {
+                position: event.pos as usize,
+                events: WaitEvents::from_bits_truncate(event.events),
+                socket: (event.fd != pg_sys::PGINVALID_SOCKET).then_some(event.fd),
+            })
+            .collect::<Vec<_>>();
+        if self.has_latch && events.iter().any(|e| e.events.contains(WaitEvents::LATCH_SET)) {
+            // SAFETY: resetting our own latch before checking for whatever set it is the
+            // standard latch protocol
+            unsafe {
+                pg_sys::ResetLatch(pg_sys::MyLatch);
+                pg_sys::check_for_interrupts!();
+            }
+        }
+        events
+    }
+}
+
+impl Drop for WaitEventSet {
+    fn drop(&mut self) {
+        // SAFETY: the set is ours, and nothing else has freed it
+        unsafe { pg_sys::FreeWaitEventSet(self.set.as_ptr()) }
+    }
+}
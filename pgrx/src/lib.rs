@@ -38,11 +38,17 @@ pub mod prelude;
 pub mod aggregate;
 pub mod array;
 pub mod atomics;
+pub mod bench;
 pub mod bgworkers;
 pub mod callbacks;
 pub mod callconv;
+pub mod condvar;
+pub mod coverage;
 pub mod datum;
+pub mod dshash;
+pub mod dsm;
 pub mod enum_helper;
+pub mod executor;
 pub mod fcinfo;
 pub mod ffi;
 pub mod fn_call;
@@ -55,8 +61,10 @@ pub mod htup;
 pub mod inoutfuncs;
 pub mod itemptr;
 pub mod iter;
+pub mod latch;
 pub mod layout;
 pub mod list;
+pub mod lock;
 pub mod lwlock;
 pub mod memcx;
 pub mod memcxt;
@@ -65,9 +73,13 @@ pub mod misc;
 pub mod namespace;
 pub mod nodes;
 pub mod nullable;
+pub mod parallel;
 pub mod pg_catalog;
 pub mod pgbox;
+pub mod pgstat;
 pub mod rel;
+pub mod scan;
+pub mod shm_mq;
 pub mod shmem;
 pub mod spi;
 #[cfg(feature = "cshim")]
@@ -75,7 +87,10 @@ pub mod spinlock;
 pub mod stringinfo;
 pub mod trigger_support;
 pub mod tupdesc;
+pub mod tuplestore;
+pub mod tuptable;
 pub mod varlena;
+pub mod wait_event;
 pub mod wrappers;
 pub mod xid;
 
@@ -87,6 +102,7 @@ mod toast;
 pub use aggregate::*;
 pub use atomics::*;
 pub use callbacks::*;
+pub use condvar::*;
 pub use datum::{
     numeric, AnyArray, AnyElement, AnyNumeric, Array, FromDatum, Inet, Internal, IntoDatum, Json,
     JsonB, Numeric, Range, Uuid, VariadicArray,
@@ -113,6 +129,7 @@ pub use spi::Spi; // only Spi.  We don't want the top-level namespace polluted w
 pub use stringinfo::*;
 pub use trigger_support::*;
 pub use tupdesc::*;
+pub use tuptable::*;
 pub use varlena::*;
 pub use wrappers::*;
 pub use xid::*;
@@ -262,6 +279,10 @@ macro_rules! pg_magic_func {
             // so we don't unwind into C / Postgres
             ::pgrx::pg_sys::panic::register_pg_guard_panic_hook();
 
+            // and, if we were built by `cargo pgrx test --coverage`, make sure this backend's
+            // coverage counters get written when it exits
+            ::pgrx::coverage::register_profile_flush();
+
             // return the magic
             &MY_MAGIC
         }
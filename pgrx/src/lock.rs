@@ -0,0 +1,334 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Heavyweight locks, the ones in `pg_locks`, on relations, database objects, and advisory keys
+//!
+//! Unlike a [`crate::PgLwLock`], these are held by transactions (or sessions), wait in a queue,
+//! take part in deadlock detection, and come in eight [`LockMode`]s that conflict as the
+//! [Postgres documentation](https://www.postgresql.org/docs/current/explicit-locking.html)
+//! describes.  Locking a [`LockTag`] returns a [`LockGuard`] that releases the lock when dropped,
+//! unless [`LockGuard::hold`] keeps it until the transaction ends.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pgrx::lock::{LockMode, LockTag};
+//!
+//! const LEADER: LockTag = LockTag::Advisory(0x7067_7278);
+//!
+//! fn lead() {
+//!     // only one backend at a time gets past here, until it exits
+//!     let Some(leader) = LEADER.try_lock_session(LockMode::Exclusive) else {
+//!         return;
+//!     };
+//!     // ... do the leader's work ...
+//!     drop(leader);
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate::pg_sys;
+use std::marker::PhantomData;
+
+/// How strongly to lock something, from weakest to strongest
+///
+/// The names are those of the table-level lock modes, but they apply to every [`LockTag`].
+/// Advisory locks usually only use [`LockMode::Share`] and [`LockMode::Exclusive`], as
+/// `pg_advisory_lock_shared()` and `pg_advisory_lock()` do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LockMode {
+    AccessShare,
+    RowShare,
+    RowExclusive,
+    ShareUpdateExclusive,
+    Share,
+    ShareRowExclusive,
+    Exclusive,
+    AccessExclusive,
+}
+
+impl LockMode {
+    /// The mode as Postgres numbers it
+    pub fn as_lockmode(self) -> pg_sys::LOCKMODE {
+        (match self {
+            LockMode::AccessShare => pg_sys::AccessShareLock,
+            LockMode::RowShare => pg_sys::RowShareLock,
+            LockMode::RowExclusive => pg_sys::RowExclusiveLock,
+            LockMode::ShareUpdateExclusive => pg_sys::ShareUpdateExclusiveLock,
+            LockMode::Share => pg_sys::ShareLock,
+            LockMode::ShareRowExclusive => pg_sys::ShareRowExclusiveLock,
+            LockMode::Exclusive => pg_sys::ExclusiveLock,
+            LockMode::AccessExclusive => pg_sys::AccessExclusiveLock,
+        }) as pg_sys::LOCKMODE
+    }
+}
+
+/// Something that can be locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockTag {
+    /// A relation, by its OID, as `LOCK TABLE` locks it
+    Relation(pg_sys::Oid),
+    /// An object in the current database, by the OID of its catalog, its own OID, and, for
+    /// things like columns, its sub-ID
+    Object { class: pg_sys::Oid, object: pg_sys::Oid, subid: u16 },
+    /// Like [`LockTag::Object`], for objects shared by every database, such as roles
+    SharedObject { class: pg_sys::Oid, object: pg_sys::Oid, subid: u16 },
+    /// An advisory lock in the current database, the same one as `pg_advisory_lock(bigint)`
+    Advisory(i64),
+    /// An advisory lock in the current database, the same one as `pg_advisory_lock(int, int)`
+    AdvisoryPair(i32, i32),
+}
+
+impl LockTag {
+    /// Lock until the returned guard is dropped or, if it's [held](LockGuard::hold), the
+    /// transaction ends, waiting for conflicting locks to be released first
+    pub fn lock(self, mode: LockMode) -> LockGuard {
+        let lockmode = mode.as_lockmode();
+        // SAFETY: Postgres raises an ERROR if the lock can't be acquired, such as on deadlock
+        unsafe {
+            match self {
+                LockTag::Relation(relid) => pg_sys::LockRelationOid(relid, lockmode),
+                LockTag::Object { class, object, subid } => {
+                    pg_sys::LockDatabaseObject(class, object, subid, lockmode)
+                }
+                LockTag::SharedObject { class, object, subid } => {
+                    pg_sys::LockSharedObject(class, object, subid, lockmode)
+                }
+                LockTag::Advisory(_) | LockTag::AdvisoryPair(..) => {
+                    pg_sys::LockAcquire(&self.to_pg(), lockmode, false, false);
+                }
+            }
+        }
+        LockGuard::new(self, mode, false)
+    }
+
+    /// Like [`LockTag::lock`], but return `None` rather than wait if the lock isn't available
+    pub fn try_lock(self, mode: LockMode) -> Option<LockGuard> {
+        let lockmode = mode.as_lockmode();
+        // SAFETY: as above
+        let locked = unsafe {
+            match self {
+                LockTag::Relation(relid) => pg_sys::ConditionalLockRelationOid(relid, lockmode),
+                LockTag::Object { class, object, subid } => {
+                    pg_sys::ConditionalLockDatabaseObject(class, object, subid, lockmode)
+                }
+                #[cfg(feature = "pg17")]
+                LockTag::SharedObject { class, object, subid } => {
+                    pg_sys::ConditionalLockSharedObject(class, object, subid, lockmode)
+                }
+                // before Postgres 17 there's no `ConditionalLockSharedObject()`, which would also
+                // process invalidation messages once the lock is acquired
+                #[cfg(not(feature = "pg17"))]
+                LockTag::SharedObject { .. } => acquire(&self.to_pg(), lockmode, false, true),
+                LockTag::Advisory(_) | LockTag::AdvisoryPair(..) => {
+                    acquire(&self.to_pg(), lockmode, false, true)
+                }
+            }
+        };
+        locked.then(|| LockGuard::new(self, mode, false))
+    }
+
+    /// Lock until the returned guard is dropped or, if it's [held](LockGuard::hold), the
+    /// session ends, even across transactions, as `pg_advisory_lock()` does
+    ///
+    /// Only advisory locks can be held by the session.
+    pub fn lock_session(self, mode: LockMode) -> LockGuard {
+        let tag = self.session_tag();
+        // SAFETY: Postgres raises an ERROR if the lock can't be acquired, such as on deadlock
+        unsafe {
+            pg_sys::LockAcquire(&tag, mode.as_lockmode(), true, false);
+        }
+        LockGuard::new(self, mode, true)
+    }
+
+    /// Like [`LockTag::lock_session`], but return `None` rather than wait if the lock isn't
+    /// available, as `pg_try_advisory_lock()` does
+    pub fn try_lock_session(self, mode: LockMode) -> Option<LockGuard> {
+        let tag = self.session_tag();
+        // SAFETY: as above
+        let locked = unsafe { acquire(&tag, mode.as_lockmode(), true, true) };
+        locked.then(|| LockGuard::new(self, mode, true))
+    }
+
+    /// Whether this backend holds the lock in `mode`, by its transaction or its session
+    pub fn is_held(self, mode: LockMode) -> bool {
+        let held = |tag: &pg_sys::LOCKTAG| {
+            // SAFETY: the tag is valid, and this only looks in our own table of locks
+            unsafe {
+                #[cfg(feature = "pg17")]
+                let held = pg_sys::LockHeldByMe(tag, mode.as_lockmode(), false);
+                #[cfg(not(feature = "pg17"))]
+                let held = pg_sys::LockHeldByMe(tag, mode.as_lockmode());
+                held
+            }
+        };
+        match self {
+            // a relation's lock is only tagged with our database if it isn't a shared catalog,
+            // and no relation can be both
+            LockTag::Relation(relid) => {
+                // SAFETY: `MyDatabaseId` is only written while the backend starts
+                let database = unsafe { pg_sys::MyDatabaseId };
+                held(&relation_tag(database, relid))
+                    || held(&relation_tag(pg_sys::InvalidOid, relid))
+            }
+            _ => held(&self.to_pg()),
+        }
+    }
+
+    fn session_tag(self) -> pg_sys::LOCKTAG {
+        assert!(
+            matches!(self, LockTag::Advisory(_) | LockTag::AdvisoryPair(..)),
+            "only advisory locks can be held by the session"
+        );
+        self.to_pg()
+    }
+
+    /// What the `SET_LOCKTAG_*()` macros make of this, except for relations
+    fn to_pg(self) -> pg_sys::LOCKTAG {
+        // SAFETY: `MyDatabaseId` is only written while the backend starts
+        let database = unsafe { pg_sys::MyDatabaseId };
+        let object_tag = |database: pg_sys::Oid, class: pg_sys::Oid, object: pg_sys::Oid, subid| {
+            pg_sys::LOCKTAG {
+                locktag_field1: database.as_u32(),
+                locktag_field2: class.as_u32(),
+                locktag_field3: object.as_u32(),
+                locktag_field4: subid,
+                locktag_type: pg_sys::LockTagType::LOCKTAG_OBJECT as u8,
+                locktag_lockmethodid: pg_sys::DEFAULT_LOCKMETHOD as u8,
+            }
+        };
+        let advisory_tag = |key1: u32, key2: u32, kind: u16| pg_sys::LOCKTAG {
+            locktag_field1: database.as_u32(),
+            locktag_field2: key1,
+            locktag_field3: key2,
+            locktag_field4: kind,
+            locktag_type: pg_sys::LockTagType::LOCKTAG_ADVISORY as u8,
+            locktag_lockmethodid: pg_sys::USER_LOCKMETHOD as u8,
+        };
+        match self {
+            LockTag::Relation(relid) => relation_tag(database, relid),
+            LockTag::Object { class, object, subid } => object_tag(database, class, object, subid),
+            LockTag::SharedObject { class, object, subid } => {
+                object_tag(pg_sys::InvalidOid, class, object, subid)
+            }
+            // the same as `pg_advisory_lock()`, which splits one key into its halves and marks
+            // which kind it was in the last field
+            LockTag::Advisory(key) => advisory_tag((key >> 32) as u32, key as u32, 1),
+            LockTag::AdvisoryPair(key1, key2) => advisory_tag(key1 as u32, key2 as u32, 2),
+        }
+    }
+
+    fn release(self, mode: LockMode, session: bool) {
+        let lockmode = mode.as_lockmode();
+        // SAFETY: we only release locks that a `LockGuard` says we hold
+        unsafe {
+            match (self, session) {
+                (LockTag::Relation(relid), false) => pg_sys::UnlockRelationOid(relid, lockmode),
+                (LockTag::Object { class, object, subid }, false) => {
+                    pg_sys::UnlockDatabaseObject(class, object, subid, lockmode)
+                }
+                (LockTag::SharedObject { class, object, subid }, false) => {
+                    pg_sys::UnlockSharedObject(class, object, subid, lockmode)
+                }
+                _ => {
+                    pg_sys::LockRelease(&self.to_pg(), lockmode, session);
+                }
+            }
+        }
+    }
+}
+
+fn relation_tag(database: pg_sys::Oid, relid: pg_sys::Oid) -> pg_sys::LOCKTAG {
+    pg_sys::LOCKTAG {
+        locktag_field1: database.as_u32(),
+        locktag_field2: relid.as_u32(),
+        locktag_field3: 0,
+        locktag_field4: 0,
+        locktag_type: pg_sys::LockTagType::LOCKTAG_RELATION as u8,
+        locktag_lockmethodid: pg_sys::DEFAULT_LOCKMETHOD as u8,
+    }
+}
+
+/// `LockAcquire()`, returning whether the lock was acquired
+///
+/// SAFETY: the caller must be able to handle an ERROR, as with any lock acquisition
+unsafe fn acquire(
+    tag: &pg_sys::LOCKTAG,
+    lockmode: pg_sys::LOCKMODE,
+    session: bool,
+    dont_wait: bool,
+) -> bool {
+    // SAFETY: the tag is valid
+    let result = unsafe { pg_sys::LockAcquire(tag, lockmode, session, dont_wait) };
+    result != pg_sys::LockAcquireResult::LOCKACQUIRE_NOT_AVAIL
+}
+
+/// A heavyweight lock this backend holds, which is released when the guard is dropped
+///
+/// Locking the same thing in the same mode twice takes the lock twice, and it's only released
+/// once both guards are.  Postgres releases a transaction's locks when it ends, so dropping a
+/// guard for one after its transaction has ended does nothing.
+#[must_use = "the lock is released as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct LockGuard {
+    tag: LockTag,
+    mode: LockMode,
+    session: bool,
+    /// The transaction holding the lock, unless the session does
+    transaction: Option<pg_sys::LocalTransactionId>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl LockGuard {
+    fn new(tag: LockTag, mode: LockMode, session: bool) -> Self {
+        let transaction = (!session).then(local_transaction_id);
+        LockGuard { tag, mode, session, transaction, _not_send: PhantomData }
+    }
+
+    pub fn tag(&self) -> LockTag {
+        self.tag
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Whether the session, rather than the transaction, holds the lock
+    pub fn is_session(&self) -> bool {
+        self.session
+    }
+
+    /// Keep the lock until the transaction ends, when Postgres releases it, rather than until
+    /// the guard is dropped.  Session locks are kept until the session ends.
+    pub fn hold(self) {
+        std::mem::forget(self)
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        // the transaction that held the lock has ended, and released it along with the rest
+        if self.transaction.is_some_and(|transaction| transaction != local_transaction_id()) {
+            return;
+        }
+        self.tag.release(self.mode, self.session)
+    }
+}
+
+/// This backend's current transaction, which Postgres numbers anew each time one starts, and
+/// sets to `InvalidLocalTransactionId` between them
+pub(crate) fn local_transaction_id() -> pg_sys::LocalTransactionId {
+    // SAFETY: `MyProc` is this backend's, and only this backend changes its transaction
+    unsafe {
+        #[cfg(not(feature = "pg17"))]
+        let lxid = (*pg_sys::MyProc).lxid;
+        #[cfg(feature = "pg17")]
+        let lxid = (*pg_sys::MyProc).vxid.lxid;
+        lxid
+    }
+}
@@ -47,6 +47,15 @@ impl<T> PgLwLock<T> {
         PgLwLock { inner: UnsafeCell::new(PgLwLockInner::empty()), name: OnceCell::new() }
     }
 
+    /// Like [`PgLwLock::new`], but name the lock's tranche `name`, which is what
+    /// `pg_stat_activity.wait_event` shows while a backend waits for it, rather than a UUID
+    pub const fn with_name(name: &'static str) -> Self {
+        PgLwLock {
+            inner: UnsafeCell::new(PgLwLockInner::empty()),
+            name: OnceCell::with_value(name),
+        }
+    }
+
     /// Create a new lock for T by attaching a LWLock, which is looked up by name
     pub fn from_named(input_name: &'static str, value: *mut T) -> Self {
         let name = OnceCell::new();
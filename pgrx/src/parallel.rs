@@ -0,0 +1,330 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Running Rust code in parallel workers, on Postgres' `ParallelContext`
+//!
+//! A [`ParallelContext`] launches parallel workers from inside a function, the way parallel
+//! query does.  Postgres restores the leader's snapshot, GUCs, and transaction state in every
+//! worker before calling its entry point, so workers see exactly what the leader sees, and any
+//! `ERROR` in a worker is raised again in the leader.
+//!
+//! The state shared between the leader and its workers is placed in the context's dynamic shared
+//! memory segment, and is only ever shared, so anything mutable in it needs to be atomics.  Each
+//! worker's entry point is an `extern "C"` function that hands its arguments to [`worker_main`],
+//! and the value it returns is sent back to the leader as CBOR.
+//!
+//! Postgres may launch fewer workers than asked for, possibly none, so work should be divided
+//! up through the shared state, with the leader taking part too, rather than by worker number.
+//! Parallel workers can't write to the database, and neither can the leader while it has
+//! workers.  Functions that don't write can be `#[pg_extern(parallel_safe)]`: when a parallel
+//! query runs one in its own worker, which can't launch workers of its own, the leader closure
+//! does all the work.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pgrx::parallel::{worker_main, ParallelContext};
+//! use pgrx::prelude::*;
+//! use pgrx::PGRXSharedMemory;
+//! use std::sync::atomic::{AtomicI64, Ordering};
+//!
+//! struct Work {
+//!     next: AtomicI64,
+//!     end: i64,
+//! }
+//! unsafe impl PGRXSharedMemory for Work {}
+//!
+//! impl Work {
+//!     fn sum(&self) -> i64 {
+//!         std::iter::from_fn(|| Some(self.next.fetch_add(1, Ordering::Relaxed)))
+//!             .take_while(|&n| n <= self.end)
+//!             .sum()
+//!     }
+//! }
+//!
+//! #[pg_extern(parallel_safe)]
+//! fn parallel_sum(end: i64) -> i64 {
+//!     let work = Work { next: AtomicI64::new(1), end };
+//!     let mut total = 0;
+//!     let partial_sums: Vec<i64> = ParallelContext::new("my_extension", "sum_worker", work)
+//!         .workers(4)
+//!         .run(|work| total += work.sum());
+//!     total + partial_sums.iter().sum::<i64>()
+//! }
+//!
+//! #[pg_guard]
+//! #[no_mangle]
+//! pub extern "C" fn sum_worker(seg: *mut pg_sys::dsm_segment, toc: *mut pg_sys::shm_toc) {
+//!     unsafe { worker_main(seg, toc, |work: &Work, _worker_number| work.sum()) }
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate::pg_sys;
+use crate::shmem::PGRXSharedMemory;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::CString;
+use std::hash::{Hash, Hasher};
+use std::ptr::NonNull;
+
+/// The `shm_toc` key of the [`Header`].  Postgres' own keys are all near `u64::MAX`.
+const HEADER_KEY: u64 = 1;
+/// The `shm_toc` key of the shared state
+const STATE_KEY: u64 = 2;
+/// The `shm_toc` key of worker 0's result queue.  Worker `n`'s is at `RESULT_QUEUE_KEY + n`.
+const RESULT_QUEUE_KEY: u64 = 3;
+/// How many bytes of a result fit in its queue at once.  Larger results are still sent, a piece
+/// at a time.
+const RESULT_QUEUE_SIZE: usize = 16 * 1024;
+
+/// Lets workers check they agree with the leader about what's in the segment
+#[repr(C)]
+struct Header {
+    fingerprint: u64,
+}
+
+fn fingerprint<S, R>() -> u64 {
+    let mut hasher = DefaultHasher::new();
+    std::any::type_name::<S>().hash(&mut hasher);
+    std::mem::size_of::<S>().hash(&mut hasher);
+    std::any::type_name::<R>().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Launches parallel workers sharing a `S`
+#[must_use]
+pub struct ParallelContext<S> {
+    library: String,
+    function: String,
+    nworkers: usize,
+    state: S,
+}
+
+impl<S: PGRXSharedMemory + Sync> ParallelContext<S> {
+    /// Share `state` with workers that run `function`, an `extern "C"` entry point in the shared
+    /// library `library`, which calls [`worker_main`].  One worker is asked for unless set with
+    /// [`ParallelContext::workers`].
+    pub fn new(library: &str, function: &str, state: S) -> Self {
+        assert!(
+            std::mem::align_of::<S>() <= pg_sys::ALIGNOF_BUFFER as usize,
+            "parallel state can't be aligned to more than ALIGNOF_BUFFER"
+        );
+        Self { library: library.to_string(), function: function.to_string(), nworkers: 1, state }
+    }
+
+    /// Ask for `nworkers` workers.  Postgres may launch fewer, limited by
+    /// `max_parallel_workers` and `max_worker_processes`.
+    pub fn workers(mut self, nworkers: usize) -> Self {
+        self.nworkers = nworkers;
+        self
+    }
+
+    /// Launch the workers, then run `leader` on the shared state alongside them, and return what
+    /// each launched worker's entry point returned, in worker number order.
+    ///
+    /// A worker that raised an `ERROR` has no result, and its error is raised again here once
+    /// every worker has finished.  The shared state is dropped once every worker has exited.
+    pub fn run<R, F>(self, leader: F) -> Vec<R>
+    where
+        R: DeserializeOwned,
+        F: FnOnce(&S),
+    {
+        // SAFETY: reading a global, which is only set in parallel workers
+        if unsafe { pg_sys::ParallelWorkerNumber } >= 0 {
+            // we're in a parallel query's worker, which can't launch workers of its own
+            leader(&self.state);
+            return Vec::new();
+        }
+
+        let library = CString::new(self.library).expect("library name contains a NUL byte");
+        let function = CString::new(self.function).expect("function name contains a NUL byte");
+        let nworkers = self.nworkers.try_into().expect("too many parallel workers");
+
+        // SAFETY: `Active` leaves parallel mode, and destroys the context, however we leave
+        // here.  The segment has room for everything we put in it, and none of it is read before
+        // it's initialized, because the workers aren't launched until it is
+        unsafe {
+            pg_sys::EnterParallelMode();
+            let mut active = Active {
+                pcxt: std::ptr::null_mut(),
+                state: std::ptr::null_mut(),
+                queues: Vec::new(),
+            };
+            active.pcxt =
+                pg_sys::CreateParallelContext(library.as_ptr(), function.as_ptr(), nworkers);
+            let pcxt = &mut *active.pcxt;
+
+            // what `shm_toc_estimate_chunk()` and `shm_toc_estimate_keys()` would do
+            let buffer_align =
+                |size: usize| pg_sys::TYPEALIGN(pg_sys::ALIGNOF_BUFFER as usize, size);
+            let queue_size = pg_sys::MAXALIGN(RESULT_QUEUE_SIZE.max(pg_sys::shm_mq_minimum_size));
+            pcxt.estimator.space_for_chunks += buffer_align(std::mem::size_of::<Header>());
+            pcxt.estimator.space_for_chunks += buffer_align(std::mem::size_of::<S>());
+            pcxt.estimator.space_for_chunks += buffer_align(queue_size) * self.nworkers;
+            pcxt.estimator.number_of_keys += 2 + self.nworkers;
+            pg_sys::InitializeParallelDSM(pcxt);
+
+            let header = pg_sys::shm_toc_allocate(pcxt.toc, std::mem::size_of::<Header>());
+            header.cast::<Header>().write(Header { fingerprint: fingerprint::<S, R>() });
+            pg_sys::shm_toc_insert(pcxt.toc, HEADER_KEY, header);
+            let state = pg_sys::shm_toc_allocate(pcxt.toc, std::mem::size_of::<S>()).cast::<S>();
+            state.write(self.state);
+            active.state = state;
+            pg_sys::shm_toc_insert(pcxt.toc, STATE_KEY, state.cast());
+
+            // `InitializeParallelDSM()` gives up on workers if it can't create a segment
+            for n in 0..pcxt.nworkers as u64 {
+                let mq = pg_sys::shm_mq_create(
+                    pg_sys::shm_toc_allocate(pcxt.toc, queue_size),
+                    queue_size,
+                );
+                pg_sys::shm_toc_insert(pcxt.toc, RESULT_QUEUE_KEY + n, mq.cast());
+                pg_sys::shm_mq_set_receiver(mq, pg_sys::MyProc);
+                let mqh = pg_sys::shm_mq_attach(mq, pcxt.seg, std::ptr::null_mut());
+                active.queues.push(NonNull::new(mqh).expect("shm_mq_handle should not be NULL"));
+            }
+
+            pg_sys::LaunchParallelWorkers(pcxt);
+            let launched = pcxt.nworkers_launched as usize;
+            for mqh in active.queues.drain(launched..) {
+                pg_sys::shm_mq_detach(mqh.as_ptr());
+            }
+            for (n, mqh) in active.queues.iter().enumerate() {
+                // a worker that exits without sending anything detaches its queue
+                pg_sys::shm_mq_set_handle(mqh.as_ptr(), (*pcxt.worker.add(n)).bgwhandle);
+            }
+
+            leader(&*state);
+
+            let mut results = Vec::with_capacity(launched);
+            for mqh in &active.queues {
+                let mut nbytes = 0;
+                let mut data = std::ptr::null_mut();
+                let res = pg_sys::shm_mq_receive(mqh.as_ptr(), &mut nbytes, &mut data, false);
+                if res == pg_sys::shm_mq_result::SHM_MQ_SUCCESS {
+                    let bytes = std::slice::from_raw_parts(data.cast::<u8>(), nbytes);
+                    let result = serde_cbor::from_slice(bytes)
+                        .unwrap_or_else(|e| panic!("unable to decode parallel worker result: {e}"));
+                    results.push(result);
+                }
+            }
+            pg_sys::WaitForParallelWorkersToFinish(pcxt);
+            results
+        }
+    }
+}
+
+/// A launched `ParallelContext`, cleaned up however `ParallelContext::run()` ends
+struct Active<S> {
+    pcxt: *mut pg_sys::ParallelContext,
+    /// The shared state, once it's been written to the segment
+    state: *mut S,
+    queues: Vec<NonNull<pg_sys::shm_mq_handle>>,
+}
+
+impl<S> Drop for Active<S> {
+    fn drop(&mut self) {
+        // SAFETY: the queues must be detached before the segment they're in goes away, along
+        // with the context, which terminates any workers still running.  The state can only be
+        // dropped once no worker can be looking at it, while the segment is still mapped
+        unsafe {
+            for mqh in self.queues.drain(..) {
+                pg_sys::shm_mq_detach(mqh.as_ptr());
+            }
+            if !self.pcxt.is_null() {
+                if !self.state.is_null() {
+                    wait_for_workers_to_exit(&*self.pcxt);
+                    self.state.drop_in_place();
+                }
+                pg_sys::DestroyParallelContext(self.pcxt);
+            }
+            pg_sys::ExitParallelMode();
+        }
+    }
+}
+
+/// Stop any workers still running, and wait for every launched worker to exit, as
+/// `DestroyParallelContext()` does, but before it unmaps the segment
+///
+/// # Safety
+///
+/// `pcxt`'s workers must have been launched
+unsafe fn wait_for_workers_to_exit(pcxt: &pg_sys::ParallelContext) {
+    // SAFETY: there's a `ParallelWorkerInfo` for each launched worker.  Interrupts are held, as
+    // `DestroyParallelContext()` holds them, so waiting doesn't raise an ERROR while cleaning up
+    unsafe {
+        pg_sys::InterruptHoldoffCount += 1;
+        for n in 0..pcxt.nworkers_launched as usize {
+            let handle = (*pcxt.worker.add(n)).bgwhandle;
+            if !handle.is_null() {
+                pg_sys::TerminateBackgroundWorker(handle);
+                pg_sys::WaitForBackgroundWorkerShutdown(handle);
+            }
+        }
+        pg_sys::InterruptHoldoffCount -= 1;
+    }
+}
+
+/// Run `work` in a parallel worker launched by a [`ParallelContext<S>`], and send what it
+/// returns back to the leader.  `work` is given the shared state and this worker's number,
+/// which counts up from zero.
+///
+/// # Panics
+///
+/// If `S` or `R` aren't the types the leader used.  This can't always be detected, hence:
+///
+/// # Safety
+///
+/// `seg` and `toc` must be the arguments Postgres passed to a parallel worker's entry point,
+/// and the leader must have been a `ParallelContext<S>::run::<R, _>()`
+pub unsafe fn worker_main<S, R, F>(
+    seg: *mut pg_sys::dsm_segment,
+    toc: *mut pg_sys::shm_toc,
+    work: F,
+) where
+    S: PGRXSharedMemory + Sync,
+    R: Serialize,
+    F: FnOnce(&S, usize) -> R,
+{
+    // SAFETY: the caller promises the leader put a `Header`, a `S`, and a result queue for each
+    // worker in `toc`
+    let (state, mq) = unsafe {
+        let header = &*pg_sys::shm_toc_lookup(toc, HEADER_KEY, false).cast::<Header>();
+        assert_eq!(
+            header.fingerprint,
+            fingerprint::<S, R>(),
+            "parallel worker's state or result type doesn't match the leader's"
+        );
+        let state = &*pg_sys::shm_toc_lookup(toc, STATE_KEY, false).cast::<S>();
+        let number = pg_sys::ParallelWorkerNumber as u64;
+        let mq = pg_sys::shm_toc_lookup(toc, RESULT_QUEUE_KEY + number, false).cast();
+        (state, mq)
+    };
+
+    // SAFETY: as a parallel worker, `ParallelWorkerNumber` is set
+    let result = work(state, unsafe { pg_sys::ParallelWorkerNumber } as usize);
+    let bytes = serde_cbor::to_vec(&result).expect("failed to encode parallel worker result");
+
+    // SAFETY: we're the only sender on our own queue, and the leader only stops receiving
+    // before we're done if it's erroring out, in which case it doesn't want the result anyway
+    unsafe {
+        pg_sys::shm_mq_set_sender(mq, pg_sys::MyProc);
+        let mqh = pg_sys::shm_mq_attach(mq, seg, std::ptr::null_mut());
+        pg_sys::shm_mq_send(
+            mqh,
+            bytes.len(),
+            bytes.as_ptr().cast(),
+            false,
+            #[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14")))]
+            true,
+        );
+        pg_sys::shm_mq_detach(mqh);
+    }
+}
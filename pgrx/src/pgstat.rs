@@ -0,0 +1,388 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Cumulative statistics kept by an extension, like Postgres' own `pg_stat_*` views
+//!
+//! A [`PgStatKind`] keeps a set of counters, declared with [`pg_stat_counters!`], for each
+//! object the extension wants to count things about.  Backends count into a local copy that's
+//! added to the shared totals when their transaction ends, so counting is cheap.  The totals are
+//! written to the `pg_stat` directory when the server shuts down cleanly and read back when it
+//! starts, like `pg_stat_statements` does, and [`pg_stat_function!`] makes a set-returning
+//! function to build a view on.
+//!
+//! Postgres 18's `pgstat_register_kind()` isn't used, since none of the versions pgrx supports
+//! have it.  Like anything else in shared memory, a [`PgStatKind`] must be a `static` passed to
+//! `pg_shmem_init!()` during `_PG_init()`, and the extension must be in
+//! `shared_preload_libraries`.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pgrx::prelude::*;
+//! use pgrx::pgstat::PgStatKind;
+//! use pgrx::{pg_shmem_init, pg_stat_counters, pg_stat_function, PgSharedMemoryInitialization};
+//!
+//! pg_stat_counters! {
+//!     pub struct CacheStats {
+//!         hits,
+//!         misses,
+//!     }
+//! }
+//!
+//! static CACHE_STATS: PgStatKind<CacheStats, 1024> = PgStatKind::new("my_cache");
+//!
+//! #[pg_guard]
+//! pub extern "C" fn _PG_init() {
+//!     pg_shmem_init!(CACHE_STATS);
+//! }
+//!
+//! pg_stat_function!(fn my_cache_stats for CACHE_STATS);
+//!
+//! extension_sql!(
+//!     "CREATE VIEW my_cache_stats AS SELECT * FROM my_cache_stats();",
+//!     name = "my_cache_stats_view",
+//!     requires = [my_cache_stats],
+//! );
+//!
+//! fn lookup(relation: pg_sys::Oid) {
+//!     let hit = true;
+//!     CACHE_STATS.update(relation.as_u32().into(), |stats| {
+//!         if hit {
+//!             stats.hits += 1
+//!         } else {
+//!             stats.misses += 1
+//!         }
+//!     });
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate::callbacks::{register_xact_callback, PgXactCallbackEvent};
+use crate::lwlock::PgLwLock;
+use crate::shmem::{PGRXSharedMemory, PgSharedMemoryInitialization};
+use crate::{log, pg_sys};
+use pgrx_macros::pg_guard;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+
+use crate as pgrx;
+
+/// A set of counters kept for each object by a [`PgStatKind`]
+///
+/// Implement it with [`pg_stat_counters!`] rather than by hand.
+pub trait StatCounters: Copy + Default + PGRXSharedMemory + 'static {
+    /// The name of each counter, in the order of [`StatCounters::values`]
+    const NAMES: &'static [&'static str];
+
+    fn values(&self) -> Vec<i64>;
+
+    /// The counters with `values`, in the order of [`StatCounters::NAMES`], any missing being 0
+    fn from_values(values: &[i64]) -> Self;
+
+    /// Add each of `other`'s counters to ours
+    fn accumulate(&mut self, other: &Self);
+}
+
+/// Declare a struct of `i64` counters that implements [`StatCounters`]
+///
+/// ```rust,no_run
+/// pgrx::pg_stat_counters! {
+///     /// What we know about each table
+///     pub struct TableStats {
+///         scans,
+///         rows_returned,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! pg_stat_counters {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field:ident),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        $vis struct $name {
+            $($(#[$field_meta])* pub $field: i64,)*
+        }
+
+        unsafe impl ::pgrx::PGRXSharedMemory for $name {}
+
+        impl ::pgrx::pgstat::StatCounters for $name {
+            const NAMES: &'static [&'static str] = &[$(stringify!($field)),*];
+
+            fn values(&self) -> Vec<i64> {
+                vec![$(self.$field),*]
+            }
+
+            fn from_values(values: &[i64]) -> Self {
+                let mut values = values.iter().copied();
+                Self { $($field: values.next().unwrap_or(0),)* }
+            }
+
+            fn accumulate(&mut self, other: &Self) {
+                $(self.$field += other.$field;)*
+            }
+        }
+    };
+}
+
+/// Create a set-returning function that shows a [`PgStatKind`]'s counters, one row for each
+/// counter of each object
+///
+/// Its columns are `database oid, object bigint, counter text, value bigint`.  See the
+/// [module documentation](crate::pgstat) for an example.
+#[macro_export]
+macro_rules! pg_stat_function {
+    ($vis:vis fn $name:ident for $kind:path) => {
+        #[::pgrx::pg_extern]
+        $vis fn $name() -> ::pgrx::iter::TableIterator<
+            'static,
+            (
+                ::pgrx::name!(database, ::pgrx::pg_sys::Oid),
+                ::pgrx::name!(object, i64),
+                ::pgrx::name!(counter, &'static str),
+                ::pgrx::name!(value, i64),
+            ),
+        > {
+            ::pgrx::iter::TableIterator::new($kind.rows())
+        }
+    };
+}
+
+/// What a [`PgStatKind`] counts things about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatKey {
+    /// The database the object is in, or [`pg_sys::InvalidOid`] for cluster-wide objects
+    pub database: pg_sys::Oid,
+    /// Whatever identifies the object, such as its OID
+    pub object: u64,
+}
+
+/// Cumulative statistics, kept as a set of counters `C` for each of up to `N` objects
+///
+/// `N` must be a power of two.  Counts for objects beyond the first `N` are discarded.  See the
+/// [module documentation](crate::pgstat) for an example.
+pub struct PgStatKind<C: StatCounters, const N: usize> {
+    name: &'static str,
+    shared: PgLwLock<heapless::FnvIndexMap<StatKey, C, N>>,
+    pending: RefCell<Option<HashMap<StatKey, C>>>,
+    flush_registered: Cell<bool>,
+}
+
+// SAFETY: the shared counters are behind a `PgLwLock`, and the pending ones are only touched by
+// the backend's one thread
+unsafe impl<C: StatCounters, const N: usize> Sync for PgStatKind<C, N> {}
+
+impl<C: StatCounters, const N: usize> PgStatKind<C, N> {
+    /// Declare statistics called `name`, as a `static`.  The name is also their `LWLock`
+    /// tranche's and part of the file they're saved in, so should be unique to the extension.
+    pub const fn new(name: &'static str) -> Self {
+        PgStatKind {
+            name,
+            shared: PgLwLock::with_name(name),
+            pending: RefCell::new(None),
+            flush_registered: Cell::new(false),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Update the counters for `object` in the current database
+    ///
+    /// Updates are made to this backend's own copy of the counters and added to the totals when
+    /// the transaction ends, or on [`PgStatKind::flush`].
+    pub fn update<F: FnOnce(&mut C)>(&'static self, object: u64, f: F) {
+        // SAFETY: `MyDatabaseId` is only written while the backend starts
+        let database = unsafe { pg_sys::MyDatabaseId };
+        self.update_key(StatKey { database, object }, f)
+    }
+
+    /// Like [`PgStatKind::update`], for any database's object, or a cluster-wide one
+    pub fn update_key<F: FnOnce(&mut C)>(&'static self, key: StatKey, f: F) {
+        if !self.flush_registered.replace(true) {
+            let this = AssertUnwindSafe(self);
+            for event in [
+                PgXactCallbackEvent::Commit,
+                PgXactCallbackEvent::Abort,
+                PgXactCallbackEvent::ParallelCommit,
+                PgXactCallbackEvent::ParallelAbort,
+            ] {
+                register_xact_callback(event, move || this.flush());
+            }
+        }
+        f(self.pending.borrow_mut().get_or_insert_with(HashMap::new).entry(key).or_default());
+    }
+
+    /// Add this backend's updates to the totals now, rather than when the transaction ends
+    pub fn flush(&self) {
+        // the callbacks all go once any of them runs, at the end of the transaction
+        self.flush_registered.set(false);
+        let Some(pending) = self.pending.borrow_mut().take() else {
+            return;
+        };
+        let mut shared = self.shared.exclusive();
+        for (key, counters) in pending {
+            match shared.get_mut(&key) {
+                Some(total) => total.accumulate(&counters),
+                None => {
+                    // discarded if the table is full
+                    let _ = shared.insert(key, counters);
+                }
+            }
+        }
+    }
+
+    /// The totals for `object` in the current database, not including updates this backend
+    /// hasn't flushed yet
+    pub fn get(&self, object: u64) -> C {
+        // SAFETY: `MyDatabaseId` is only written while the backend starts
+        let database = unsafe { pg_sys::MyDatabaseId };
+        self.get_key(StatKey { database, object })
+    }
+
+    /// Like [`PgStatKind::get`], for any database's object, or a cluster-wide one
+    pub fn get_key(&self, key: StatKey) -> C {
+        self.shared.share().get(&key).copied().unwrap_or_default()
+    }
+
+    /// The totals for every object
+    pub fn entries(&self) -> Vec<(StatKey, C)> {
+        self.shared.share().iter().map(|(key, counters)| (*key, *counters)).collect()
+    }
+
+    /// Every counter of every object, as `(database, object, counter, value)`, which is what
+    /// [`pg_stat_function!`] returns
+    pub fn rows(&self) -> impl Iterator<Item = (pg_sys::Oid, i64, &'static str, i64)> {
+        self.entries().into_iter().flat_map(|(key, counters)| {
+            C::NAMES
+                .iter()
+                .zip(counters.values())
+                .map(move |(name, value)| (key.database, key.object as i64, *name, value))
+        })
+    }
+
+    /// Forget every object's totals, along with this backend's pending updates
+    pub fn reset(&self) {
+        self.pending.borrow_mut().take();
+        self.shared.exclusive().clear();
+    }
+
+    fn path(&self) -> PathBuf {
+        // relative to the data directory, where the postmaster runs
+        PathBuf::from(format!("pg_stat/pgrx_{}.stat", self.name))
+    }
+
+    /// Read the totals saved at the last clean shutdown, if any
+    fn load(&self) {
+        let path = self.path();
+        let Ok(bytes) = std::fs::read(&path) else {
+            return;
+        };
+        // they're only good for one startup
+        let _ = std::fs::remove_file(&path);
+        let saved: SavedStats = match serde_cbor::from_slice(&bytes) {
+            Ok(saved) => saved,
+            Err(e) => {
+                log!("ignoring statistics in \"{}\": {e}", path.display());
+                return;
+            }
+        };
+
+        // counters may have been added, removed, or moved since they were saved
+        let positions = saved
+            .counters
+            .iter()
+            .map(|name| C::NAMES.iter().position(|known| known == name))
+            .collect::<Vec<_>>();
+        let mut shared = self.shared.exclusive();
+        for (database, object, saved_values) in saved.entries {
+            let mut values = vec![0; C::NAMES.len()];
+            for (position, value) in positions.iter().zip(saved_values) {
+                if let Some(position) = position {
+                    values[*position] = value;
+                }
+            }
+            let key = StatKey { database: database.into(), object };
+            if shared.insert(key, C::from_values(&values)).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Save the totals, for [`PgStatKind::load`] to read at the next startup
+    fn save(&self) -> std::io::Result<()> {
+        let saved = SavedStats {
+            counters: C::NAMES.iter().map(|name| name.to_string()).collect(),
+            entries: self
+                .entries()
+                .into_iter()
+                .map(|(key, counters)| (key.database.as_u32(), key.object, counters.values()))
+                .collect(),
+        };
+        let bytes = serde_cbor::to_vec(&saved).map_err(std::io::Error::other)?;
+        let path = self.path();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, &path)
+    }
+}
+
+impl<C: StatCounters, const N: usize> PgSharedMemoryInitialization for PgStatKind<C, N> {
+    fn pg_init(&'static self) {
+        self.shared.pg_init();
+    }
+
+    unsafe fn shmem_init(&'static self) {
+        // SAFETY: the caller promises we're in the shared memory init hook
+        unsafe {
+            self.shared.shmem_init();
+        }
+        // SAFETY: only the postmaster (or a single-user backend) creates shared memory, so it's
+        // the one to fill it and save it again when the server stops.  `self` is `'static`
+        unsafe {
+            if !pg_sys::IsUnderPostmaster {
+                self.load();
+                pg_sys::on_shmem_exit(
+                    Some(save_at_exit::<C, N>),
+                    pg_sys::Datum::from(self as *const Self),
+                );
+            }
+        }
+    }
+}
+
+#[pg_guard]
+unsafe extern "C" fn save_at_exit<C: StatCounters, const N: usize>(
+    code: std::os::raw::c_int,
+    arg: pg_sys::Datum,
+) {
+    // after a crash, the totals can't be trusted
+    if code != 0 {
+        return;
+    }
+    // SAFETY: `arg` is the `&'static PgStatKind` given to `on_shmem_exit()`
+    let kind = unsafe { &*arg.cast_mut_ptr::<PgStatKind<C, N>>() };
+    if let Err(e) = kind.save() {
+        log!("could not save \"{}\" statistics: {e}", kind.name);
+    }
+}
+
+/// What's saved, naming the counters so they can be matched up again even if they've changed
+#[derive(Serialize, Deserialize)]
+struct SavedStats {
+    counters: Vec<String>,
+    entries: Vec<(u32, u64, Vec<i64>)>,
+}
@@ -21,6 +21,7 @@ pub use crate::{default, name};
 
 // Needed for variant RETURNS
 pub use crate::iter::{SetOfIterator, TableIterator};
+pub use crate::tuplestore::{MaterializedSetOf, MaterializedTable};
 
 // Needed for complex returns and Triggers
 pub use crate::heap_tuple::{PgHeapTuple, PgHeapTupleError};
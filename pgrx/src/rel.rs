@@ -8,6 +8,7 @@
 //LICENSE
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
 //! Provides a safe wrapper around Postgres' `pg_sys::RelationData` struct
+use crate::scan::{HeapScan, IndexScan, ScanKey, ScanKeyError};
 use crate::{
     direct_function_call, name_data_to_str, pg_sys, FromDatum, IntoDatum, PgBox, PgTupleDesc,
 };
@@ -202,6 +203,55 @@ impl PgRelation {
             .into_iter()
     }
 
+    /// Begin a sequential scan of this table, seeing the rows visible to `snapshot`
+    ///
+    /// The scan yields each row as a `PgHeapTuple`, or, without copying, a slot from
+    /// [`HeapScan::next_slot`].  It's ended when dropped.
+    ///
+    /// ## Panics
+    ///
+    /// If this relation is not a table or materialized view
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe as we cannot guarantee that `snapshot` is valid, nor that it stays
+    /// valid for as long as the scan, as a registered or active snapshot would.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn seq_scan(&self, snapshot: pg_sys::Snapshot) -> HeapScan<'_> {
+        unsafe { HeapScan::new(self, snapshot) }
+    }
+
+    /// Begin a scan of `table` through this index, for the rows matching every one of `keys` and
+    /// visible to `snapshot`
+    ///
+    /// The scan yields each row as a `PgHeapTuple`, or, without copying, a slot from
+    /// [`IndexScan::next_slot`].  Rows come in index order for `ForwardScanDirection`, and in
+    /// reverse for `BackwardScanDirection`, if the index is ordered.  It's ended when dropped.
+    ///
+    /// ## Errors
+    ///
+    /// If a key names a column the index doesn't have, or there's no operator for its strategy
+    /// and type in that column's operator family
+    ///
+    /// ## Panics
+    ///
+    /// If this relation is not a btree index on `table`
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe as we cannot guarantee that `snapshot` is valid, nor that it stays
+    /// valid for as long as the scan, as a registered or active snapshot would.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn index_scan<'a>(
+        &'a self,
+        table: &'a PgRelation,
+        snapshot: pg_sys::Snapshot,
+        keys: &[ScanKey],
+        direction: pg_sys::ScanDirection::Type,
+    ) -> Result<IndexScan<'a>, ScanKeyError> {
+        unsafe { IndexScan::new(self, table, snapshot, keys, direction) }
+    }
+
     /// Returned a wrapped `PgTupleDesc`
     ///
     /// The returned `PgTupleDesc` is tied to the lifetime of this `PgRelation` instance.
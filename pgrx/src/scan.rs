@@ -0,0 +1,297 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Scanning tables and indexes directly, without SPI
+//!
+//! [`PgRelation::seq_scan`] reads every row of a table through its table access method, and
+//! [`PgRelation::index_scan`] reads the rows of a table that a btree index finds for a set of
+//! [`ScanKey`]s.  Both scans can be read a slot at a time, with `next_slot`, which copies nothing,
+//! or used as an [`Iterator`] of [`PgHeapTuple`]s, which copies each row.  A scan is ended when it
+//! is dropped, including when it's dropped because of an error.
+//!
+//! ```rust,no_run
+//! use pgrx::prelude::*;
+//! use pgrx::scan::{BTreeStrategy, ScanKey};
+//! use pgrx::{pg_sys, PgRelation};
+//! use std::num::NonZeroUsize;
+//!
+//! let table = PgRelation::open_with_name_and_share_lock("dogs").unwrap();
+//! let index = PgRelation::open_with_name_and_share_lock("dogs_age_idx").unwrap();
+//! let keys = [ScanKey::new(NonZeroUsize::new(1).unwrap(), BTreeStrategy::GreaterEqual, 10)];
+//!
+//! unsafe {
+//!     let snapshot = pg_sys::GetActiveSnapshot();
+//!     let names = index
+//!         .index_scan(&table, snapshot, &keys, pg_sys::ScanDirection::ForwardScanDirection)
+//!         .unwrap()
+//!         .map(|dog| dog.get_by_name::<String>("name").unwrap())
+//!         .collect::<Vec<_>>();
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+
+use core::ptr::{self, NonNull};
+use std::num::NonZeroUsize;
+
+use crate::heap_tuple::PgHeapTuple;
+use crate::{pg_sys, AllocatedByPostgres, IntoDatum, PgRelation, PgTupleSlot};
+
+/// The strategy numbers of the btree operator families
+///
+/// [`PgRelation::index_scan`] only scans btree indexes.  Other access methods, such as hash and
+/// GiST, may find rows that don't match the keys, which would then need checking again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum BTreeStrategy {
+    Less = pg_sys::BTLessStrategyNumber as u16,
+    LessEqual = pg_sys::BTLessEqualStrategyNumber as u16,
+    Equal = pg_sys::BTEqualStrategyNumber as u16,
+    GreaterEqual = pg_sys::BTGreaterEqualStrategyNumber as u16,
+    Greater = pg_sys::BTGreaterStrategyNumber as u16,
+}
+
+impl From<BTreeStrategy> for pg_sys::StrategyNumber {
+    fn from(strategy: BTreeStrategy) -> Self {
+        strategy as pg_sys::StrategyNumber
+    }
+}
+
+/// Errors in the [`ScanKey`]s given to [`PgRelation::index_scan`]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScanKeyError {
+    #[error("The index has no column `{0}`")]
+    NoSuchColumn(NonZeroUsize),
+
+    #[error("Index column `{column}` has no operator for strategy {strategy} taking the Rust type {rust_type}")]
+    NoOperator { column: NonZeroUsize, strategy: pg_sys::StrategyNumber, rust_type: &'static str },
+}
+
+/// A condition on an index column, such as "column 1 >= 10"
+///
+/// The operator is the one in the column's operator family for `strategy` that takes the column's
+/// type and `T`, so it's only found once the key is given to [`PgRelation::index_scan`].  A NULL
+/// value matches no rows, just as `column >= NULL` doesn't.
+pub struct ScanKey {
+    column: NonZeroUsize,
+    strategy: pg_sys::StrategyNumber,
+    argument: Option<pg_sys::Datum>,
+    subtype: pg_sys::Oid,
+    rust_type: &'static str,
+}
+
+impl ScanKey {
+    /// Compare index column `column` to `value` with the operator for `strategy`
+    ///
+    /// Column numbers start at 1, not 0, and count the index's columns, not the table's.
+    pub fn new<T: IntoDatum>(
+        column: NonZeroUsize,
+        strategy: impl Into<pg_sys::StrategyNumber>,
+        value: T,
+    ) -> Self {
+        ScanKey {
+            column,
+            strategy: strategy.into(),
+            argument: value.into_datum(),
+            subtype: T::type_oid(),
+            rust_type: std::any::type_name::<T>(),
+        }
+    }
+
+    /// Look up this key's operator in `index`, and initialize `entry` to use it
+    ///
+    /// # Safety
+    /// `index` must be an open index relation
+    unsafe fn init(
+        &self,
+        index: &PgRelation,
+        entry: &mut pg_sys::ScanKeyData,
+    ) -> Result<(), ScanKeyError> {
+        let natts = unsafe { (*index.rd_index).indnkeyatts } as usize;
+        if self.column.get() > natts {
+            return Err(ScanKeyError::NoSuchColumn(self.column));
+        }
+
+        // SAFETY: an index relation has its operator families, input types, and collations for
+        // each key column, and we just checked this is one of them
+        unsafe {
+            let i = self.column.get() - 1;
+            let opfamily = *index.rd_opfamily.add(i);
+            let lefttype = *index.rd_opcintype.add(i);
+            let collation = *index.rd_indcollation.add(i);
+
+            let opno =
+                pg_sys::get_opfamily_member(opfamily, lefttype, self.subtype, self.strategy as _);
+            if opno == pg_sys::InvalidOid {
+                return Err(ScanKeyError::NoOperator {
+                    column: self.column,
+                    strategy: self.strategy,
+                    rust_type: self.rust_type,
+                });
+            }
+
+            pg_sys::ScanKeyEntryInitialize(
+                entry,
+                if self.argument.is_none() { pg_sys::SK_ISNULL as _ } else { 0 },
+                self.column.get() as _,
+                self.strategy,
+                self.subtype,
+                collation,
+                pg_sys::get_opcode(opno),
+                self.argument.unwrap_or(pg_sys::Datum::from(0)),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A sequential scan of a table, from [`PgRelation::seq_scan`]
+pub struct HeapScan<'a> {
+    scan: NonNull<pg_sys::TableScanDescData>,
+    slot: PgTupleSlot<'a>,
+}
+
+impl<'a> HeapScan<'a> {
+    /// # Safety
+    /// `snapshot` must stay valid for as long as the scan
+    pub(crate) unsafe fn new(table: &'a PgRelation, snapshot: pg_sys::Snapshot) -> Self {
+        assert!(
+            table.is_table() || table.is_matview() || table.is_toast_value(),
+            "`{}` is not a table",
+            table.name()
+        );
+
+        // SAFETY: an open table has a table access method, and we begin the scan the same way
+        // `table_beginscan()` does
+        unsafe {
+            let rel = table.as_ptr();
+            let flags = pg_sys::ScanOptions::SO_TYPE_SEQSCAN
+                | pg_sys::ScanOptions::SO_ALLOW_STRAT
+                | pg_sys::ScanOptions::SO_ALLOW_SYNC
+                | pg_sys::ScanOptions::SO_ALLOW_PAGEMODE;
+            let scan_begin = (*(*rel).rd_tableam).scan_begin.unwrap();
+            let scan = scan_begin(rel, snapshot, 0, ptr::null_mut(), ptr::null_mut(), flags);
+            let slot = PgTupleSlot::from_pg_owned(pg_sys::table_slot_create(rel, ptr::null_mut()));
+            HeapScan { scan: NonNull::new(scan).unwrap(), slot }
+        }
+    }
+
+    /// The next row, or `None` once the scan is done
+    ///
+    /// The slot is reused for every row, so holds only the latest one.
+    pub fn next_slot(&mut self) -> Option<&mut PgTupleSlot<'a>> {
+        // SAFETY: this is `table_scan_getnextslot()`, with the scan and slot we made for the table
+        unsafe {
+            let scan = self.scan.as_ptr();
+            let getnextslot = (*(*(*scan).rs_rd).rd_tableam).scan_getnextslot.unwrap();
+            getnextslot(scan, pg_sys::ScanDirection::ForwardScanDirection, self.slot.as_ptr())
+                .then_some(&mut self.slot)
+        }
+    }
+}
+
+impl<'a> Iterator for HeapScan<'a> {
+    type Item = PgHeapTuple<'a, AllocatedByPostgres>;
+
+    /// The next row, copied into the current memory context
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_slot().map(|slot| slot.to_heap_tuple())
+    }
+}
+
+impl Drop for HeapScan<'_> {
+    fn drop(&mut self) {
+        // SAFETY: this is `table_endscan()`, for a scan we began and haven't ended
+        unsafe {
+            let scan = self.scan.as_ptr();
+            (*(*(*scan).rs_rd).rd_tableam).scan_end.unwrap()(scan)
+        }
+    }
+}
+
+/// A scan of a table through one of its btree indexes, from [`PgRelation::index_scan`]
+pub struct IndexScan<'a> {
+    scan: NonNull<pg_sys::IndexScanDescData>,
+    slot: PgTupleSlot<'a>,
+    direction: pg_sys::ScanDirection::Type,
+    // the index AM may refer to these for as long as the scan
+    _keys: Vec<pg_sys::ScanKeyData>,
+}
+
+impl<'a> IndexScan<'a> {
+    /// # Safety
+    /// `snapshot` must stay valid for as long as the scan
+    pub(crate) unsafe fn new(
+        index: &'a PgRelation,
+        table: &'a PgRelation,
+        snapshot: pg_sys::Snapshot,
+        keys: &[ScanKey],
+        direction: pg_sys::ScanDirection::Type,
+    ) -> Result<Self, ScanKeyError> {
+        assert!(index.is_index(), "`{}` is not an index", index.name());
+        // SAFETY: every relation has its pg_class entry
+        let relam = unsafe { (*index.rd_rel).relam };
+        // btree never sets `xs_recheck`, so every row it finds matches the keys
+        assert_eq!(relam, pg_sys::BTREE_AM_OID, "`{}` is not a btree index", index.name());
+        // SAFETY: an index relation always has its pg_index entry
+        let indrelid = unsafe { (*index.rd_index).indrelid };
+        assert_eq!(
+            indrelid,
+            table.oid(),
+            "`{}` is not an index on `{}`",
+            index.name(),
+            table.name()
+        );
+
+        let mut entries = vec![pg_sys::ScanKeyData::default(); keys.len()];
+        for (key, entry) in keys.iter().zip(entries.iter_mut()) {
+            // SAFETY: we just checked `index` is an index
+            unsafe { key.init(index, entry)? };
+        }
+
+        // SAFETY: both relations are open, the keys are initialized, and `entries` lives as long
+        // as the scan
+        unsafe {
+            let nkeys = entries.len() as _;
+            let scan = pg_sys::index_beginscan(table.as_ptr(), index.as_ptr(), snapshot, nkeys, 0);
+            let slot = PgTupleSlot::from_pg_owned(pg_sys::table_slot_create(
+                table.as_ptr(),
+                ptr::null_mut(),
+            ));
+            pg_sys::index_rescan(scan, entries.as_mut_ptr(), nkeys, ptr::null_mut(), 0);
+            Ok(IndexScan { scan: NonNull::new(scan).unwrap(), slot, direction, _keys: entries })
+        }
+    }
+
+    /// The next row the index finds, or `None` once the scan is done
+    ///
+    /// The slot is reused for every row, so holds only the latest one.
+    pub fn next_slot(&mut self) -> Option<&mut PgTupleSlot<'a>> {
+        // SAFETY: the scan and the slot are both for the index's table
+        unsafe {
+            pg_sys::index_getnext_slot(self.scan.as_ptr(), self.direction, self.slot.as_ptr())
+                .then_some(&mut self.slot)
+        }
+    }
+}
+
+impl<'a> Iterator for IndexScan<'a> {
+    type Item = PgHeapTuple<'a, AllocatedByPostgres>;
+
+    /// The next row, copied into the current memory context
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_slot().map(|slot| slot.to_heap_tuple())
+    }
+}
+
+impl Drop for IndexScan<'_> {
+    fn drop(&mut self) {
+        // SAFETY: we began this scan and haven't ended it
+        unsafe { pg_sys::index_endscan(self.scan.as_ptr()) }
+    }
+}
@@ -0,0 +1,389 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Typed message queues between backends, built on Postgres' `shm_mq`
+//!
+//! A [`ShmMqSegment`] is a dynamic shared memory segment holding a fixed number of queues.  One
+//! backend creates it and passes its [`ShmMqSegment::handle`] to another, typically a
+//! [`crate::bgworkers::DynamicBackgroundWorker`] through its `Datum` argument, which then
+//! [`ShmMqSegment::attach`]es to it.  Each queue has exactly one [`ShmMqSender`] and one
+//! [`ShmMqReceiver`], so two queues give a channel in each direction.
+//!
+//! Messages are serialized as CBOR.  Blocking operations wait on the backend's latch and check
+//! for interrupts, so a query cancel ends them with an `ERROR`.  Background workers that handle
+//! `SIGTERM` themselves should use the non-blocking operations alongside
+//! [`crate::bgworkers::BackgroundWorker::wait_latch`] instead.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pgrx::bgworkers::*;
+//! use pgrx::prelude::*;
+//! use pgrx::shm_mq::ShmMqSegment;
+//!
+//! #[pg_extern]
+//! fn sum_in_worker(numbers: Vec<i64>) -> i64 {
+//!     let channel = ShmMqSegment::create(2, 64 * 1024);
+//!     let worker = BackgroundWorkerBuilder::new("summer")
+//!         .set_library("my_extension")
+//!         .set_function("summer_main")
+//!         .set_argument(channel.handle().into_datum())
+//!         .set_notify_pid(unsafe { pg_sys::MyProcPid })
+//!         .load_dynamic()
+//!         .unwrap();
+//!
+//!     let mut to_worker = channel.sender::<Vec<i64>>(0);
+//!     let mut from_worker = channel.receiver::<i64>(1);
+//!     to_worker.watch_worker(&worker);
+//!     from_worker.watch_worker(&worker);
+//!     to_worker.send(&numbers).unwrap();
+//!     from_worker.recv().unwrap()
+//! }
+//!
+//! #[pg_guard]
+//! #[no_mangle]
+//! pub extern "C" fn summer_main(arg: pg_sys::Datum) {
+//!     let handle = unsafe { u32::from_datum(arg, false) }.unwrap();
+//!     let channel = ShmMqSegment::attach(handle).expect("the backend went away");
+//!     let numbers = channel.receiver::<Vec<i64>>(0).recv().unwrap();
+//!     channel.sender::<i64>(1).send(&numbers.iter().sum()).unwrap();
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate::bgworkers::DynamicBackgroundWorker;
+use crate::dsm::DsmSegment;
+use crate::memcxt::PgMemoryContexts;
+use crate::pg_sys;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+
+/// Identifies segments created by [`ShmMqSegment::create`]
+const SHM_MQ_MAGIC: u64 = 0x7067_7278_5f6d_7100;
+/// The `shm_toc` key of the number of queues.  Queue `n` is at key `n + 1`.
+const NQUEUES_KEY: u64 = 0;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ShmMqError {
+    /// The other end of the queue has detached, or, if it's being watched, its background
+    /// worker has exited.  No more messages can be sent or received.
+    #[error("the other end of the message queue has detached")]
+    Detached,
+    /// The queue is full, or empty, and the operation was not allowed to wait
+    #[error("the message queue operation would block")]
+    WouldBlock,
+    /// A received message couldn't be decoded as the receiver's type
+    #[error("unable to decode message: {0}")]
+    Decode(String),
+}
+
+/// A dynamic shared memory segment containing message queues
+pub struct ShmMqSegment {
+    seg: DsmSegment,
+    toc: NonNull<pg_sys::shm_toc>,
+    nqueues: usize,
+}
+
+impl ShmMqSegment {
+    /// Create a segment with `nqueues` queues, each able to hold `queue_size` bytes of messages
+    /// at a time.  Larger messages are still sent, a piece at a time.
+    pub fn create(nqueues: usize, queue_size: usize) -> Self {
+        // SAFETY: reading a constant
+        let queue_size = unsafe { pg_sys::MAXALIGN(queue_size.max(pg_sys::shm_mq_minimum_size)) };
+        let mut estimator = pg_sys::shm_toc_estimator::default();
+        // what `shm_toc_estimate_chunk()` and `shm_toc_estimate_keys()` would do
+        // SAFETY: just arithmetic
+        let buffer_align =
+            |size: usize| unsafe { pg_sys::TYPEALIGN(pg_sys::ALIGNOF_BUFFER as usize, size) };
+        estimator.space_for_chunks += buffer_align(std::mem::size_of::<u64>());
+        estimator.space_for_chunks += buffer_align(queue_size) * nqueues;
+        estimator.number_of_keys += 1 + nqueues;
+
+        // SAFETY: the segment is big enough for the table of contents and everything we put in
+        // it, and the queues are initialized before anyone else can know the segment's handle
+        unsafe {
+            let size = pg_sys::shm_toc_estimate(&mut estimator);
+            let seg = DsmSegment::create(size);
+            let toc = pg_sys::shm_toc_create(SHM_MQ_MAGIC, seg.as_ptr().cast(), size);
+
+            let header = pg_sys::shm_toc_allocate(toc, std::mem::size_of::<u64>()).cast::<u64>();
+            header.write(nqueues as u64);
+            pg_sys::shm_toc_insert(toc, NQUEUES_KEY, header.cast());
+            for n in 0..nqueues {
+                let address = pg_sys::shm_toc_allocate(toc, queue_size);
+                pg_sys::shm_mq_create(address, queue_size);
+                pg_sys::shm_toc_insert(toc, n as u64 + 1, address);
+            }
+
+            Self { seg, toc: NonNull::new_unchecked(toc), nqueues }
+        }
+    }
+
+    /// Attach to the segment identified by `handle`, returning `None` if it no longer exists.
+    /// Postgres raises an `ERROR` if it isn't a message queue segment.
+    pub fn attach(handle: pg_sys::dsm_handle) -> Option<Self> {
+        let seg = DsmSegment::attach(handle)?;
+        // SAFETY: the segment is attached, and `shm_toc_attach()` only reads its header, which
+        // has room for the magic number in any segment
+        let toc = unsafe { pg_sys::shm_toc_attach(SHM_MQ_MAGIC, seg.as_ptr().cast()) };
+        let Some(toc) = NonNull::new(toc) else {
+            crate::error!("dynamic shared memory segment {handle} does not contain message queues")
+        };
+        // SAFETY: the segment has our magic number, so it has our header
+        let nqueues = unsafe {
+            *pg_sys::shm_toc_lookup(toc.as_ptr(), NQUEUES_KEY, false).cast::<u64>() as usize
+        };
+        Some(Self { seg, toc, nqueues })
+    }
+
+    /// The handle other backends can use to [`ShmMqSegment::attach`] to this segment
+    pub fn handle(&self) -> pg_sys::dsm_handle {
+        self.seg.handle()
+    }
+
+    /// How many queues the segment has
+    pub fn len(&self) -> usize {
+        self.nqueues
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nqueues == 0
+    }
+
+    /// Keep the segment mapped until this value is dropped, rather than only until the current
+    /// resource owner is released
+    pub fn pin_mapping(&mut self) {
+        self.seg.pin_mapping()
+    }
+
+    /// Become the sender on queue `n`.  Postgres raises an `ERROR` if another backend already
+    /// has.
+    pub fn sender<T: Serialize>(&self, n: usize) -> ShmMqSender<'_, T> {
+        // SAFETY: the queue exists, and `MyProc` is us
+        let handle = unsafe {
+            let mq = self.queue(n);
+            pg_sys::shm_mq_set_sender(mq, pg_sys::MyProc);
+            self.attach_queue(mq)
+        };
+        ShmMqSender { handle, pending: VecDeque::new(), _marker: PhantomData }
+    }
+
+    /// Become the receiver on queue `n`.  Postgres raises an `ERROR` if another backend already
+    /// has.
+    pub fn receiver<T: DeserializeOwned>(&self, n: usize) -> ShmMqReceiver<'_, T> {
+        // SAFETY: the queue exists, and `MyProc` is us
+        let handle = unsafe {
+            let mq = self.queue(n);
+            pg_sys::shm_mq_set_receiver(mq, pg_sys::MyProc);
+            self.attach_queue(mq)
+        };
+        ShmMqReceiver { handle, _marker: PhantomData }
+    }
+
+    fn queue(&self, n: usize) -> *mut pg_sys::shm_mq {
+        assert!(n < self.nqueues, "queue {n} is out of range for a segment of {}", self.nqueues);
+        // SAFETY: every queue up to `nqueues` was inserted by `create()`
+        unsafe { pg_sys::shm_toc_lookup(self.toc.as_ptr(), n as u64 + 1, false).cast() }
+    }
+
+    /// # Safety
+    ///
+    /// `mq` must be one of our queues, which we've just become the sender or receiver of
+    unsafe fn attach_queue(&self, mq: *mut pg_sys::shm_mq) -> QueueHandle<'_> {
+        // SAFETY: the handle, and the buffer it allocates for large messages, must outlive the
+        // current memory context, so they get one of their own.  `shm_mq_attach()` raises an
+        // ERROR rather than return NULL
+        let (mqh, cx) = unsafe {
+            let mut cx = PgMemoryContexts::TopMemoryContext
+                .switch_to(|_| PgMemoryContexts::new("pgrx::shm_mq::QueueHandle"));
+            let mqh = cx
+                .switch_to(|_| pg_sys::shm_mq_attach(mq, self.seg.as_raw(), std::ptr::null_mut()));
+            (mqh, cx)
+        };
+        QueueHandle {
+            mqh: NonNull::new(mqh).expect("shm_mq_handle should not be NULL"),
+            _cx: cx,
+            seg: self,
+        }
+    }
+}
+
+/// Our end of a queue, detached when dropped
+struct QueueHandle<'s> {
+    mqh: NonNull<pg_sys::shm_mq_handle>,
+    /// Holds the handle and its buffer, and frees whatever's left of them after we're dropped
+    _cx: PgMemoryContexts,
+    seg: &'s ShmMqSegment,
+}
+
+impl<'s> QueueHandle<'s> {
+    fn as_raw(&self) -> *mut pg_sys::shm_mq_handle {
+        assert!(
+            self.seg.seg.is_attached(),
+            "message queue segment was detached when its resource owner was released"
+        );
+        self.mqh.as_ptr()
+    }
+
+    fn watch_worker(&self, worker: &'s DynamicBackgroundWorker) {
+        // SAFETY: the queue is attached, and the worker outlives our handle
+        unsafe { pg_sys::shm_mq_set_handle(self.as_raw(), worker.as_raw()) }
+    }
+
+    fn wait_for_attach(&self) -> Result<(), ShmMqError> {
+        // SAFETY: the queue is attached
+        result(unsafe { pg_sys::shm_mq_wait_for_attach(self.as_raw()) })
+    }
+}
+
+impl Drop for QueueHandle<'_> {
+    fn drop(&mut self) {
+        // SAFETY: if the segment's still there we detach from the queue.  Otherwise the queue was
+        // detached along with it, and the handle and its buffer are freed with our memory context
+        if self.seg.seg.is_attached() {
+            unsafe { pg_sys::shm_mq_detach(self.mqh.as_ptr()) }
+        }
+    }
+}
+
+fn result(res: pg_sys::shm_mq_result::Type) -> Result<(), ShmMqError> {
+    match res {
+        pg_sys::shm_mq_result::SHM_MQ_SUCCESS => Ok(()),
+        pg_sys::shm_mq_result::SHM_MQ_WOULD_BLOCK => Err(ShmMqError::WouldBlock),
+        pg_sys::shm_mq_result::SHM_MQ_DETACHED => Err(ShmMqError::Detached),
+        other => panic!("unexpected shm_mq_result: {other}"),
+    }
+}
+
+/// The sending end of a message queue
+pub struct ShmMqSender<'s, T> {
+    handle: QueueHandle<'s>,
+    /// Serialized messages that haven't been completely sent, the first of which may be
+    /// partially in the queue
+    pending: VecDeque<Vec<u8>>,
+    _marker: PhantomData<fn(&T)>,
+}
+
+impl<'s, T: Serialize> ShmMqSender<'s, T> {
+    /// Treat `worker` exiting as the receiver detaching, even if it never attached
+    pub fn watch_worker(&mut self, worker: &'s DynamicBackgroundWorker) {
+        self.handle.watch_worker(worker)
+    }
+
+    /// Wait until the receiver has attached to the queue
+    pub fn wait_for_attach(&self) -> Result<(), ShmMqError> {
+        self.handle.wait_for_attach()
+    }
+
+    /// Send `message`, waiting for room in the queue as needed
+    pub fn send(&mut self, message: &T) -> Result<(), ShmMqError> {
+        self.pending.push_back(encode(message));
+        self.flush(false)
+    }
+
+    /// Send `message` without waiting.
+    ///
+    /// If the queue is full, this returns [`ShmMqError::WouldBlock`], but `message` is kept and
+    /// sent ahead of later messages, by [`ShmMqSender::try_flush`] or the next send.
+    pub fn try_send(&mut self, message: &T) -> Result<(), ShmMqError> {
+        self.pending.push_back(encode(message));
+        self.flush(true)
+    }
+
+    /// Send any messages left over from [`ShmMqSender::try_send`] without waiting
+    pub fn try_flush(&mut self) -> Result<(), ShmMqError> {
+        self.flush(true)
+    }
+
+    /// How many messages are waiting to be sent
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn flush(&mut self, nowait: bool) -> Result<(), ShmMqError> {
+        while let Some(bytes) = self.pending.front() {
+            // SAFETY: the queue is attached.  If this would block, `shm_mq` remembers how much
+            // it sent and expects the same message again next time, which is what `pending`
+            // gives it
+            let res = unsafe {
+                pg_sys::shm_mq_send(
+                    self.handle.as_raw(),
+                    bytes.len(),
+                    bytes.as_ptr().cast(),
+                    nowait,
+                    #[cfg(not(any(feature = "pg12", feature = "pg13", feature = "pg14")))]
+                    true,
+                )
+            };
+            result(res)?;
+            self.pending.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// The receiving end of a message queue
+pub struct ShmMqReceiver<'s, T> {
+    handle: QueueHandle<'s>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'s, T: DeserializeOwned> ShmMqReceiver<'s, T> {
+    /// Treat `worker` exiting as the sender detaching, even if it never attached
+    pub fn watch_worker(&mut self, worker: &'s DynamicBackgroundWorker) {
+        self.handle.watch_worker(worker)
+    }
+
+    /// Wait until the sender has attached to the queue
+    pub fn wait_for_attach(&self) -> Result<(), ShmMqError> {
+        self.handle.wait_for_attach()
+    }
+
+    /// Receive the next message, waiting for one as needed
+    pub fn recv(&mut self) -> Result<T, ShmMqError> {
+        self.receive(false)
+    }
+
+    /// Receive the next message without waiting, returning [`ShmMqError::WouldBlock`] if it
+    /// hasn't completely arrived yet
+    pub fn try_recv(&mut self) -> Result<T, ShmMqError> {
+        self.receive(true)
+    }
+
+    fn receive(&mut self, nowait: bool) -> Result<T, ShmMqError> {
+        let mut nbytes = 0;
+        let mut data = std::ptr::null_mut();
+        // SAFETY: the queue is attached, and on success `data` points to `nbytes` bytes that
+        // stay put until the next receive
+        unsafe {
+            result(pg_sys::shm_mq_receive(self.handle.as_raw(), &mut nbytes, &mut data, nowait))?;
+            let bytes = std::slice::from_raw_parts(data.cast::<u8>(), nbytes);
+            serde_cbor::from_slice(bytes).map_err(|e| ShmMqError::Decode(e.to_string()))
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for ShmMqReceiver<'_, T> {
+    type Item = T;
+
+    /// Receive messages until the sender detaches.  Messages that can't be decoded panic.
+    fn next(&mut self) -> Option<T> {
+        match self.recv() {
+            Ok(message) => Some(message),
+            Err(ShmMqError::Detached) => None,
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
+
+fn encode<T: Serialize>(message: &T) -> Vec<u8> {
+    serde_cbor::to_vec(message).expect("failed to encode message as CBOR")
+}
@@ -9,7 +9,7 @@
 //LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
 #![deny(unsafe_op_in_unsafe_fn)]
 use crate::lwlock::*;
-use crate::{pg_sys, PgAtomic};
+use crate::{pg_sys, PgAtomic, PgConditionVariable};
 use std::hash::Hash;
 use uuid::Uuid;
 
@@ -28,6 +28,10 @@ pub unsafe trait PGRXSharedMemory {}
 ///
 /// Custom types need to also implement the `PGRXSharedMemory` trait.
 ///
+/// Shared memory that's created at runtime, or that needs to grow, is available from
+/// [`crate::dsm`] instead, and [`crate::dshash::PgSharedHashMap`] is a growable map with
+/// finer-grained locking than a `heapless` map behind a single `PgLwLock`.
+///
 /// > Extensions that use shared memory **must** be loaded via `postgresql.conf`'s
 /// `shared_preload_libraries` configuration setting.  
 ///
@@ -155,6 +159,19 @@ where
     }
 }
 
+impl PgSharedMemoryInitialization for PgConditionVariable {
+    fn pg_init(&'static self) {
+        PgSharedMem::pg_init_condition_variable(self);
+    }
+
+    /// SAFETY: Must only be called from inside the Postgres shared memory init hook
+    unsafe fn shmem_init(&'static self) {
+        unsafe {
+            PgSharedMem::shmem_init_condition_variable(self);
+        }
+    }
+}
+
 /// This struct contains methods to drive creation of types in shared memory
 pub struct PgSharedMem {}
 
@@ -218,6 +235,39 @@ impl PgSharedMem {
             pg_sys::LWLockRelease(addin_shmem_init_lock);
         }
     }
+
+    /// Must be run from `_PG_init` for condition variables
+    pub fn pg_init_condition_variable(_cv: &PgConditionVariable) {
+        unsafe {
+            pg_sys::RequestAddinShmemSpace(std::mem::size_of::<pg_sys::ConditionVariable>());
+        }
+    }
+
+    /// Must be run from the shared memory init hook, use for `PgConditionVariable`
+    /// SAFETY: Must only be called from inside the Postgres shared memory init hook
+    pub unsafe fn shmem_init_condition_variable(cv: &PgConditionVariable) {
+        unsafe {
+            let shm_name = alloc::ffi::CString::new(Uuid::new_v4().to_string())
+                .expect("CString::new() failed");
+
+            let addin_shmem_init_lock: *mut pg_sys::LWLock =
+                &mut (*pg_sys::MainLWLockArray.add(21)).lock;
+
+            let mut found = false;
+            pg_sys::LWLockAcquire(addin_shmem_init_lock, pg_sys::LWLockMode::LW_EXCLUSIVE);
+            let fv_shmem = pg_sys::ShmemInitStruct(
+                shm_name.into_raw(),
+                std::mem::size_of::<pg_sys::ConditionVariable>(),
+                &mut found,
+            ) as *mut pg_sys::ConditionVariable;
+
+            if !found {
+                pg_sys::ConditionVariableInit(fv_shmem);
+            }
+            cv.attach(fv_shmem);
+            pg_sys::LWLockRelease(addin_shmem_init_lock);
+        }
+    }
 }
 
 unsafe impl PGRXSharedMemory for bool {}
@@ -18,12 +18,20 @@ use std::mem;
 
 mod client;
 mod cursor;
+mod named;
+mod plan_cache;
 mod query;
+mod row;
+mod stream;
 mod tuple;
 pub use client::SpiClient;
 use client::SpiConnection;
 pub use cursor::SpiCursor;
+pub use named::NamedQuery;
+pub use plan_cache::PlanCacheStats;
 pub use query::{OwnedPreparedStatement, PreparedStatement, Query};
+pub use row::{FromSpiRow, SpiColumn, SpiField, SpiRows};
+pub use stream::{SpiStream, SpiStreamRows};
 pub use tuple::{SpiHeapTupleData, SpiHeapTupleDataEntry, SpiTupleTable};
 
 pub type SpiResult<T> = std::result::Result<T, SpiError>;
@@ -188,6 +196,26 @@ pub enum SpiError {
     /// The [`pg_sys::SPI_tuptable`] is null
     #[error("The active `SPI_tuptable` is NULL")]
     NoTupleTable,
+
+    /// A [`NamedQuery`] parameter was never bound to a value
+    #[error("Parameter :{0} has no value")]
+    UnboundParameter(String),
+
+    /// A value was bound to a parameter a [`NamedQuery`] doesn't have
+    #[error("Query has no parameter :{0}")]
+    UnknownParameter(String),
+
+    /// A column a [`FromSpiRow`] type reads is not in the result
+    #[error("{0} is not in the result")]
+    NoSuchColumn(String),
+
+    /// A column's type can't be read as the field a [`FromSpiRow`] type reads it into
+    #[error("column \"{column}\" is of type {column_type}, which can't be read as `{rust_type}`")]
+    ColumnTypeMismatch { column: String, column_type: String, rust_type: &'static str },
+
+    /// A column is NULL, but the field a [`FromSpiRow`] type reads it into isn't an `Option`
+    #[error("column \"{0}\" is NULL, but is read into a field that isn't an `Option`")]
+    UnexpectedNull(String),
 }
 
 pub type Error = SpiError;
@@ -3,7 +3,10 @@ use std::ptr::NonNull;
 
 use crate::datum::DatumWithOid;
 use crate::pg_sys::{self, PgOid};
-use crate::spi::{PreparedStatement, Query, Spi, SpiCursor, SpiError, SpiResult, SpiTupleTable};
+use crate::spi::{
+    FromSpiRow, PreparedStatement, Query, Spi, SpiCursor, SpiError, SpiResult, SpiRows, SpiStream,
+    SpiTupleTable,
+};
 
 use super::query::PreparableQuery;
 
@@ -41,6 +44,17 @@ impl<'conn> SpiClient<'conn> {
         query.execute(self, limit, args)
     }
 
+    /// perform a SELECT statement, reading each row as a `T`
+    ///
+    /// The result's columns are checked against `T` once, before any rows are read.
+    pub fn select_as<'mcx, T: FromSpiRow, Q: Query<'conn>>(
+        &self,
+        query: Q,
+        args: &[DatumWithOid<'mcx>],
+    ) -> SpiResult<SpiRows<'conn, T>> {
+        self.select(query, None, args)?.rows_as()
+    }
+
     /// perform any query (including utility statements) that modify the database in some way
     pub fn update<'mcx, Q: Query<'conn>>(
         &mut self,
@@ -106,6 +120,24 @@ impl<'conn> SpiClient<'conn> {
         query.try_open_cursor(self, args)
     }
 
+    /// Execute the specified query, fetching its rows `batch_size` at a time as they're read
+    ///
+    /// See [`SpiStream`] docs for usage details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` isn't positive.
+    pub fn stream<'mcx, Q: Query<'conn>>(
+        &self,
+        query: Q,
+        args: &[DatumWithOid<'mcx>],
+        batch_size: libc::c_long,
+    ) -> SpiResult<SpiStream<'conn>> {
+        // checked before the cursor is opened, so a panic doesn't leave it open
+        assert!(batch_size > 0, "batch_size must be positive");
+        Ok(SpiStream::new(self.try_open_cursor(query, args)?, batch_size))
+    }
+
     /// Set up a cursor that will execute the specified update (mutating) query
     ///
     /// Rows may be then fetched using [`SpiCursor::fetch`].
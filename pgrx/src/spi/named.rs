@@ -0,0 +1,237 @@
+use crate::datum::{DatumWithOid, IntoDatum};
+use crate::pg_sys::PgOid;
+
+use super::{Query, Spi, SpiClient, SpiError, SpiResult, SpiTupleTable};
+
+/// A query with named parameters, like `:user_id`, whose types come from the values bound to them
+///
+/// The query is rewritten to use positional parameters, `$1`, `$2`, and so on, and its plan is
+/// prepared once per backend for each query and set of parameter types, with
+/// [`Spi::cached_plan`], then reused.
+/// Placeholders inside string literals, quoted identifiers, and comments are left alone, as are
+/// casts like `::int` and the bounds of array slices like `arr[lo:hi]`.  Inside a subscript, a
+/// `:name` right after a value is taken as a slice's upper bound, so `arr[1:hi]` is a slice while
+/// `arr[:n]` uses the parameter `:n`.  A query can't use both named and positional parameters.
+///
+/// ```rust,no_run
+/// use pgrx::prelude::*;
+/// use pgrx::spi::NamedQuery;
+///
+/// # fn names() -> spi::Result<Vec<String>> {
+/// let query = NamedQuery::new("SELECT name FROM users WHERE id > :min_id AND status = :status")
+///     .bind("min_id", 100_i64)
+///     .bind("status", "active");
+/// Spi::connect(|client| {
+///     query.select(&client, None)?.map(|row| Ok(row.get::<String>(1)?.unwrap_or_default())).collect()
+/// })
+/// # }
+/// ```
+pub struct NamedQuery<'mcx> {
+    sql: String,
+    names: Vec<String>,
+    args: Vec<Option<DatumWithOid<'mcx>>>,
+    unknown: Option<String>,
+}
+
+impl<'mcx> NamedQuery<'mcx> {
+    /// Parse the named parameters of `query`
+    pub fn new(query: &str) -> Self {
+        let (sql, names) = rewrite_named_params(query);
+        let args = names.iter().map(|_| None).collect();
+        NamedQuery { sql, names, args, unknown: None }
+    }
+
+    /// Bind `value` to the parameter `:name`, which takes the value's type
+    ///
+    /// Binding a parameter the query doesn't have is an [`SpiError::UnknownParameter`] when the
+    /// query runs.
+    pub fn bind<T: IntoDatum + 'mcx>(mut self, name: &str, value: T) -> Self {
+        match self.names.iter().position(|n| n == name) {
+            Some(idx) => self.args[idx] = Some(DatumWithOid::from(value)),
+            None => {
+                self.unknown.get_or_insert_with(|| name.to_string());
+            }
+        }
+        self
+    }
+
+    /// The query, rewritten to use positional parameters
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    /// The names of the parameters, in the order of their positions
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Run the query, as [`SpiClient::select`] does
+    pub fn select<'conn>(
+        self,
+        client: &SpiClient<'conn>,
+        limit: Option<libc::c_long>,
+    ) -> SpiResult<SpiTupleTable<'conn>> {
+        self.execute(client, limit)
+    }
+
+    /// Run a query that modifies the database, as [`SpiClient::update`] does
+    pub fn update<'conn>(
+        self,
+        client: &mut SpiClient<'conn>,
+        limit: Option<libc::c_long>,
+    ) -> SpiResult<SpiTupleTable<'conn>> {
+        Spi::mark_mutable();
+        self.execute(client, limit)
+    }
+
+    /// Run the query, ignoring any results, as [`Spi::run`] does
+    pub fn run(self) -> SpiResult<()> {
+        Spi::connect(|mut client| self.update(&mut client, None).map(|_| ()))
+    }
+
+    fn execute<'conn>(
+        self,
+        client: &SpiClient<'conn>,
+        limit: Option<libc::c_long>,
+    ) -> SpiResult<SpiTupleTable<'conn>> {
+        if let Some(name) = self.unknown {
+            return Err(SpiError::UnknownParameter(name));
+        }
+        let args = self
+            .args
+            .into_iter()
+            .zip(self.names)
+            .map(|(arg, name)| arg.ok_or(SpiError::UnboundParameter(name)))
+            .collect::<SpiResult<Vec<_>>>()?;
+        let oids = args.iter().map(|arg| PgOid::from(arg.oid())).collect::<Vec<_>>();
+        // each set of parameter types needs a plan of its own
+        let key = format!("pgrx::spi::NamedQuery {oids:?} {}", self.sql);
+        let plan = Spi::cached_plan(&key, &self.sql, &oids)?;
+        (&*plan).execute(client, limit, &args)
+    }
+}
+
+/// Rewrite the `:name` parameters of `query` to `$1`, `$2`, and so on, returning the rewritten
+/// query and the names in the order of their positions
+fn rewrite_named_params(query: &str) -> (String, Vec<String>) {
+    let mut sql = String::with_capacity(query.len());
+    let mut names: Vec<String> = Vec::new();
+    // whether each open `[` is a subscript, like `arr[...]`, rather than an `ARRAY[...]`
+    let mut subscripts: Vec<bool> = Vec::new();
+    let bytes = query.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            // string literals and quoted identifiers, where `E'...'` strings may escape quotes
+            quote @ (b'\'' | b'"') => {
+                let escapes = quote == b'\''
+                    && i > 0
+                    && matches!(bytes[i - 1], b'E' | b'e')
+                    && (i == 1 || !is_ident_byte(bytes[i - 2]));
+                i += 1;
+                while i < bytes.len() {
+                    if escapes && bytes[i] == b'\\' {
+                        i += 2;
+                    } else if bytes[i] == quote {
+                        i += 1;
+                        // a doubled quote is one quote inside the string
+                        if bytes.get(i) != Some(&quote) {
+                            break;
+                        }
+                        i += 1;
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = query[i..].find('\n').map_or(bytes.len(), |end| i + end);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // block comments nest
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i..].starts_with(b"/*") {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            // dollar-quoted strings, `$tag$...$tag$`, but not positional parameters like `$1`
+            b'$' if bytes.get(i + 1).is_some_and(|b| *b == b'$' || is_ident_start(*b))
+                && (i == 0 || !is_ident_byte(bytes[i - 1])) =>
+            {
+                let tag_len = bytes[i + 1..].iter().take_while(|b| is_ident_byte(**b)).count();
+                if bytes.get(i + 1 + tag_len) == Some(&b'$') {
+                    let tag = &query[i..i + tag_len + 2];
+                    i += tag.len();
+                    i = query[i..].find(tag).map_or(bytes.len(), |end| i + end + tag.len());
+                } else {
+                    i += 1;
+                }
+            }
+            b'[' => {
+                subscripts.push(follows_value(&sql) && !follows_array_keyword(&sql));
+                i += 1;
+            }
+            b']' => {
+                subscripts.pop();
+                i += 1;
+            }
+            b':' if bytes.get(i + 1) == Some(&b':') => i += 2,
+            // the `:` of a slice, like `arr[lo:hi]`
+            b':' if subscripts.last() == Some(&true) && follows_value(&sql) => i += 1,
+            b':' if bytes.get(i + 1).is_some_and(|b| is_ident_start(*b)) => {
+                let len = bytes[i + 1..].iter().take_while(|b| is_ident_byte(**b)).count();
+                let name = &query[i + 1..i + 1 + len];
+                let position = match names.iter().position(|n| n == name) {
+                    Some(idx) => idx + 1,
+                    None => {
+                        names.push(name.to_string());
+                        names.len()
+                    }
+                };
+                sql.push_str(&format!("${position}"));
+                i += len + 1;
+                continue;
+            }
+            _ => {
+                i += query[i..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        sql.push_str(&query[start..i.min(bytes.len())]);
+        i = i.min(bytes.len());
+    }
+    (sql, names)
+}
+
+/// Whether what's been written so far ends with a value, like a name, a literal, or `(...)`
+fn follows_value(sql: &str) -> bool {
+    sql.trim_end()
+        .bytes()
+        .last()
+        .is_some_and(|b| is_ident_byte(b) || matches!(b, b')' | b']' | b'"' | b'\''))
+}
+
+fn follows_array_keyword(sql: &str) -> bool {
+    let sql = sql.trim_end();
+    let word = sql.trim_end_matches(|c: char| c.is_ascii() && is_ident_byte(c as u8));
+    sql[word.len()..].eq_ignore_ascii_case("array")
+}
+
+fn is_ident_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
@@ -0,0 +1,96 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::pg_sys::{self, PgOid};
+
+use super::{OwnedPreparedStatement, Spi, SpiResult};
+
+/// How often [`Spi::cached_plan`] has found a plan already prepared, in this backend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlanCacheStats {
+    /// Plans that were found in the cache
+    pub hits: u64,
+    /// Plans that had to be prepared
+    pub misses: u64,
+    /// Plans in the cache now
+    pub plans: usize,
+}
+
+struct CachedPlan {
+    query: String,
+    args: Vec<pg_sys::Oid>,
+    plan: Rc<OwnedPreparedStatement>,
+}
+
+thread_local! {
+    static PLANS: RefCell<HashMap<String, CachedPlan>> = RefCell::new(HashMap::new());
+    static HITS: Cell<u64> = const { Cell::new(0) };
+    static MISSES: Cell<u64> = const { Cell::new(0) };
+}
+
+impl Spi {
+    /// The plan kept for `key`, preparing `query`, with arguments of the types `args`, the first
+    /// time
+    ///
+    /// Plans are kept, with [`pg_sys::SPI_keepplan`], for the rest of the backend's life, and
+    /// Postgres replans them itself when the tables or functions they use change.  If `key` was
+    /// last used with another query or other argument types, its plan is replaced.
+    ///
+    /// ```rust,no_run
+    /// use pgrx::prelude::*;
+    ///
+    /// #[pg_extern]
+    /// fn user_name(id: i64) -> spi::Result<Option<String>> {
+    ///     let plan = Spi::cached_plan(
+    ///         "user_name",
+    ///         "SELECT name FROM users WHERE id = $1",
+    ///         &[PgBuiltInOids::INT8OID.oid()],
+    ///     )?;
+    ///     Spi::connect(|client| client.select(&*plan, Some(1), &[id.into()])?.first().get_one())
+    /// }
+    /// ```
+    pub fn cached_plan(
+        key: &str,
+        query: &str,
+        args: &[PgOid],
+    ) -> SpiResult<Rc<OwnedPreparedStatement>> {
+        let arg_oids = args.iter().map(|arg| arg.value()).collect::<Vec<_>>();
+        // no borrow is held while preparing, which can run code that uses the cache itself
+        let cached = PLANS.with(|plans| {
+            plans
+                .borrow()
+                .get(key)
+                .filter(|cached| cached.query == query && cached.args == arg_oids)
+                .map(|cached| cached.plan.clone())
+        });
+        if let Some(plan) = cached {
+            HITS.set(HITS.get() + 1);
+            return Ok(plan);
+        }
+
+        MISSES.set(MISSES.get() + 1);
+        let plan = Rc::new(Spi::connect(|client| client.prepare(query, args).map(|s| s.keep()))?);
+        let cached = CachedPlan { query: query.to_string(), args: arg_oids, plan: plan.clone() };
+        PLANS.with(|plans| plans.borrow_mut().insert(key.to_string(), cached));
+        Ok(plan)
+    }
+
+    /// How well [`Spi::cached_plan`] has done in this backend
+    pub fn plan_cache_stats() -> PlanCacheStats {
+        PlanCacheStats {
+            hits: HITS.get(),
+            misses: MISSES.get(),
+            plans: PLANS.with(|plans| plans.borrow().len()),
+        }
+    }
+
+    /// Forget every plan [`Spi::cached_plan`] has kept
+    ///
+    /// Each plan is freed once nothing is using it.
+    pub fn clear_plan_cache() {
+        // drop the plans after the borrow, as freeing them doesn't need the cache
+        let plans = PLANS.with(|plans| std::mem::take(&mut *plans.borrow_mut()));
+        drop(plans);
+    }
+}
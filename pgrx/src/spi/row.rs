@@ -0,0 +1,152 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::datum::{is_binary_coercible, lookup_type_name, FromDatum, IntoDatum};
+
+use super::{SpiError, SpiErrorCodes, SpiHeapTupleData, SpiResult, SpiTupleTable};
+
+/// A type that each row of an SPI result can be read into, usually with `#[derive(FromSpiRow)]`
+///
+/// The derive reads each field of a struct from the column of the same name, or each field of a
+/// tuple struct from the column in the same position.  `#[spi(name = "...")]` or
+/// `#[spi(position = N)]`, where the first column is `1`, reads a field from another column.
+/// Fields that are `Option`s are `None` for NULLs, and NULLs in other fields are an error.
+///
+/// ```rust,no_run
+/// use pgrx::prelude::*;
+///
+/// #[derive(FromSpiRow)]
+/// struct Dog {
+///     name: String,
+///     #[spi(name = "age_in_years")]
+///     age: Option<i32>,
+/// }
+///
+/// # fn dogs() -> spi::Result<Vec<Dog>> {
+/// Spi::connect(|client| {
+///     client.select_as::<Dog, _>("SELECT name, age_in_years FROM dogs", &[])?.collect()
+/// })
+/// # }
+/// ```
+pub trait FromSpiRow: Sized {
+    /// Find the columns this type is read from, in field order, checking they can be read as the
+    /// types of their fields
+    fn columns(table: &SpiTupleTable<'_>) -> SpiResult<Vec<usize>>;
+
+    /// Read a row, whose columns are at the `ordinals` that [`FromSpiRow::columns`] found
+    fn from_spi_row(row: &SpiHeapTupleData<'_>, ordinals: &[usize]) -> SpiResult<Self>;
+}
+
+/// Where a field of a [`FromSpiRow`] is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpiColumn {
+    Name(&'static str),
+    /// 1-based
+    Position(usize),
+}
+
+impl SpiColumn {
+    /// The ordinal of this column in `table`, checking it can be read as a `T`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SpiError::NoSuchColumn`] if `table` has no such column, or
+    /// [`SpiError::ColumnTypeMismatch`] if it can't be read as a `T`
+    pub fn find<T: SpiField>(self, table: &SpiTupleTable<'_>) -> SpiResult<usize> {
+        let ordinal = match self {
+            SpiColumn::Name(name) => table.column_ordinal(name),
+            SpiColumn::Position(position) => table.column_type_oid(position).map(|_| position),
+        };
+        let ordinal = ordinal.map_err(|e| match e {
+            SpiError::SpiError(SpiErrorCodes::NoAttribute) => {
+                SpiError::NoSuchColumn(self.to_string())
+            }
+            e => e,
+        })?;
+
+        let type_oid = table.column_type_oid(ordinal)?.value();
+        if !is_binary_coercible::<T::Value>(type_oid) {
+            return Err(SpiError::ColumnTypeMismatch {
+                column: table.column_name(ordinal)?,
+                column_type: lookup_type_name(type_oid),
+                rust_type: std::any::type_name::<T>(),
+            });
+        }
+        Ok(ordinal)
+    }
+}
+
+impl fmt::Display for SpiColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpiColumn::Name(name) => write!(f, "column \"{name}\""),
+            SpiColumn::Position(position) => write!(f, "column {position}"),
+        }
+    }
+}
+
+/// The type of a field of a [`FromSpiRow`]: any type that can be read from a column, or an
+/// `Option` of one, for columns that may be NULL
+pub trait SpiField: Sized {
+    type Value: FromDatum + IntoDatum;
+
+    /// `None` if this type can't hold the NULL `value` is
+    fn from_value(value: Option<Self::Value>) -> Option<Self>;
+
+    /// Read the column at `ordinal` of `row`, which [`SpiColumn::find`] has already checked
+    fn read(row: &SpiHeapTupleData<'_>, ordinal: usize) -> SpiResult<Self> {
+        let entry = row.get_datum_by_ordinal(ordinal)?;
+        // SAFETY: the column's type was checked against `Self::Value` once for the whole result
+        let value = unsafe { entry.value_unchecked::<Self::Value>() };
+        match Self::from_value(value) {
+            Some(value) => Ok(value),
+            None => Err(SpiError::UnexpectedNull(row.column_name(ordinal)?)),
+        }
+    }
+}
+
+impl<T: FromDatum + IntoDatum> SpiField for T {
+    type Value = T;
+
+    fn from_value(value: Option<T>) -> Option<T> {
+        value
+    }
+}
+
+impl<T: FromDatum + IntoDatum> SpiField for Option<T> {
+    type Value = T;
+
+    fn from_value(value: Option<T>) -> Option<Option<T>> {
+        Some(value)
+    }
+}
+
+/// The rows of an SPI result, read as `T`s
+///
+/// See [`SpiClient::select_as`][super::SpiClient::select_as] and [`SpiTupleTable::rows_as`].
+pub struct SpiRows<'conn, T> {
+    table: SpiTupleTable<'conn>,
+    ordinals: Vec<usize>,
+    __marker: PhantomData<fn() -> T>,
+}
+
+impl<'conn, T: FromSpiRow> SpiRows<'conn, T> {
+    pub(super) fn new(table: SpiTupleTable<'conn>) -> SpiResult<Self> {
+        // a result without a tuple table has no rows, so has nothing to check
+        let ordinals = if table.table.is_some() { T::columns(&table)? } else { Vec::new() };
+        Ok(SpiRows { table: table.rewind(), ordinals, __marker: PhantomData })
+    }
+}
+
+impl<'conn, T: FromSpiRow> Iterator for SpiRows<'conn, T> {
+    type Item = SpiResult<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.table.next()?;
+        Some(T::from_spi_row(&row, &self.ordinals))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.table.size_hint()
+    }
+}
@@ -0,0 +1,144 @@
+use std::marker::PhantomData;
+
+use crate::pg_sys;
+
+use super::{
+    FromSpiRow, SpiClient, SpiCursor, SpiError, SpiHeapTupleData, SpiOkCodes, SpiResult,
+    SpiTupleTable,
+};
+
+/// The rows of a query, fetched from a cursor a batch at a time
+///
+/// Unlike a [`SpiTupleTable`], which holds every row of a result, a stream holds one batch, and
+/// frees it before fetching the next.  Each row borrows the stream, so can't be kept past the
+/// next call to [`SpiStream::next_row`], but the values read from it can.  The cursor is closed
+/// when the stream is dropped.
+///
+/// A stream is made with [`SpiClient::stream`].
+///
+/// ```rust,no_run
+/// use pgrx::prelude::*;
+///
+/// # fn foo() -> spi::Result<i64> {
+/// Spi::connect(|client| {
+///     let mut rows = client.stream("SELECT * FROM generate_series(1, 1000000)", &[], 1000)?;
+///     let mut sum = 0;
+///     while let Some(row) = rows.next_row()? {
+///         sum += row.get::<i32>(1)?.unwrap_or_default() as i64;
+///     }
+///     Ok(sum)
+/// })
+/// # }
+/// ```
+pub struct SpiStream<'conn> {
+    cursor: SpiCursor<'conn>,
+    batch_size: libc::c_long,
+    batch: Option<SpiTupleTable<'conn>>,
+    done: bool,
+}
+
+impl<'conn> SpiStream<'conn> {
+    pub(super) fn new(cursor: SpiCursor<'conn>, batch_size: libc::c_long) -> Self {
+        SpiStream { cursor, batch_size, batch: None, done: false }
+    }
+
+    /// The next row, fetching another batch if this one is done
+    ///
+    /// Returns `None` after the last row.
+    pub fn next_row(&mut self) -> SpiResult<Option<SpiHeapTupleData<'_>>> {
+        self.fill()?;
+        match &mut self.batch {
+            Some(batch) => {
+                batch.current += 1;
+                batch.get_heap_tuple()
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read each row as a `T`, checking once that the columns match `T`
+    ///
+    /// The rows are still fetched a batch at a time, but as the `T`s are copied out of the
+    /// batch, they can be kept, so the rows can be iterated.
+    pub fn rows_as<T: FromSpiRow>(self) -> SpiStreamRows<'conn, T> {
+        SpiStreamRows { stream: self, ordinals: None, __marker: PhantomData }
+    }
+
+    /// Make sure the batch has another row, unless there are no more rows
+    fn fill(&mut self) -> SpiResult<()> {
+        loop {
+            if let Some(batch) = &self.batch {
+                if ((batch.current + 1) as usize) < batch.size {
+                    return Ok(());
+                }
+                // a batch that isn't full was the last
+                self.done = batch.size < self.batch_size as usize;
+                self.free_batch();
+            }
+            if self.done {
+                return Ok(());
+            }
+
+            // SAFETY: no concurrent access
+            unsafe {
+                pg_sys::SPI_tuptable = std::ptr::null_mut();
+            }
+            // SAFETY: the cursor is open until it's dropped, and the previous batch was freed
+            unsafe { pg_sys::SPI_cursor_fetch(self.cursor.ptr.as_ptr(), true, self.batch_size) }
+            self.batch = Some(SpiClient::prepare_tuple_table(SpiOkCodes::Fetch as i32)?);
+        }
+    }
+
+    fn free_batch(&mut self) {
+        if let Some(table) = self.batch.take().and_then(|batch| batch.table) {
+            // SAFETY: the table came from `SPI_cursor_fetch`, and no row borrows it any more
+            unsafe { pg_sys::SPI_freetuptable(table) }
+        }
+    }
+}
+
+impl Drop for SpiStream<'_> {
+    fn drop(&mut self) {
+        // the cursor is closed after, when it's dropped
+        self.free_batch();
+    }
+}
+
+/// The rows of a [`SpiStream`], read as `T`s
+///
+/// See [`SpiStream::rows_as`].
+pub struct SpiStreamRows<'conn, T> {
+    stream: SpiStream<'conn>,
+    ordinals: Option<Vec<usize>>,
+    __marker: PhantomData<fn() -> T>,
+}
+
+impl<'conn, T: FromSpiRow> Iterator for SpiStreamRows<'conn, T> {
+    type Item = SpiResult<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.stream.fill() {
+            return self.fail(e);
+        }
+        if self.ordinals.is_none() {
+            // every batch has the same columns, so checking the first checks them all
+            match T::columns(self.stream.batch.as_ref()?) {
+                Ok(ordinals) => self.ordinals = Some(ordinals),
+                Err(e) => return self.fail(e),
+            }
+        }
+        match self.stream.next_row() {
+            Ok(row) => Some(T::from_spi_row(&row?, self.ordinals.as_deref().unwrap())),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl<T> SpiStreamRows<'_, T> {
+    /// Stop reading rows after an error that every later row would also have
+    fn fail(&mut self, e: SpiError) -> Option<SpiResult<T>> {
+        self.stream.done = true;
+        self.stream.free_batch();
+        Some(Err(e))
+    }
+}
@@ -8,7 +8,7 @@ use crate::memcxt::PgMemoryContexts;
 use crate::pg_sys::panic::ErrorReportable;
 use crate::prelude::*;
 
-use super::{SpiError, SpiErrorCodes, SpiOkCodes, SpiResult};
+use super::{FromSpiRow, SpiError, SpiErrorCodes, SpiOkCodes, SpiResult, SpiRows};
 
 #[derive(Debug)]
 pub struct SpiTupleTable<'conn> {
@@ -249,6 +249,16 @@ impl<'conn> SpiTupleTable<'conn> {
         }
     }
 
+    /// Read each row as a `T`, checking once that the columns match `T`
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`SpiError::NoSuchColumn`] or [`SpiError::ColumnTypeMismatch`] if `T` can't be
+    /// read from this table's columns
+    pub fn rows_as<T: FromSpiRow>(self) -> SpiResult<SpiRows<'conn, T>> {
+        SpiRows::new(self)
+    }
+
     /// Returns the ordinal (1-based position) of the specified column name
     ///
     /// # Errors
@@ -451,6 +461,30 @@ impl<'conn> SpiHeapTupleData<'conn> {
         }
     }
 
+    /// Returns column name of the 1-based `ordinal` position
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SpiError(SpiError::NoAttribute)`] if the specified ordinal value is out of bounds
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the column name at the specified ordinal position is not also
+    /// a valid UTF8 string.
+    pub fn column_name(&self, ordinal: usize) -> SpiResult<String> {
+        self.check_ordinal_bounds(ordinal)?;
+        unsafe {
+            // SAFETY:  we own a valid tupdesc and we know ordinal is in bounds
+            let name = pg_sys::SPI_fname(self.tupdesc.as_ptr(), ordinal as i32);
+            let str =
+                CStr::from_ptr(name).to_str().expect("column name is not value UTF8").to_string();
+
+            // SAFETY: we just asked Postgres to allocate name for us
+            pg_sys::pfree(name as *mut _);
+            Ok(str)
+        }
+    }
+
     /// is the specified ordinal valid for the underlying tuple descriptor?
     #[inline]
     fn check_ordinal_bounds(&self, ordinal: usize) -> SpiResult<()> {
@@ -480,6 +514,24 @@ impl<'conn> SpiHeapTupleDataEntry<'conn> {
         }
     }
 
+    /// Like [`SpiHeapTupleDataEntry::value`], without checking the datum's type
+    ///
+    /// # Safety
+    ///
+    /// The datum's type must be one `T` can be read from
+    pub(super) unsafe fn value_unchecked<T: FromDatum>(&self) -> Option<T> {
+        unsafe {
+            T::from_datum_in_memory_context(
+                PgMemoryContexts::CurrentMemoryContext
+                    .parent()
+                    .expect("parent memory context is absent"),
+                self.datum.unwrap_or(pg_sys::Datum::from(0)),
+                self.datum.is_none(),
+                self.type_oid,
+            )
+        }
+    }
+
     pub fn oid(&self) -> pg_sys::Oid {
         self.type_oid
     }
@@ -0,0 +1,312 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Set-returning functions that return all their rows at once, in a tuplestore
+//!
+//! A [`SetOfIterator`] or [`TableIterator`] returns one row each time Postgres calls the
+//! function, in what Postgres calls "value-per-call" mode.  That suits a lazy iterator, but not a
+//! function that must produce its rows all together, such as one that holds a lock or a cursor
+//! while it works.  [`MaterializedSetOf`] and [`MaterializedTable`] instead run once, writing
+//! every row to a [`TupleStoreWriter`], in Postgres' "materialize" mode.  The rows are kept in a
+//! tuplestore, which spills to a temporary file once it outgrows `work_mem`.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pgrx::prelude::*;
+//!
+//! #[pg_extern]
+//! fn squares(n: i32) -> MaterializedTable<'static, (name!(n, i32), name!(square, i64))> {
+//!     MaterializedTable::new(move |rows| {
+//!         for i in 1..=n {
+//!             rows.push((i, i as i64 * i as i64));
+//!         }
+//!     })
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate::callconv::RetAbi;
+use crate::iter::{SetOfIterator, TableIterator};
+use crate::{ereport, is_a, pg_sys, IntoDatum, IntoHeapTuple, PgMemoryContexts, PgSqlErrorCode};
+use pgrx_sql_entity_graph::metadata::{
+    ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
+};
+use std::ffi::c_int;
+use std::ptr;
+
+type Fill<'a, Row> = Box<dyn FnOnce(&mut TupleStoreWriter<Row>) + 'a>;
+
+/// Returns a `SETOF T` from an SQL function, written all at once to a [`TupleStoreWriter`]
+///
+/// ```rust,no_run
+/// use pgrx::prelude::*;
+///
+/// #[pg_extern]
+/// fn words<'a>(input: &'a str) -> MaterializedSetOf<'a, &'a str> {
+///     MaterializedSetOf::new(move |rows| rows.extend(input.split_whitespace()))
+/// }
+/// ```
+pub struct MaterializedSetOf<'a, T> {
+    fill: Fill<'a, T>,
+}
+
+impl<'a, T> MaterializedSetOf<'a, T> {
+    /// Return the rows `fill` writes, after the function returns
+    pub fn new(fill: impl FnOnce(&mut TupleStoreWriter<T>) + 'a) -> Self {
+        MaterializedSetOf { fill: Box::new(fill) }
+    }
+}
+
+/// Returns a `TABLE (...)` from an SQL function, written all at once to a [`TupleStoreWriter`]
+///
+/// Like a [`TableIterator`], `Row` is a tuple of columns named with [`name!`][crate::name].
+pub struct MaterializedTable<'a, Row> {
+    fill: Fill<'a, Row>,
+}
+
+impl<'a, Row> MaterializedTable<'a, Row> {
+    /// Return the rows `fill` writes, after the function returns
+    pub fn new(fill: impl FnOnce(&mut TupleStoreWriter<Row>) + 'a) -> Self {
+        MaterializedTable { fill: Box::new(fill) }
+    }
+}
+
+/// Writes the rows of a [`MaterializedSetOf`] or [`MaterializedTable`] to its tuplestore
+///
+/// Anything Postgres allocates converting a row is freed once it's written, so a function can
+/// return many more rows than fit in memory.
+pub struct TupleStoreWriter<Row> {
+    store: *mut pg_sys::Tuplestorestate,
+    tupdesc: pg_sys::TupleDesc,
+    put: unsafe fn(Row, *mut pg_sys::Tuplestorestate, pg_sys::TupleDesc),
+    row_cx: PgMemoryContexts,
+}
+
+impl<Row> TupleStoreWriter<Row> {
+    /// Write one row
+    pub fn push(&mut self, row: Row) {
+        let (store, tupdesc, put) = (self.store, self.tupdesc, self.put);
+        // SAFETY: the store and tupdesc were made for this function's result type, which `Row`
+        // was checked against when the function was created, and the tuplestore copies the row
+        // into its own memory before the row's context is reset
+        unsafe {
+            self.row_cx.switch_to(|_| put(row, store, tupdesc));
+            self.row_cx.reset();
+        }
+    }
+
+    /// How many rows have been written
+    pub fn len(&self) -> usize {
+        // SAFETY: the store is valid until the query ends
+        unsafe { pg_sys::tuplestore_tuple_count(self.store) as usize }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<Row> Extend<Row> for TupleStoreWriter<Row> {
+    fn extend<I: IntoIterator<Item = Row>>(&mut self, rows: I) {
+        for row in rows {
+            self.push(row);
+        }
+    }
+}
+
+unsafe fn put_value<T: IntoDatum>(
+    value: T,
+    store: *mut pg_sys::Tuplestorestate,
+    tupdesc: pg_sys::TupleDesc,
+) {
+    let datum = value.into_datum();
+    let mut isnull = datum.is_none();
+    let mut datum = datum.unwrap_or(pg_sys::Datum::from(0));
+    // SAFETY: the caller gives us a store and its one-column tupdesc
+    unsafe { pg_sys::tuplestore_putvalues(store, tupdesc, &mut datum, &mut isnull) }
+}
+
+unsafe fn put_column<C: IntoDatum>(
+    (column,): (C,),
+    store: *mut pg_sys::Tuplestorestate,
+    tupdesc: pg_sys::TupleDesc,
+) {
+    unsafe { put_value(column, store, tupdesc) }
+}
+
+unsafe fn put_row<Row: IntoHeapTuple>(
+    row: Row,
+    store: *mut pg_sys::Tuplestorestate,
+    tupdesc: pg_sys::TupleDesc,
+) {
+    // SAFETY: the caller gives us a store and the tupdesc of its rows
+    unsafe { pg_sys::tuplestore_puttuple(store, row.into_heap_tuple(tupdesc)) }
+}
+
+/// Set up `fcinfo` to return a tuplestore in materialize mode, then fill it
+///
+/// # Safety
+///
+/// `fcinfo` must be that of a function returning a set of the rows `put` writes
+unsafe fn materialize<Row>(
+    fcinfo: pg_sys::FunctionCallInfo,
+    put: unsafe fn(Row, *mut pg_sys::Tuplestorestate, pg_sys::TupleDesc),
+    fill: Fill<'_, Row>,
+) -> pg_sys::Datum {
+    unsafe {
+        let rsinfo = (*fcinfo).resultinfo.cast::<pg_sys::ReturnSetInfo>();
+        if rsinfo.is_null() || !is_a(rsinfo.cast(), pg_sys::NodeTag::T_ReturnSetInfo) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                "set-valued function called in context that cannot accept a set"
+            );
+        }
+        let allowed = (*rsinfo).allowedModes;
+        if allowed & pg_sys::SetFunctionReturnMode::SFRM_Materialize as c_int == 0 {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                "materialize mode required, but it is not allowed in this context"
+            );
+        }
+
+        // the tuplestore and its tupdesc must outlive this call, until the query is done with them
+        let per_query = (*(*rsinfo).econtext).ecxt_per_query_memory;
+        let (store, tupdesc) = PgMemoryContexts::For(per_query).switch_to(|_| {
+            let random_access =
+                allowed & pg_sys::SetFunctionReturnMode::SFRM_Materialize_Random as c_int != 0;
+            let store = pg_sys::tuplestore_begin_heap(random_access, false, pg_sys::work_mem);
+            (store, result_tupdesc(fcinfo))
+        });
+        (*rsinfo).returnMode = pg_sys::SetFunctionReturnMode::SFRM_Materialize;
+        (*rsinfo).setResult = store;
+        (*rsinfo).setDesc = tupdesc;
+
+        let mut writer = TupleStoreWriter {
+            store,
+            tupdesc,
+            put,
+            row_cx: PgMemoryContexts::new("TupleStoreWriter rows"),
+        };
+        fill(&mut writer);
+
+        // with materialize mode, Postgres ignores what the function itself returns
+        (*fcinfo).isnull = true;
+        pg_sys::Datum::from(0)
+    }
+}
+
+/// The tupdesc of the rows a function returns, in the current memory context
+unsafe fn result_tupdesc(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::TupleDesc {
+    let mut typid = pg_sys::InvalidOid;
+    let mut tupdesc = ptr::null_mut();
+    unsafe {
+        match pg_sys::get_call_result_type(fcinfo, &mut typid, &mut tupdesc) {
+            pg_sys::TypeFuncClass::TYPEFUNC_COMPOSITE => pg_sys::CreateTupleDescCopy(tupdesc),
+            pg_sys::TypeFuncClass::TYPEFUNC_SCALAR => {
+                // what Postgres itself uses for the rows of a `SETOF` scalar
+                let tupdesc = pg_sys::CreateTemplateTupleDesc(1);
+                pg_sys::TupleDescInitEntry(tupdesc, 1, ptr::null(), typid, -1, 0);
+                tupdesc
+            }
+            _ => {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                    "function returning a set of records called in context that cannot accept type record"
+                );
+            }
+        }
+    }
+}
+
+unsafe impl<'a, T> RetAbi for MaterializedSetOf<'a, T>
+where
+    T: IntoDatum,
+{
+    type Item = T;
+    type Ret = Self;
+
+    fn to_ret(self) -> Self::Ret {
+        self
+    }
+
+    unsafe fn box_ret_in_fcinfo(fcinfo: pg_sys::FunctionCallInfo, ret: Self::Ret) -> pg_sys::Datum {
+        unsafe { materialize(fcinfo, put_value::<T>, ret.fill) }
+    }
+
+    unsafe fn fill_fcinfo_fcx(&self, _fcinfo: pg_sys::FunctionCallInfo) {}
+
+    unsafe fn move_into_fcinfo_fcx(self, _fcinfo: pg_sys::FunctionCallInfo) {}
+}
+
+unsafe impl<'a, C> RetAbi for MaterializedTable<'a, (C,)>
+where
+    C: IntoDatum,
+{
+    type Item = (C,);
+    type Ret = Self;
+
+    fn to_ret(self) -> Self::Ret {
+        self
+    }
+
+    unsafe fn box_ret_in_fcinfo(fcinfo: pg_sys::FunctionCallInfo, ret: Self::Ret) -> pg_sys::Datum {
+        unsafe { materialize(fcinfo, put_column::<C>, ret.fill) }
+    }
+
+    unsafe fn fill_fcinfo_fcx(&self, _fcinfo: pg_sys::FunctionCallInfo) {}
+
+    unsafe fn move_into_fcinfo_fcx(self, _fcinfo: pg_sys::FunctionCallInfo) {}
+}
+
+unsafe impl<'a, Row> RetAbi for MaterializedTable<'a, Row>
+where
+    Row: IntoHeapTuple,
+{
+    type Item = Row;
+    type Ret = Self;
+
+    fn to_ret(self) -> Self::Ret {
+        self
+    }
+
+    unsafe fn box_ret_in_fcinfo(fcinfo: pg_sys::FunctionCallInfo, ret: Self::Ret) -> pg_sys::Datum {
+        unsafe { materialize(fcinfo, put_row::<Row>, ret.fill) }
+    }
+
+    unsafe fn fill_fcinfo_fcx(&self, _fcinfo: pg_sys::FunctionCallInfo) {}
+
+    unsafe fn move_into_fcinfo_fcx(self, _fcinfo: pg_sys::FunctionCallInfo) {}
+}
+
+unsafe impl<'a, T> SqlTranslatable for MaterializedSetOf<'a, T>
+where
+    SetOfIterator<'a, T>: SqlTranslatable,
+{
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Err(ArgumentError::SetOf)
+    }
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        SetOfIterator::<'a, T>::return_sql()
+    }
+}
+
+unsafe impl<'a, Row> SqlTranslatable for MaterializedTable<'a, Row>
+where
+    TableIterator<'a, Row>: SqlTranslatable,
+{
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        Err(ArgumentError::Table)
+    }
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        TableIterator::<'a, Row>::return_sql()
+    }
+}
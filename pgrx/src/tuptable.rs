@@ -0,0 +1,339 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Provides a safe interface to Postgres `TupleTableSlot` objects.
+//!
+//! A slot is how the executor, table access methods, and triggers pass rows around.  Unlike a
+//! [`PgHeapTuple`], which is always a formed tuple, a slot may hold a heap tuple, a tuple in a
+//! shared buffer, or just an array of values, which Postgres calls a "virtual" tuple.  Values are
+//! only "deformed" out of the underlying tuple as they're asked for.
+#![deny(unsafe_op_in_unsafe_fn)]
+
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+use std::num::NonZeroUsize;
+
+use crate::datum::{FromDatum, IntoDatum, TryFromDatumError, UnboxDatum};
+use crate::heap_tuple::{check_attribute_type, PgHeapTuple};
+use crate::{pg_sys, AllocatedByPostgres, PgTupleDesc, WhoAllocated};
+
+/// A lightweight wrapper around a Postgres [`pg_sys::TupleTableSlot`]
+///
+/// A [`PgTupleSlot`] either borrows a slot that Postgres made, from [`PgTupleSlot::from_pg`], or
+/// owns one it made itself, with [`PgTupleSlot::new_virtual`] or [`PgTupleSlot::from_heap_tuple`],
+/// which it drops along with itself.
+///
+/// ```rust,no_run
+/// use pgrx::prelude::*;
+/// use pgrx::{PgTupleDesc, PgTupleSlot};
+/// use std::num::NonZeroUsize;
+///
+/// let tupdesc = PgTupleDesc::for_composite_type("dog").unwrap();
+/// let mut slot = PgTupleSlot::new_virtual(&tupdesc);
+/// slot.set_by_name("name", "Brandy").unwrap();
+/// slot.set_by_name("age", 42).unwrap();
+///
+/// assert_eq!(slot.get_by_name::<i32>("age").unwrap(), Some(42));
+/// assert!(slot.is_null(NonZeroUsize::new(3).unwrap()).is_err());
+///
+/// let tuple = slot.to_heap_tuple();
+/// assert_eq!(tuple.get_by_name::<String>("name").unwrap(), Some("Brandy".to_string()));
+/// ```
+pub struct PgTupleSlot<'a> {
+    slot: NonNull<pg_sys::TupleTableSlot>,
+    owned: bool,
+    _marker: PhantomData<&'a mut pg_sys::TupleTableSlot>,
+}
+
+impl<'a> PgTupleSlot<'a> {
+    /// Wrap a slot that Postgres made, without taking ownership of it
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe as we cannot guarantee that `slot` points to a valid, initialized
+    /// [`pg_sys::TupleTableSlot`], nor that it, and its tuple descriptor, outlive `'a`.
+    pub unsafe fn from_pg(slot: *mut pg_sys::TupleTableSlot) -> PgTupleSlot<'a> {
+        PgTupleSlot {
+            slot: NonNull::new(slot).expect("slot must not be NULL"),
+            owned: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Wrap a slot made by `MakeSingleTupleTableSlot`, such as by `table_slot_create`, taking
+    /// ownership of it
+    ///
+    /// The slot is dropped via `pg_sys::ExecDropSingleTupleTableSlot()` when this instance is
+    /// dropped.
+    ///
+    /// ## Safety
+    ///
+    /// As for [`PgTupleSlot::from_pg`], and nothing else may drop the slot.
+    pub unsafe fn from_pg_owned(slot: *mut pg_sys::TupleTableSlot) -> PgTupleSlot<'a> {
+        PgTupleSlot {
+            slot: NonNull::new(slot).expect("slot must not be NULL"),
+            owned: true,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Make a virtual slot in the shape of `tupdesc`, with every attribute set to NULL
+    ///
+    /// Values set into it with [`PgTupleSlot::set`] are allocated in the current memory context.
+    pub fn new_virtual(tupdesc: &'a PgTupleDesc<'_>) -> PgTupleSlot<'a> {
+        unsafe {
+            // SAFETY: the slot keeps a pointer to `tupdesc`, which we borrow for as long as the
+            // slot lives, and `TTSOpsVirtual` is a static
+            let slot = pg_sys::MakeSingleTupleTableSlot(
+                tupdesc.as_ptr(),
+                ptr::addr_of!(pg_sys::TTSOpsVirtual),
+            );
+            pg_sys::ExecStoreAllNullTuple(slot);
+            PgTupleSlot { slot: NonNull::new_unchecked(slot), owned: true, _marker: PhantomData }
+        }
+    }
+
+    /// Make a slot holding `tuple`, which it borrows rather than copies
+    pub fn from_heap_tuple<AllocatedBy: WhoAllocated>(
+        tuple: &'a PgHeapTuple<'_, AllocatedBy>,
+    ) -> PgTupleSlot<'a> {
+        unsafe {
+            // SAFETY: both the tuple and its descriptor are borrowed for as long as the slot
+            // lives, and the slot mustn't free the tuple, which it doesn't own
+            let slot = pg_sys::MakeSingleTupleTableSlot(
+                tuple.tupdesc().as_ptr(),
+                ptr::addr_of!(pg_sys::TTSOpsHeapTuple),
+            );
+            pg_sys::ExecStoreHeapTuple(tuple.as_ptr(), slot, false);
+            PgTupleSlot { slot: NonNull::new_unchecked(slot), owned: true, _marker: PhantomData }
+        }
+    }
+
+    /// Form a new heap tuple from the values in this slot
+    ///
+    /// The tuple is allocated in the current memory context, and doesn't depend on the slot.
+    ///
+    /// ## Panics
+    ///
+    /// If the slot is empty.
+    pub fn to_heap_tuple(&self) -> PgHeapTuple<'a, AllocatedByPostgres> {
+        assert!(!self.is_empty(), "an empty slot has no tuple to copy");
+        unsafe {
+            // SAFETY: every kind of slot knows how to copy itself into a heap tuple, and the
+            // slot's descriptor outlives 'a
+            let slot = self.as_ptr();
+            let copy_heap_tuple = (*(*slot).tts_ops).copy_heap_tuple.unwrap();
+            let tuple = copy_heap_tuple(slot);
+            PgHeapTuple::from_heap_tuple(
+                PgTupleDesc::from_pg_unchecked((*slot).tts_tupleDescriptor),
+                tuple,
+            )
+        }
+    }
+
+    /// The underlying [`pg_sys::TupleTableSlot`] pointer
+    #[inline]
+    pub fn as_ptr(&self) -> *mut pg_sys::TupleTableSlot {
+        self.slot.as_ptr()
+    }
+
+    /// The tuple descriptor describing this slot's attributes
+    #[inline]
+    pub fn tupdesc(&self) -> PgTupleDesc<'_> {
+        // SAFETY: a valid slot always has a descriptor, which lives at least as long as it
+        unsafe { PgTupleDesc::from_pg_unchecked(self.slot.as_ref().tts_tupleDescriptor) }
+    }
+
+    /// Does this slot hold no tuple at all?
+    ///
+    /// This is not the same as holding a tuple whose attributes are all NULL.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        // SAFETY: a valid slot's flags are always initialized
+        unsafe { self.slot.as_ref().tts_flags as u32 & pg_sys::TTS_FLAG_EMPTY != 0 }
+    }
+
+    /// Is this a virtual slot, one that holds values rather than a formed tuple?
+    #[inline]
+    pub fn is_virtual(&self) -> bool {
+        // SAFETY: a valid slot always has its ops
+        unsafe { ptr::eq(self.slot.as_ref().tts_ops, ptr::addr_of!(pg_sys::TTSOpsVirtual)) }
+    }
+
+    /// Make this slot independent of whatever its tuple came from, such as a shared buffer or
+    /// another slot, by copying everything it refers to into its own memory
+    pub fn materialize(&mut self) {
+        unsafe {
+            // SAFETY: this is `ExecMaterializeSlot()`, which every kind of slot implements
+            let slot = self.as_ptr();
+            (*(*slot).tts_ops).materialize.unwrap()(slot)
+        }
+    }
+
+    /// Get the raw Datum of the specified attribute, deforming the tuple up to it if needed
+    ///
+    /// Attribute numbers start at 1, not 0.
+    ///
+    /// ## Errors
+    /// - return [`TryFromDatumError::NoSuchAttributeNumber`] if the attribute does not exist
+    pub fn get_datum(
+        &self,
+        attno: NonZeroUsize,
+    ) -> Result<Option<pg_sys::Datum>, TryFromDatumError> {
+        let natts = self.tupdesc().len();
+        if attno.get() > natts || self.is_empty() {
+            return Err(TryFromDatumError::NoSuchAttributeNumber(attno));
+        }
+
+        unsafe {
+            // SAFETY: this is `slot_getattr()`: we checked `attno` is in bounds, and once the
+            // slot has deformed that many attributes, their values and nulls are valid
+            let slot = self.as_ptr();
+            if ((*slot).tts_nvalid as usize) < attno.get() {
+                pg_sys::slot_getsomeattrs_int(slot, attno.get() as _);
+            }
+            let i = attno.get() - 1;
+            if *(*slot).tts_isnull.add(i) {
+                Ok(None)
+            } else {
+                Ok(Some(*(*slot).tts_values.add(i)))
+            }
+        }
+    }
+
+    /// Is the specified attribute NULL?
+    ///
+    /// Attribute numbers start at 1, not 0.
+    ///
+    /// ## Errors
+    /// - return [`TryFromDatumError::NoSuchAttributeNumber`] if the attribute does not exist
+    pub fn is_null(&self, attno: NonZeroUsize) -> Result<bool, TryFromDatumError> {
+        self.get_datum(attno).map(|datum| datum.is_none())
+    }
+
+    /// Retrieve the value of the specified attribute, by index.
+    ///
+    /// Attribute numbers start at 1, not 0.  A borrowed value, like a `&str`, borrows the slot, as
+    /// the slot may free what it points to.  The following code will not compile:
+    ///
+    /// ```rust,compile_fail
+    /// use pgrx::PgTupleSlot;
+    /// use std::num::NonZeroUsize;
+    ///
+    /// fn leak(slot: &PgTupleSlot, attno: NonZeroUsize) -> &'static [u8] {
+    ///     slot.get::<&'static [u8]>(attno).unwrap().unwrap()
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    /// - return [`TryFromDatumError::NoSuchAttributeNumber`] if the attribute does not exist
+    /// - return [`TryFromDatumError::IncompatibleTypes`] if the Rust type of the `value` is not
+    /// compatible with the attribute's Postgres type
+    pub fn get<'tup, T>(&'tup self, attno: NonZeroUsize) -> Result<Option<T>, TryFromDatumError>
+    where
+        T: FromDatum + IntoDatum + UnboxDatum<As<'tup> = T> + 'tup,
+    {
+        let datum = self.get_datum(attno)?;
+        let type_oid = match T::type_oid() {
+            record @ pg_sys::RECORDOID => record,
+            _ => self.tupdesc().get(attno.get() - 1).unwrap().atttypid,
+        };
+
+        unsafe {
+            // SAFETY: the datum came from this slot, which says it's a `type_oid`
+            T::try_from_datum(datum.unwrap_or(pg_sys::Datum::from(0)), datum.is_none(), type_oid)
+        }
+    }
+
+    /// Retrieve the value of the specified attribute, by name.
+    ///
+    /// ## Errors
+    /// - return [`TryFromDatumError::NoSuchAttributeName`] if the attribute does not exist
+    /// - return [`TryFromDatumError::IncompatibleTypes`] if the Rust type of the `value` is not
+    /// compatible with the attribute's Postgres type
+    pub fn get_by_name<'tup, T>(&'tup self, attname: &str) -> Result<Option<T>, TryFromDatumError>
+    where
+        T: FromDatum + IntoDatum + UnboxDatum<As<'tup> = T> + 'tup,
+    {
+        self.get(self.attno_of(attname)?)
+    }
+
+    /// Change the value of the specified attribute of a virtual slot, by index.
+    ///
+    /// Attribute numbers start at 1, not 0.
+    ///
+    /// ## Errors
+    /// - return [`TryFromDatumError::NoSuchAttributeNumber`] if the attribute does not exist
+    /// - return [`TryFromDatumError::IncompatibleTypes`] if the Rust type of the `value` is not
+    /// compatible with the attribute's Postgres type
+    ///
+    /// ## Panics
+    ///
+    /// If this isn't a virtual slot, as other kinds of slot hold their values in a formed tuple.
+    pub fn set<T: IntoDatum>(
+        &mut self,
+        attno: NonZeroUsize,
+        value: T,
+    ) -> Result<(), TryFromDatumError> {
+        assert!(self.is_virtual(), "only a virtual slot's values can be set");
+        let tupdesc = self.tupdesc();
+        match tupdesc.get(attno.get() - 1) {
+            Some(att) if !self.is_empty() => check_attribute_type(att, &value)?,
+            _ => return Err(TryFromDatumError::NoSuchAttributeNumber(attno)),
+        }
+
+        unsafe {
+            // SAFETY: a stored virtual slot has all its attributes valid, and we checked `attno`
+            // is in bounds.  The slot doesn't own what the datum points to, so it must outlive
+            // the slot, or at least the next call to `materialize`
+            let slot = self.as_ptr();
+            let datum = value.into_datum();
+            let i = attno.get() - 1;
+            *(*slot).tts_isnull.add(i) = datum.is_none();
+            *(*slot).tts_values.add(i) = datum.unwrap_or(pg_sys::Datum::from(0));
+        }
+        Ok(())
+    }
+
+    /// Change the value of the specified attribute of a virtual slot, by name.
+    ///
+    /// ## Errors
+    /// - return [`TryFromDatumError::NoSuchAttributeName`] if the attribute does not exist
+    /// - return [`TryFromDatumError::IncompatibleTypes`] if the Rust type of the `value` is not
+    /// compatible with the attribute's Postgres type
+    ///
+    /// ## Panics
+    ///
+    /// If this isn't a virtual slot.
+    pub fn set_by_name<T: IntoDatum>(
+        &mut self,
+        attname: &str,
+        value: T,
+    ) -> Result<(), TryFromDatumError> {
+        let attno = self.attno_of(attname)?;
+        self.set(attno, value)
+    }
+
+    fn attno_of(&self, attname: &str) -> Result<NonZeroUsize, TryFromDatumError> {
+        self.tupdesc()
+            .iter()
+            .position(|att| !att.is_dropped() && att.name() == attname)
+            .map(|i| NonZeroUsize::new(i + 1).unwrap())
+            .ok_or_else(|| TryFromDatumError::NoSuchAttributeName(attname.to_owned()))
+    }
+}
+
+impl Drop for PgTupleSlot<'_> {
+    fn drop(&mut self) {
+        if self.owned {
+            // SAFETY: we made this slot with MakeSingleTupleTableSlot and nothing else owns it
+            unsafe { pg_sys::ExecDropSingleTupleTableSlot(self.as_ptr()) }
+        }
+    }
+}
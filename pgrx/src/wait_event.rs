@@ -0,0 +1,127 @@
+//LICENSE Portions Copyright 2019-2021 ZomboDB, LLC.
+//LICENSE
+//LICENSE Portions Copyright 2021-2023 Technology Concepts & Design, Inc.
+//LICENSE
+//LICENSE Portions Copyright 2023-2023 PgCentral Foundation, Inc. <contact@pgcentral.org>
+//LICENSE
+//LICENSE All rights reserved.
+//LICENSE
+//LICENSE Use of this source code is governed by the MIT license that can be found in the LICENSE file.
+//! Reporting what this backend is waiting for in `pg_stat_activity`
+//!
+//! Postgres' own waits, on latches, locks, and I/O, show up in `pg_stat_activity.wait_event`,
+//! but a backend blocked in an extension's own code, such as a network call, shows nothing.  A
+//! [`WaitEventGuard`] reports a wait event until it's dropped, and a [`CustomWaitEvent`] gives
+//! the extension's waits names of their own.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pgrx::wait_event::CustomWaitEvent;
+//!
+//! static REMOTE_FETCH: CustomWaitEvent = CustomWaitEvent::new(c"RemoteFetch");
+//!
+//! fn fetch() -> Vec<u8> {
+//!     let _waiting = REMOTE_FETCH.report();
+//!     // while this blocks, pg_stat_activity shows `Extension` / `RemoteFetch`
+//!     Vec::new()
+//! }
+//! ```
+#![deny(unsafe_op_in_unsafe_fn)]
+use crate::pg_sys;
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// A wait event of type `Extension` with a name of its own
+///
+/// Postgres 17 and later show the name in `pg_stat_activity.wait_event`.  Earlier versions have
+/// no custom wait events, so show `Extension` instead.  The event is registered the first time
+/// it's used in each backend, and every backend registering the same name gets the same event.
+pub struct CustomWaitEvent {
+    name: &'static CStr,
+    info: AtomicU32,
+}
+
+impl CustomWaitEvent {
+    /// Declare a wait event called `name`, as a `static`
+    pub const fn new(name: &'static CStr) -> Self {
+        CustomWaitEvent { name, info: AtomicU32::new(0) }
+    }
+
+    pub fn name(&self) -> &'static CStr {
+        self.name
+    }
+
+    /// The `wait_event_info` to give Postgres functions that wait, such as
+    /// [`crate::latch::WaitEventSetBuilder::wait_event`], so they report this event
+    pub fn info(&self) -> u32 {
+        match self.info.load(Ordering::Relaxed) {
+            0 => {
+                #[cfg(feature = "pg17")]
+                // SAFETY: the name is a valid C string, and Postgres raises an ERROR if it can't
+                // register another event
+                let info = unsafe { pg_sys::WaitEventExtensionNew(self.name.as_ptr()) };
+                #[cfg(not(feature = "pg17"))]
+                let info = pg_sys::PG_WAIT_EXTENSION;
+                self.info.store(info, Ordering::Relaxed);
+                info
+            }
+            info => info,
+        }
+    }
+
+    /// Report this event until the returned guard is dropped
+    pub fn report(&self) -> WaitEventGuard {
+        WaitEventGuard::start(self.info())
+    }
+}
+
+/// Reports a wait event in `pg_stat_activity` until dropped, when whatever was reported before
+/// is reported again
+#[must_use = "the wait event is only reported until the guard is dropped"]
+pub struct WaitEventGuard {
+    previous: u32,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl WaitEventGuard {
+    /// Report `wait_event_info`, which can be any of Postgres' `PG_WAIT_*` classes combined
+    /// with an event, or a [`CustomWaitEvent::info`]
+    pub fn start(wait_event_info: u32) -> Self {
+        let previous = report_wait(wait_event_info);
+        WaitEventGuard { previous, _not_send: PhantomData }
+    }
+}
+
+impl Drop for WaitEventGuard {
+    fn drop(&mut self) {
+        report_wait(self.previous);
+    }
+}
+
+/// What `pgstat_report_wait_start()`, a `static inline` function, does, returning what was
+/// reported before
+fn report_wait(wait_event_info: u32) -> u32 {
+    // SAFETY: `my_wait_event_info` always points somewhere valid, to our `PGPROC` once we have
+    // one, and volatile accesses are what Postgres uses because other backends read it
+    #[cfg(not(any(feature = "pg12", feature = "pg13")))]
+    unsafe {
+        let previous = pg_sys::my_wait_event_info.read_volatile();
+        pg_sys::my_wait_event_info.write_volatile(wait_event_info);
+        previous
+    }
+
+    // SAFETY: as above, but before Postgres 14 it had to be our `PGPROC`, if we have one
+    #[cfg(any(feature = "pg12", feature = "pg13"))]
+    unsafe {
+        let proc = pg_sys::MyProc;
+        if !pg_sys::pgstat_track_activities || proc.is_null() {
+            return 0;
+        }
+        let info = std::ptr::addr_of_mut!((*proc).wait_event_info);
+        let previous = info.read_volatile();
+        info.write_volatile(wait_event_info);
+        previous
+    }
+}